@@ -194,11 +194,7 @@ pub async fn update_balance(
         });
         let pending_utxos: Vec<PendingUtxo> = utxos
             .iter()
-            .map(|u| PendingUtxo {
-                outpoint: u.outpoint.clone(),
-                value: u.value,
-                confirmations: tip_height - u.height + 1,
-            })
+            .map(|u| to_pending_utxo(u, tip_height))
             .collect();
 
         let current_confirmations = pending_utxos.iter().map(|u| u.confirmations).max();
@@ -283,6 +279,16 @@ pub async fn update_balance(
     Ok(utxo_statuses)
 }
 
+/// Returns the pending UTXO with its confirmations relative to `tip_height`.
+fn to_pending_utxo(utxo: &Utxo, tip_height: u32) -> PendingUtxo {
+    PendingUtxo {
+        outpoint: utxo.outpoint.clone(),
+        value: utxo.value,
+        // A reorg can report UTXOs above the tip height.
+        confirmations: tip_height.saturating_sub(utxo.height).saturating_add(1),
+    }
+}
+
 async fn kyt_check_utxo(
     caller: Principal,
     utxo: &Utxo,
@@ -367,3 +373,24 @@ pub(crate) async fn mint(amount: u64, to: Account, memo: Memo) -> Result<u64, Up
         })??;
     Ok(block_index.0.to_u64().expect("nat does not fit into u64"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_utxo_above_the_tip_should_not_underflow() {
+        let utxo = |height| Utxo {
+            outpoint: OutPoint {
+                txid: [1; 32].into(),
+                vout: 0,
+            },
+            value: 10_000,
+            height,
+        };
+        assert_eq!(to_pending_utxo(&utxo(100), 105).confirmations, 6);
+        assert_eq!(to_pending_utxo(&utxo(100), 100).confirmations, 1);
+        assert_eq!(to_pending_utxo(&utxo(101), 100).confirmations, 1);
+        assert_eq!(to_pending_utxo(&utxo(0), u32::MAX).confirmations, u32::MAX);
+    }
+}
@@ -1,5 +1,6 @@
 // @review (alpha)
 use crate::address;
+use crate::metrics::{get_metrics, MinterMetrics};
 use crate::state;
 use crate::tx::DisplayAmount;
 use ic_btc_interface::{Network, Txid};
@@ -593,7 +594,7 @@ pub fn build_unconfirmed_change() -> String {
 pub fn build_update_balance_principals() -> String {
     with_utf8_buffer(|buf| {
         state::read_state(|s| {
-            for p in &s.update_balance_accounts {
+            for p in s.update_balance_accounts.keys() {
                 writeln!(buf, "<li>{}</li>", p).unwrap();
             }
         })
@@ -618,13 +619,52 @@ fn get_total_btc_managed() -> u64 {
 pub fn build_retrieve_btc_principals() -> String {
     with_utf8_buffer(|buf| {
         state::read_state(|s| {
-            for p in &s.retrieve_btc_accounts {
+            for p in s.retrieve_btc_accounts.keys() {
                 writeln!(buf, "<li>{}</li>", p).unwrap();
             }
         })
     })
 }
 
+/// The operational status of the minter, served as JSON on the `/status` path.
+#[derive(Debug, serde::Serialize)]
+pub struct MinterStatus {
+    pub btc_network: String,
+    pub min_confirmations: u32,
+    pub mode: String,
+    /// The last exchange rate used to mint SUSD, if any.
+    pub last_exchange_rate: Option<u64>,
+    /// The time (in nanoseconds since the epoch) of the last exchange rate.
+    pub last_exchange_rate_timestamp: Option<u64>,
+    pub provider_count: usize,
+    pub metrics: MinterMetrics,
+}
+
+pub fn get_status() -> MinterStatus {
+    let (btc_network, min_confirmations, mode, last_exchange_rate) = state::read_state(|s| {
+        (
+            s.btc_network.to_string(),
+            s.min_confirmations,
+            format!("{:?}", s.mode),
+            s.last_exchange_rate,
+        )
+    });
+    MinterStatus {
+        btc_network,
+        min_confirmations,
+        mode,
+        last_exchange_rate: last_exchange_rate.map(|(rate, _)| rate),
+        last_exchange_rate_timestamp: last_exchange_rate.map(|(_, timestamp)| timestamp),
+        provider_count: crate::https::provider::get_providers().len(),
+        metrics: get_metrics(),
+    }
+}
+
+/// Returns the status of the minter as JSON. It reads the state only and makes no outcalls.
+pub fn build_status() -> Vec<u8> {
+    serde_json::to_vec(&get_status()).expect("failed to encode the minter status")
+}
+
 fn txid_link(txid: &Txid) -> String {
     txid_link_on(txid, state::read_state(|s| s.btc_network))
 }
@@ -659,3 +699,19 @@ fn test_txid_link() {
         "<a target='_blank' href='https://blockstream.info/testnet/tx/0627052b6f28912f2703066a912ea577f2ce4da4caa5a5fbd8a57286c345c2f2'><code>0627052b6f28912f2703066a912ea577f2ce4da4caa5a5fbd8a57286c345c2f2</code></a>"
     );
 }
+
+#[test]
+fn test_build_status() {
+    let mut minter_state = state::MinterState::from(crate::lifecycle::init::test_init_args());
+    minter_state.last_exchange_rate = Some((60_000, 1_700_000_000_000_000_000));
+    state::replace_state(minter_state);
+
+    let status: serde_json::Value =
+        serde_json::from_slice(&build_status()).expect("the status should be valid JSON");
+    assert_eq!(status["btc_network"], "regtest");
+    assert_eq!(status["mode"], "GeneralAvailability");
+    assert_eq!(status["last_exchange_rate"], 60_000);
+    assert_eq!(status["last_exchange_rate_timestamp"], 1_700_000_000_000_000_000u64);
+    assert_eq!(status["provider_count"], 0);
+    assert_eq!(status["metrics"]["in_flight_update_balance"], 0);
+}
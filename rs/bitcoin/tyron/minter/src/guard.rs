@@ -1,10 +1,17 @@
+use crate::logs::P0;
 use crate::state::{mutate_state, MinterState};
+use crate::updates::get_btc_address::SyronOperation;
 //use candid::Principal;
-use std::collections::BTreeSet;
+use ic_canister_log::log;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
 use std::marker::PhantomData;
 use icrc_ledger_types::icrc1::account::Account;
 
-const MAX_CONCURRENT: usize = 100;
+/// The upper bound of [MinterState::max_concurrent_requests]. Every request makes
+/// several inter-canister calls, so more concurrent requests would fill the canister's
+/// output queues (500 messages per destination).
+pub const MAX_CONCURRENT_REQUESTS_LIMIT: u64 = 500;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum GuardError {
@@ -13,48 +20,90 @@ pub enum GuardError {
 }
 
 pub trait PendingRequests {
-    fn pending_requests(state: &mut MinterState) -> &mut BTreeSet<Account>;
+    /// What a guard locks, e.g. an account.
+    type Key: Copy + Ord + Debug;
+
+    /// The keys holding a guard and the time (in nanoseconds) they acquired it.
+    fn pending_requests(state: &mut MinterState) -> &mut BTreeMap<Self::Key, u64>;
 }
 
 pub struct PendingBalanceUpdates;
 
 impl PendingRequests for PendingBalanceUpdates {
-    fn pending_requests(state: &mut MinterState) -> &mut BTreeSet<Account> {
+    type Key = Account;
+
+    fn pending_requests(state: &mut MinterState) -> &mut BTreeMap<Account, u64> {
         &mut state.update_balance_accounts
     }
 }
 pub struct RetrieveBtcUpdates;
 
 impl PendingRequests for RetrieveBtcUpdates {
-    fn pending_requests(state: &mut MinterState) -> &mut BTreeSet<Account> {
+    type Key = Account;
+
+    fn pending_requests(state: &mut MinterState) -> &mut BTreeMap<Account, u64> {
         &mut state.retrieve_btc_accounts
     }
 }
 
+pub struct PendingOperations;
+
+impl PendingRequests for PendingOperations {
+    type Key = (Account, SyronOperation);
+
+    fn pending_requests(state: &mut MinterState) -> &mut BTreeMap<(Account, SyronOperation), u64> {
+        &mut state.update_balance_operations
+    }
+}
+
 /// Guards a block from executing twice when called by the same user and from being
-/// executed [MAX_CONCURRENT] or more times in parallel.
+/// executed [MinterState::max_concurrent_requests] or more times in parallel.
+///
+/// A guard is not released if its call traps after an await, so guards older than the
+/// configured [MinterState::guard_timeout] are considered stale and reclaimed.
 #[must_use]
 pub struct Guard<PR: PendingRequests> {
-    account: Account,
+    key: PR::Key,
+    acquired_at: u64,
     _marker: PhantomData<PR>,
 }
 
 impl<PR: PendingRequests> Guard<PR> {
     /// Attempts to create a new guard for the current block. Fails if there is
-    /// already a pending request for the specified [principal] or if there
-    /// are at least [MAX_CONCURRENT] pending requests.
-    pub fn new(account: Account) -> Result<Self, GuardError> {
+    /// already a pending request for the specified [key] or if there
+    /// are at least [MinterState::max_concurrent_requests] pending requests.
+    pub fn new(key: PR::Key) -> Result<Self, GuardError> {
+        Self::new_at(key, now())
+    }
+
+    fn new_at(key: PR::Key, now: u64) -> Result<Self, GuardError> {
         mutate_state(|s| {
+            let guard_timeout = s.guard_timeout;
+            let max_concurrent_requests = s.max_concurrent_requests as usize;
             let accounts = PR::pending_requests(s);
-            if accounts.contains(&account) {
+            accounts.retain(|stale_key, &mut acquired_at| {
+                let age = now.saturating_sub(acquired_at);
+                if age < guard_timeout {
+                    return true;
+                }
+                log!(
+                    P0,
+                    "[guard]: reclaiming the guard of {:?} held for {} ns",
+                    stale_key,
+                    age
+                );
+                false
+            });
+            if accounts.contains_key(&key) {
                 return Err(GuardError::AlreadyProcessing);
             }
-            if accounts.len() >= MAX_CONCURRENT {
+            if accounts.len() >= max_concurrent_requests {
                 return Err(GuardError::TooManyConcurrentRequests);
             }
-            accounts.insert(account);
+            accounts.insert(key, now);
             Ok(Self {
-                account,
+                key,
+                acquired_at: now,
                 _marker: PhantomData,
             })
         })
@@ -63,7 +112,27 @@ impl<PR: PendingRequests> Guard<PR> {
 
 impl<PR: PendingRequests> Drop for Guard<PR> {
     fn drop(&mut self) {
-        mutate_state(|s| PR::pending_requests(s).remove(&self.account));
+        mutate_state(|s| {
+            let accounts = PR::pending_requests(s);
+            // @dev a reclaimed guard must not release the guard that replaced it
+            if accounts.get(&self.key) == Some(&self.acquired_at) {
+                accounts.remove(&self.key);
+            }
+        });
+    }
+}
+
+fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::time()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("the system time is before the UNIX epoch")
+            .as_nanos() as u64
     }
 }
 
@@ -90,6 +159,36 @@ impl Drop for TimerLogicGuard {
     }
 }
 
+/// Reserves SUSD against the debt ceiling while a mint is in flight, so that concurrent
+/// mints cannot exceed the ceiling together. Dropping it releases the reservation.
+#[must_use]
+pub struct DebtReservation(u64);
+
+impl DebtReservation {
+    /// Reserves `susd` if the total and reserved debt stay within the debt ceiling, or
+    /// returns the total and reserved debt otherwise.
+    pub fn new(susd: u64) -> Result<Self, u64> {
+        mutate_state(|s| {
+            let debt = s.total_debt.saturating_add(s.reserved_debt);
+            match debt.checked_add(susd) {
+                Some(new_debt) if new_debt <= s.debt_ceiling_susd => {
+                    s.reserved_debt += susd;
+                    Ok(DebtReservation(susd))
+                }
+                _ => Err(debt),
+            }
+        })
+    }
+}
+
+impl Drop for DebtReservation {
+    fn drop(&mut self) {
+        mutate_state(|s| {
+            s.reserved_debt = s.reserved_debt.saturating_sub(self.0);
+        });
+    }
+}
+
 #[must_use]
 pub struct DistributeKytFeeGuard(());
 
@@ -113,10 +212,51 @@ impl Drop for DistributeKytFeeGuard {
     }
 }
 
+/// Returns the accounts holding a balance update or operation guard and how long
+/// (in nanoseconds) they have held it.
+pub fn in_flight(state: &MinterState, now: u64) -> Vec<(Account, u64)> {
+    state
+        .update_balance_accounts
+        .iter()
+        .chain(
+            state
+                .update_balance_operations
+                .iter()
+                .map(|((account, _), acquired_at)| (account, acquired_at)),
+        )
+        .map(|(account, acquired_at)| (*account, now.saturating_sub(*acquired_at)))
+        .collect()
+}
+
 pub fn balance_update_guard(p: Account) -> Result<Guard<PendingBalanceUpdates>, GuardError> {
     Guard::new(p)
 }
 
+/// Guards an [update_ssi_balance] operation on the given account.
+///
+/// The operations that change the collateral or the loan (deposits, with or without a loan,
+/// redemptions and liquidations) share the [loan_guard], since a mint computes the SUSD from
+/// the collateral that a concurrent redemption would burn. The other operations (e.g. a runes
+/// deposit) run concurrently. The runes minter account keeps its own [balance_update_guard].
+pub fn operation_guard(
+    account: Account,
+    op: SyronOperation,
+) -> Result<Guard<PendingOperations>, GuardError> {
+    let op = match op {
+        SyronOperation::GetSyron | SyronOperation::AddCollateral | SyronOperation::Liquidation => {
+            SyronOperation::RedeemBitcoin
+        }
+        op => op,
+    };
+    Guard::new((account, op))
+}
+
+/// Guards the repayment of the loan of the given account. A repayment, a loan closure and
+/// every [operation_guard] that changes the collateral or the loan share one guard.
+pub fn loan_guard(account: Account) -> Result<Guard<PendingOperations>, GuardError> {
+    operation_guard(account, SyronOperation::RedeemBitcoin)
+}
+
 pub fn retrieve_btc_guard(p: Account) -> Result<Guard<RetrieveBtcUpdates>, GuardError> {
     Guard::new(p)
 }
@@ -124,14 +264,19 @@ pub fn retrieve_btc_guard(p: Account) -> Result<Guard<RetrieveBtcUpdates>, Guard
 #[cfg(test)]
 mod tests {
     use crate::{
-        guard::{GuardError, MAX_CONCURRENT},
-        lifecycle::init::{init, BtcNetwork, InitArgs},
+        guard::GuardError,
+        lifecycle::init::{init, test_init_args, InitArgs},
         state::read_state,
     };
     use candid::Principal;
     use ic_base_types::CanisterId;
+    use icrc_ledger_types::icrc1::account::Account;
 
-    use super::{balance_update_guard, TimerLogicGuard};
+    use super::{
+        balance_update_guard, in_flight, loan_guard, operation_guard, Guard, PendingBalanceUpdates,
+        TimerLogicGuard,
+    };
+    use crate::updates::get_btc_address::SyronOperation;
 
     fn test_principal(id: u64) -> Principal {
         Principal::try_from_slice(&id.to_le_bytes()).unwrap()
@@ -139,18 +284,8 @@ mod tests {
 
     fn test_state_args() -> InitArgs {
         InitArgs {
-            btc_network: BtcNetwork::Regtest,
-            ecdsa_key_name: "some_key".to_string(),
-            retrieve_btc_min_amount: 2000,
-            ledger_id: CanisterId::from_u64(42),
-            susd_id: CanisterId::from_u64(42),
-            xrc_id: CanisterId::from_u64(42),
-            siwb_id: CanisterId::from_u64(42),
-            max_time_in_queue_nanos: 0,
-            min_confirmations: None,
-            mode: crate::state::Mode::GeneralAvailability,
             kyt_principal: Some(CanisterId::from(0)),
-            kyt_fee: None,
+            ..test_init_args()
         }
     }
 
@@ -172,23 +307,150 @@ mod tests {
     #[test]
     #[allow(clippy::needless_collect)]
     fn guard_prevents_more_than_max_concurrent_principals() {
-        // test that at most MAX_CONCURRENT guards can be created if each one
+        // test that at most max_concurrent_requests guards can be created if each one
         // is for a different principal
 
         init(test_state_args());
-        let guards: Vec<_> = (0..MAX_CONCURRENT)
+        let max_concurrent = read_state(|s| s.max_concurrent_requests) as usize;
+        let guards: Vec<_> = (0..max_concurrent)
             .map(|id| {
                 balance_update_guard(test_principal(id as u64)).unwrap_or_else(|e| {
                     panic!("Could not create guard for principal num {}: {:#?}", id, e)
                 })
             })
             .collect();
-        assert_eq!(guards.len(), MAX_CONCURRENT);
-        let pid = test_principal(MAX_CONCURRENT as u64 + 1);
+        assert_eq!(guards.len(), max_concurrent);
+        let pid = test_principal(max_concurrent as u64 + 1);
         let res = balance_update_guard(pid).err();
         assert_eq!(res, Some(GuardError::TooManyConcurrentRequests));
     }
 
+    #[test]
+    fn stale_guard_should_be_reclaimed_after_the_timeout() {
+        init(test_state_args());
+        let account = Account {
+            owner: test_principal(0),
+            subaccount: None,
+        };
+        let timeout = read_state(|s| s.guard_timeout);
+
+        // A call that traps after an await never releases its guard.
+        std::mem::forget(Guard::<PendingBalanceUpdates>::new_at(account, 1_000).unwrap());
+        assert_eq!(
+            Guard::<PendingBalanceUpdates>::new_at(account, 1_000 + timeout - 1).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+
+        let guard = Guard::<PendingBalanceUpdates>::new_at(account, 1_000 + timeout).unwrap();
+        assert_eq!(
+            read_state(|s| s.update_balance_accounts.get(&account).copied()),
+            Some(1_000 + timeout)
+        );
+        drop(guard);
+        assert!(read_state(|s| s.update_balance_accounts.is_empty()));
+    }
+
+    #[test]
+    fn reclaimed_guard_should_not_release_its_replacement() {
+        init(test_state_args());
+        let account = Account {
+            owner: test_principal(0),
+            subaccount: None,
+        };
+        let timeout = read_state(|s| s.guard_timeout);
+
+        let stale = Guard::<PendingBalanceUpdates>::new_at(account, 0).unwrap();
+        let fresh = Guard::<PendingBalanceUpdates>::new_at(account, timeout).unwrap();
+        drop(stale);
+        assert!(read_state(|s| s.update_balance_accounts.contains_key(&account)));
+        drop(fresh);
+        assert!(read_state(|s| s.update_balance_accounts.is_empty()));
+    }
+
+    #[test]
+    fn in_flight_should_report_the_age_of_each_guard() {
+        init(test_state_args());
+        let account = |id| Account {
+            owner: test_principal(id),
+            subaccount: None,
+        };
+        let _first = Guard::<PendingBalanceUpdates>::new_at(account(0), 1_000).unwrap();
+        let _second = Guard::<PendingBalanceUpdates>::new_at(account(1), 1_500).unwrap();
+
+        assert_eq!(
+            read_state(|s| in_flight(s, 2_000)),
+            vec![(account(0), 1_000), (account(1), 500)]
+        );
+    }
+
+    #[test]
+    fn operation_guards_should_exclude_the_operations_on_the_loan() {
+        init(test_state_args());
+        let account = Account {
+            owner: test_principal(0),
+            subaccount: None,
+        };
+
+        let _deposit = operation_guard(account, SyronOperation::GetSyron).unwrap();
+        let _runes_deposit = operation_guard(account, SyronOperation::DepositSyron).unwrap();
+        assert_eq!(
+            operation_guard(account, SyronOperation::DepositSyron).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+        assert_eq!(
+            operation_guard(account, SyronOperation::GetSyron).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+        assert_eq!(
+            operation_guard(account, SyronOperation::AddCollateral).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+        assert_eq!(
+            operation_guard(account, SyronOperation::RedeemBitcoin).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+    }
+
+    #[test]
+    fn loan_guard_should_exclude_deposits() {
+        init(test_state_args());
+        let account = Account {
+            owner: test_principal(0),
+            subaccount: None,
+        };
+
+        let repayment = loan_guard(account).unwrap();
+        for op in [SyronOperation::GetSyron, SyronOperation::AddCollateral] {
+            assert_eq!(operation_guard(account, op).err(), Some(GuardError::AlreadyProcessing));
+        }
+        drop(repayment);
+        let deposit = operation_guard(account, SyronOperation::GetSyron).unwrap();
+        assert_eq!(loan_guard(account).err(), Some(GuardError::AlreadyProcessing));
+        drop(deposit);
+    }
+
+    #[test]
+    fn loan_guard_should_exclude_redemptions() {
+        init(test_state_args());
+        let account = Account {
+            owner: test_principal(0),
+            subaccount: None,
+        };
+
+        let repayment = loan_guard(account).unwrap();
+        assert_eq!(
+            operation_guard(account, SyronOperation::RedeemBitcoin).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+        assert_eq!(
+            operation_guard(account, SyronOperation::Liquidation).err(),
+            Some(GuardError::AlreadyProcessing)
+        );
+        assert_eq!(read_state(|s| in_flight(s, 0)).len(), 1);
+        drop(repayment);
+        assert!(operation_guard(account, SyronOperation::RedeemBitcoin).is_ok());
+    }
+
     #[test]
     fn guard_timer_guard() {
         init(test_state_args());
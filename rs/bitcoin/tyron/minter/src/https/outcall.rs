@@ -1,45 +1,71 @@
 use crate::updates::UpdateBalanceError;
 use ic_btc_interface::Utxo;
 use num_traits::ToPrimitive;
+use ic_cdk::api::call::RejectionCode;
 use ic_cdk::api::management_canister::http_request::{
     HttpHeader, HttpMethod, TransformContext, CanisterHttpRequestArgument, HttpResponse
 };
-use super:: types::{ServiceProvider, ResolvedServiceProvider, ServiceError, ServiceResult, HttpOutcallError};
-use super::provider::resolve_service_provider;
+use super:: types::{JsonRpcError, Provider, ServiceProvider, ResolvedServiceProvider, ServiceError, ServiceResult, HttpOutcallError};
+use super::provider::{add_cycles_owed, get_chain_provider_ids, record_provider_outcome, resolve_service_provider};
 use serde_json::Value;
 
-/// Extract Runes amount from parsed JSON with comprehensive validation
-fn extract_runes_amount_from_json(outcall_json: Value) -> Result<u64, UpdateBalanceError> {
-    // @dev get runes amount with proper error handling
-    let amount_str = match outcall_json["amount"].as_str() {
-        Some(amount) => amount,
+/// Extract a base-unit integer (e.g. satoshis) given as a string at the path of the parsed JSON
+pub fn extract_u64_field(json: &Value, path: &[&str]) -> Result<u64, UpdateBalanceError> {
+    let field = path.join(".");
+    let error = |reason: String| UpdateBalanceError::CallError {
+        method: "extract_u64_field".to_string(),
+        reason,
+    };
+
+    // @dev walk the path with proper error handling
+    let value = match path.iter().try_fold(json, |value, key| value.get(key)).and_then(Value::as_str) {
+        Some(value) => value,
         None => {
-            ic_cdk::println!("Missing 'amount' field in outcall response: {:?}", outcall_json);
-            return Err(UpdateBalanceError::CallError {
-                method: "extract_runes_amount_from_json".to_string(),
-                reason: "Missing 'amount' field in JSON response".to_string(),
-            });
+            ic_cdk::println!("Missing '{}' field in outcall response", field);
+            return Err(error(format!("Missing '{}' field in JSON response", field)));
         }
     };
-    
-    // Check if the amount string contains commas or dots, which would indicate it's not in satoshis
-    if amount_str.contains(',') || amount_str.contains('.') {
-        return Err(UpdateBalanceError::CallError {
-            method: "extract_runes_amount_from_json".to_string(),
-            reason: format!("Amount '{}' contains commas or dots, indicating it's not in satoshis format", amount_str),
-        });
+
+    // Check if the value contains commas or dots, which would indicate it's not in base units
+    if value.contains(',') || value.contains('.') {
+        return Err(error(format!(
+            "Field '{}' value '{}' contains commas or dots, indicating it's not in base units",
+            field, value
+        )));
     }
 
-    let amount_u64: u64 = amount_str.parse().unwrap_or(0);
+    value.parse::<u64>().map_err(|e| {
+        error(format!("Field '{}' value '{}' is not a base-unit integer: {}", field, value, e))
+    })
+}
 
-    Ok(amount_u64)
+/// The number of times an indexer outcall is retried with a doubled response size limit,
+/// which bounds its cost to 2^N times the cost of the first attempt
+const MAX_RESPONSE_SIZE_RETRIES: u32 = 3;
+
+/// The maximum response size of an HTTPS outcall
+const MAX_HTTP_RESPONSE_BYTES: u64 = 2_000_000;
+
+// @dev the IC rejects responses larger than max_response_bytes with a SysFatal error
+fn is_response_too_large(err: &ServiceError) -> bool {
+    match err {
+        ServiceError::HttpOutcallError(HttpOutcallError::IcError { code, message }) => {
+            *code == RejectionCode::SysFatal && message.contains("size limit")
+        }
+        _ => false,
+    }
 }
 
 /// Get Runes balance for a specific UTXO with comprehensive error handling
+///
+/// If the response exceeds `max_response_bytes`, the outcall is retried with a doubled limit
+/// (and cycles) up to [MAX_RESPONSE_SIZE_RETRIES] times.
 pub async fn call_indexer_runes_balance(
     utxo: Utxo,
     cycles_cost: u128,
-    provider: u64,
+    service: ServiceProvider,
+    max_response_bytes: u64,
+    retry_policy: RetryPolicy,
 ) -> Result<u64, UpdateBalanceError> {
     // @dev convert utxo outpoint to bitcoin transaction id and vout/index
     let txid_bytes = utxo.outpoint.txid.as_ref().iter().rev().map(|n| *n as u8).collect::<Vec<u8>>();
@@ -49,21 +75,34 @@ pub async fn call_indexer_runes_balance(
     // @dev build api endpoint url
     let endpoint = format!("get-unisat-runes-balance?txid={}&index={}", txid, index);
 
-    // @dev execute https outcall @review (alpha) max_response_bytes, add var to state?
-    let outcall = match web3_request(ServiceProvider::Provider(provider), &endpoint, "", 2048, cycles_cost).await {
+    // @dev execute https outcall
+    let mut response_bytes = max_response_bytes;
+    let mut retries = 0;
+    let outcall = loop {
+        let cost = cycles_cost * (response_bytes / max_response_bytes.max(1)) as u128;
+        match web3_request(service.clone(), &endpoint, "", response_bytes, cost, Some(retry_policy)).await {
+            Err(err) if is_response_too_large(&err) && retries < MAX_RESPONSE_SIZE_RETRIES && response_bytes < MAX_HTTP_RESPONSE_BYTES => {
+                retries += 1;
+                response_bytes = (response_bytes * 2).min(MAX_HTTP_RESPONSE_BYTES);
+                ic_cdk::println!("Runes balance response too large, retrying with max_response_bytes = {}", response_bytes);
+            }
+            result => break result,
+        }
+    };
+    let outcall = match outcall {
         Ok(result) => result,
         Err(err) => {
             return Err(UpdateBalanceError::CallError {
                 method: "call_indexer_runes_balance".to_string(),
-                reason: format!("HTTPS Outcall failed with error: {:?}", err),
+                reason: format!("HTTPS Outcall failed with error: {:?}", omit_response_body(err)),
             });
         }
     };
 
-    // @dev validate response is not HTML error page
+    // @dev the response body is never logged nor returned, since the indexer may echo the credentials
+    // @dev validate response is not HTML error page, in case the provider did not report the content type
     if outcall.trim_start().starts_with("<!DOCTYPE html>") {
-        ic_cdk::println!("Received HTML error page for UTXO {}: {}", 
-            format!("{}:{}", txid, index), outcall);
+        ic_cdk::println!("Received HTML error page ({} bytes) for UTXO {}:{}", outcall.len(), txid, index);
         return Err(UpdateBalanceError::CallError {
             method: "call_indexer_runes_balance".to_string(),
             reason: "Received HTML error page instead of JSON".to_string(),
@@ -73,34 +112,256 @@ pub async fn call_indexer_runes_balance(
     let outcall_json: Value = match serde_json::from_str(&outcall) {
         Ok(json) => json,
         Err(e) => {
-            ic_cdk::println!("Failed to parse runes balance response with error: {:?}, for outcall response: {:?}", e, outcall);
+            ic_cdk::println!("Failed to parse runes balance response ({} bytes) with error: {:?}", outcall.len(), e);
             return Err(UpdateBalanceError::CallError {
                 method: "check_runes_minter_utxos".to_string(),
-                reason: format!("Failed to parse runes balance response: {:?}, response: {:?}", e, outcall),
+                reason: format!("Failed to parse runes balance response: {:?}", e),
             })
         }
     };
 
-    ic_cdk::println!("runes balance outcall ({:?}) for utxo ({:?})", outcall_json, utxo);
-    extract_runes_amount_from_json(outcall_json)
+    let amount = extract_u64_field(&outcall_json, &["amount"])?;
+    ic_cdk::println!("runes balance outcall ({}) for utxo ({}:{})", amount, txid, index);
+    Ok(amount)
+}
+
+/// Get the BTC price in the given fiat currency from a service provider
+pub async fn call_provider_btc_price(
+    quote_currency: &str,
+    cycles_cost: u128,
+    service: ServiceProvider,
+    retry_policy: RetryPolicy,
+) -> Result<u64, UpdateBalanceError> {
+    let endpoint = format!("get-btc-price?currency={}", quote_currency);
+
+    let outcall = match web3_request(service, &endpoint, "", 2048, cycles_cost, Some(retry_policy)).await {
+        Ok(result) => result,
+        Err(err) => {
+            return Err(UpdateBalanceError::CallError {
+                method: "call_provider_btc_price".to_string(),
+                reason: format!("HTTPS Outcall failed with error: {:?}", err),
+            });
+        }
+    };
+
+    let outcall_json: Value = serde_json::from_str(&outcall).map_err(|e| UpdateBalanceError::CallError {
+        method: "call_provider_btc_price".to_string(),
+        reason: format!("Failed to parse BTC price response: {:?}, response: {:?}", e, outcall),
+    })?;
+
+    // @dev the price is expected as a string of whole units of the quote currency
+    extract_u64_field(&outcall_json, &["price"])
+}
+
+/// Returns the runes amount reported by at least `threshold` providers, or None if no amount
+/// reaches the threshold or the providers disagree on more than one amount reaching it
+fn runes_amount_consensus(amounts: &[u64], threshold: usize) -> Option<u64> {
+    let mut votes: std::collections::BTreeMap<u64, usize> = std::collections::BTreeMap::new();
+    for amount in amounts {
+        *votes.entry(*amount).or_default() += 1;
+    }
+    let mut agreed = votes.into_iter().filter(|(_, count)| *count >= threshold);
+    match (agreed.next(), agreed.next()) {
+        (Some((amount, _)), None) => Some(amount),
+        _ => None,
+    }
+}
+
+/// Get Runes balance for a specific UTXO from several providers, requiring at least `threshold` of them to agree
+pub async fn call_indexer_runes_balance_consensus(
+    utxo: Utxo,
+    cycles_cost: u128,
+    providers: Vec<u64>,
+    threshold: usize,
+    max_response_bytes: u64,
+) -> Result<u64, UpdateBalanceError> {
+    let results = futures::future::join_all(
+        providers
+            .iter()
+            .map(|&provider| {
+                call_indexer_runes_balance(
+                    utxo.clone(),
+                    cycles_cost,
+                    ServiceProvider::Provider(provider),
+                    max_response_bytes,
+                    PROVIDER_RETRY_POLICY,
+                )
+            }),
+    )
+    .await;
+
+    let mut amounts = Vec::with_capacity(results.len());
+    for (provider, result) in providers.iter().zip(results) {
+        match result {
+            Ok(amount) => amounts.push(amount),
+            Err(err) => ic_cdk::println!("Runes balance outcall to provider {} failed: {:?}", provider, err),
+        }
+    }
+
+    runes_amount_consensus(&amounts, threshold.max(1)).ok_or_else(|| UpdateBalanceError::CallError {
+        method: "call_indexer_runes_balance_consensus".to_string(),
+        reason: format!(
+            "Fewer than {} of {} providers agree on the runes balance: {:?}",
+            threshold,
+            providers.len(),
+            amounts
+        ),
+    })
+}
+
+const REDACTED: &str = "<redacted>";
+
+// @dev the credential header values and the credential path are all redacted, however short,
+// since a secret left in an error is leaked; only empty values are skipped
+fn credential_secrets(provider: &Provider) -> Vec<String> {
+    provider
+        .credential_headers
+        .iter()
+        .map(|header| header.value.clone())
+        .chain(std::iter::once(provider.credential_path.clone()))
+        .filter(|secret| !secret.is_empty())
+        .collect()
+}
+
+// @dev replaces the body of an invalid response with its length, so that it is neither logged nor returned
+fn omit_response_body(err: ServiceError) -> ServiceError {
+    match err {
+        ServiceError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse { status, body, parsing_error }) => {
+            HttpOutcallError::InvalidHttpJsonRpcResponse {
+                status,
+                body: format!("<{} bytes omitted>", body.len()),
+                parsing_error,
+            }
+            .into()
+        }
+        err => err,
+    }
+}
+
+fn redact(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .fold(text.to_string(), |text, secret| text.replace(secret.as_str(), REDACTED))
+}
+
+// @dev the messages of the errors may echo the request, so the secrets are removed before they reach callers or logs
+fn redact_service_error(err: ServiceError, secrets: &[String]) -> ServiceError {
+    match err {
+        ServiceError::HttpOutcallError(HttpOutcallError::IcError { code, message }) => {
+            HttpOutcallError::IcError { code, message: redact(&message, secrets) }.into()
+        }
+        ServiceError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse { status, body, parsing_error }) => {
+            HttpOutcallError::InvalidHttpJsonRpcResponse {
+                status,
+                body: redact(&body, secrets),
+                parsing_error: parsing_error.map(|e| redact(&e, secrets)),
+            }
+            .into()
+        }
+        ServiceError::JsonRpcError(JsonRpcError { code, message }) => {
+            ServiceError::JsonRpcError(JsonRpcError { code, message: redact(&message, secrets) })
+        }
+        err => err,
+    }
+}
+
+/// How [web3_request] retries the requests that fail with a retryable error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one
+    pub attempts: u32,
+    /// Whether to retry against the next provider registered for the same chain
+    pub rotate_providers: bool,
+}
+
+/// Retries a failed request once against the same provider, for the results that must come
+/// from that provider, e.g. the independent sources of a consensus
+pub const PROVIDER_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    attempts: 2,
+    rotate_providers: false,
+};
+
+// @dev transient IC errors, server errors and JSON-RPC errors may succeed on another attempt
+fn is_retryable(err: &ServiceError) -> bool {
+    match err {
+        ServiceError::HttpOutcallError(HttpOutcallError::IcError { code, .. }) => *code == RejectionCode::SysTransient,
+        ServiceError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse { status, .. }) => *status >= 500,
+        ServiceError::JsonRpcError(_) => true,
+        _ => false,
+    }
+}
+
+// @dev the providers to try in order, starting with the resolved one and wrapping around the others of its chain
+fn provider_rotation(first: u64, mut chain_providers: Vec<u64>) -> Vec<u64> {
+    match chain_providers.iter().position(|id| *id == first) {
+        Some(position) => chain_providers.rotate_left(position),
+        None => chain_providers.insert(0, first),
+    }
+    chain_providers
 }
 
+/// Sends the request to the service provider, retrying according to the policy if any
+///
+/// The retries are immediate since a canister cannot sleep within a call.
 pub async fn web3_request(
+    service: ServiceProvider,
+    endpoint: &str,
+    payload: &str,
+    max_response_bytes: u64,
+    cycles_cost: u128,
+    retry_policy: Option<RetryPolicy>,
+) -> Result<String, ServiceError> {
+    let Some(RetryPolicy { attempts, rotate_providers }) = retry_policy else {
+        return web3_request_once(service, endpoint, payload, max_response_bytes, cycles_cost).await;
+    };
+
+    let ResolvedServiceProvider::Provider(first) = resolve_service_provider(service)?;
+    let providers = if rotate_providers {
+        provider_rotation(first.provider_id, get_chain_provider_ids(first.chain_id))
+    } else {
+        vec![first.provider_id]
+    };
+
+    let mut attempt = 0;
+    loop {
+        let provider = providers[attempt as usize % providers.len()];
+        attempt += 1;
+        match web3_request_once(ServiceProvider::Provider(provider), endpoint, payload, max_response_bytes, cycles_cost).await {
+            Ok(body) => {
+                ic_cdk::println!("web3_request to {} succeeded with provider {} (attempt {}/{})", endpoint, provider, attempt, attempts);
+                return Ok(body);
+            }
+            Err(err) if is_retryable(&err) && attempt < attempts => {
+                ic_cdk::println!("web3_request to {} failed with provider {} (attempt {}/{}): {:?}", endpoint, provider, attempt, attempts, err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+async fn web3_request_once(
     service: ServiceProvider,
     endpoint: &str,
     payload: &str,
     max_response_bytes: u64,
     cycles_cost: u128
 ) -> Result<String, ServiceError> {
+    let service = resolve_service_provider(service)?;
+    let ResolvedServiceProvider::Provider(provider) = &service;
+    let secrets = credential_secrets(provider);
+
     let response = do_request(
-        resolve_service_provider(service)?,
+        service,
         endpoint,
         payload,
         max_response_bytes,
         cycles_cost
     )
-    .await?;
-    get_http_response_body(response)
+    .await;
+    // @dev only the errors are redacted, since the successful responses are parsed and never logged
+    response
+        .and_then(get_http_response_body)
+        .map_err(|err| redact_service_error(err, &secrets))
 }
 
 async fn do_request(
@@ -125,6 +386,9 @@ async fn do_request(
         method = HttpMethod::POST;
         body = Some(payload.as_bytes().to_vec());
     }
+    let ResolvedServiceProvider::Provider(resolved) = &service;
+    let provider_id = resolved.provider_id;
+
     // Match service provider to the appropriate transform function
     let transform_fn: Option<TransformContext> = match service {
         ResolvedServiceProvider::Provider(provider) => {
@@ -155,26 +419,227 @@ async fn do_request(
     };
     match ic_cdk::api::management_canister::http_request::http_request(request, cycles_cost).await {
         Ok((response,)) => {
+            add_cycles_owed(provider_id, response.body.len() as u64);
+            // @dev server errors count as failures of the provider, unlike client errors
+            let success = get_http_response_status(response.status.clone()) < 500;
+            record_provider_outcome(provider_id, success, ic_cdk::api::time());
             Ok(response)
         }
         Err((code, message)) => {
+            record_provider_outcome(provider_id, false, ic_cdk::api::time());
             Err(HttpOutcallError::IcError{code, message}.into())
         }
     }
 }
 
 fn get_http_response_body(response: HttpResponse) -> Result<String, ServiceError> {
-    String::from_utf8(response.body).map_err(|e| {
-        HttpOutcallError::InvalidHttpJsonRpcResponse {
-            status: get_http_response_status(response.status),
+    let status = get_http_response_status(response.status);
+    // @dev transform functions may strip the headers, so a missing Content-Type is accepted
+    let content_type = response
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("content-type"))
+        .map(|header| header.value.to_ascii_lowercase());
+    let body = String::from_utf8(response.body).map_err(|e| {
+        ServiceError::from(HttpOutcallError::InvalidHttpJsonRpcResponse {
+            status,
             body: "".to_string(),
             parsing_error: Some(format!("{e}")),
+        })
+    })?;
+
+    if !(200..300).contains(&status) || content_type.as_ref().is_some_and(|ct| !ct.contains("json")) {
+        return Err(HttpOutcallError::InvalidHttpJsonRpcResponse {
+            status,
+            body,
+            parsing_error: Some(format!(
+                "expected a successful JSON response, got status {} with content type {:?}",
+                status, content_type
+            )),
         }
-        .into()
-    })
+        .into());
+    }
+    Ok(body)
 }
 
 pub fn get_http_response_status(status: candid::Nat) -> u16 {
     // If status.0 cannot be converted to u16, return u16::MAX (65535) as a fallback
     status.0.to_u16().unwrap_or(u16::MAX)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_response(status: u16, content_type: Option<&str>, body: &str) -> HttpResponse {
+        HttpResponse {
+            status: candid::Nat::from(status),
+            headers: content_type
+                .map(|value| HttpHeader {
+                    name: "Content-Type".to_string(),
+                    value: value.to_string(),
+                })
+                .into_iter()
+                .collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn should_accept_successful_json_responses() {
+        for content_type in [Some("application/json"), Some("application/json; charset=utf-8"), None] {
+            assert_eq!(
+                get_http_response_body(http_response(200, content_type, "{}")),
+                Ok("{}".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn should_reject_non_json_or_failed_responses() {
+        for response in [
+            http_response(500, Some("application/json"), "{}"),
+            http_response(200, Some("text/plain"), "Internal Server Error"),
+            http_response(404, None, "<!DOCTYPE html>"),
+        ] {
+            match get_http_response_body(response) {
+                Err(ServiceError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse { .. })) => {}
+                result => panic!("unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn should_rotate_providers_starting_with_the_resolved_one() {
+        assert_eq!(provider_rotation(2, vec![0, 1, 2, 3]), vec![2, 3, 0, 1]);
+        assert_eq!(provider_rotation(0, vec![0, 1]), vec![0, 1]);
+        assert_eq!(provider_rotation(5, vec![0, 1]), vec![5, 0, 1]);
+    }
+
+    #[test]
+    fn should_only_retry_transient_errors() {
+        let ic_error = |code| ServiceError::from(HttpOutcallError::IcError { code, message: String::new() });
+        let http_error = |status| {
+            ServiceError::from(HttpOutcallError::InvalidHttpJsonRpcResponse {
+                status,
+                body: String::new(),
+                parsing_error: None,
+            })
+        };
+        assert!(is_retryable(&ic_error(RejectionCode::SysTransient)));
+        assert!(!is_retryable(&ic_error(RejectionCode::CanisterReject)));
+        assert!(is_retryable(&http_error(503)));
+        assert!(!is_retryable(&http_error(404)));
+        assert!(is_retryable(&ServiceError::JsonRpcError(JsonRpcError { code: -32000, message: String::new() })));
+    }
+
+    #[test]
+    fn should_detect_responses_above_the_size_limit() {
+        let ic_error = |code, message: &str| ServiceError::from(HttpOutcallError::IcError { code, message: message.to_string() });
+        assert!(is_response_too_large(&ic_error(RejectionCode::SysFatal, "Http body exceeds size limit of 2048 bytes.")));
+        assert!(!is_response_too_large(&ic_error(RejectionCode::SysFatal, "Timeout expired")));
+        assert!(!is_response_too_large(&ic_error(RejectionCode::SysTransient, "size limit")));
+    }
+
+    #[test]
+    fn should_extract_base_unit_integer_fields() {
+        let json: Value = serde_json::json!({ "amount": "1000", "data": { "price": "62000" } });
+        assert_eq!(extract_u64_field(&json, &["amount"]), Ok(1_000));
+        assert_eq!(extract_u64_field(&json, &["data", "price"]), Ok(62_000));
+    }
+
+    #[test]
+    fn should_reject_missing_or_invalid_fields() {
+        let json: Value = serde_json::json!({ "amount": "1,000", "price": "1.5", "fee": "abc", "count": 3 });
+        for path in [&["amount"][..], &["price"], &["fee"], &["count"], &["missing"], &["amount", "value"]] {
+            assert!(extract_u64_field(&json, path).is_err(), "path: {:?}", path);
+        }
+    }
+
+    #[test]
+    fn should_redact_credentials_from_outcall_errors() {
+        let provider = Provider {
+            provider_id: 0,
+            owner: candid::Principal::anonymous(),
+            chain_id: 0,
+            hostname: "main.tyron.io/".to_string(),
+            credential_path: "v1/path-secret/".to_string(),
+            credential_headers: vec![HttpHeader {
+                name: "Authorization".to_string(),
+                value: "Bearer header-secret".to_string(),
+            }],
+            credential_path_secret: Some(true),
+            cycles_per_call: 0,
+            cycles_per_message_byte: 0,
+            cycles_owed: 0,
+            primary: false,
+            weight: None,
+            health: None,
+        };
+        let secrets = credential_secrets(&provider);
+        let err: ServiceError = HttpOutcallError::IcError {
+            code: ic_cdk::api::call::RejectionCode::SysTransient,
+            message: "failed: https://main.tyron.io/v1/path-secret/x (Authorization: Bearer header-secret)".to_string(),
+        }
+        .into();
+
+        let redacted = format!("{:?}", redact_service_error(err, &secrets));
+        assert!(!redacted.contains("header-secret"), "{}", redacted);
+        assert!(!redacted.contains("path-secret"), "{}", redacted);
+        assert!(redacted.contains(REDACTED));
+    }
+
+    #[test]
+    fn should_redact_all_the_credentials() {
+        let mut provider = Provider {
+            provider_id: 0,
+            owner: candid::Principal::anonymous(),
+            chain_id: 0,
+            hostname: "main.tyron.io/".to_string(),
+            credential_path: "api/v1/".to_string(),
+            credential_headers: vec![HttpHeader {
+                name: "X-Api-Key".to_string(),
+                value: "abc".to_string(),
+            }],
+            credential_path_secret: None,
+            cycles_per_call: 0,
+            cycles_per_message_byte: 0,
+            cycles_owed: 0,
+            primary: false,
+            weight: None,
+            health: None,
+        };
+        // short values are redacted too
+        assert_eq!(credential_secrets(&provider), vec!["abc".to_string(), "api/v1/".to_string()]);
+
+        provider.credential_path = String::new();
+        provider.credential_headers[0].value = String::new();
+        assert!(credential_secrets(&provider).is_empty());
+    }
+
+    #[test]
+    fn should_omit_the_body_of_invalid_responses() {
+        let err: ServiceError = HttpOutcallError::InvalidHttpJsonRpcResponse {
+            status: 500,
+            body: "error: key-0123456789".to_string(),
+            parsing_error: None,
+        }
+        .into();
+        let omitted = format!("{:?}", omit_response_body(err));
+        assert!(!omitted.contains("key-0123456789"), "{}", omitted);
+        assert!(omitted.contains("21 bytes omitted"), "{}", omitted);
+    }
+
+    #[test]
+    fn should_agree_on_the_runes_amount_reported_by_the_threshold() {
+        assert_eq!(runes_amount_consensus(&[1_000, 1_000, 999], 2), Some(1_000));
+        assert_eq!(runes_amount_consensus(&[1_000], 1), Some(1_000));
+    }
+
+    #[test]
+    fn should_reject_conflicting_runes_amounts() {
+        assert_eq!(runes_amount_consensus(&[1_000, 999, 998], 2), None);
+        assert_eq!(runes_amount_consensus(&[1_000, 1_000, 999, 999], 2), None);
+        assert_eq!(runes_amount_consensus(&[], 1), None);
+    }
+}
@@ -2,46 +2,45 @@
 // but a Candid dependency issue prevents direct import into Tyron.
 // I'm also making it more blockchain agnostic.
 
-use super::types::{Provider, RegisterProviderArgs, ServiceProvider, StorableServiceProvider, ProviderError, Metadata, ResolvedServiceProvider};
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-
-#[cfg(target_arch = "wasm32")]
-use ic_stable_structures::DefaultMemoryImpl;
-#[cfg(not(target_arch = "wasm32"))]
-use ic_stable_structures::VectorMemory;
+use super::types::{Provider, PrincipalStorable, RegisterProviderArgs, UpdateProviderArgs, ServiceProvider, StorableServiceProvider, ProviderError, Metadata, ResolvedServiceProvider, ServiceResult, ValidationError, HttpOutcallError, ProviderHealth, ProviderSelection};
+use candid::Principal;
+use crate::storage::{
+    memory, VMem as Memory, ALLOWED_HOSTNAMES_MEMORY_ID, AUTHORIZED_PRINCIPALS_MEMORY_ID,
+    PROVIDERS_MEMORY_ID, PROVIDER_METADATA_MEMORY_ID, SERVICE_PROVIDER_MAP_MEMORY_ID,
+};
 use ic_stable_structures::{Cell, StableBTreeMap};
 use std::cell::RefCell;
-
-#[cfg(not(target_arch = "wasm32"))]
-type Memory = VirtualMemory<VectorMemory>;
-#[cfg(target_arch = "wasm32")]
-type Memory = VirtualMemory<DefaultMemoryImpl>;
+use std::collections::BTreeMap;
 
 thread_local! {
     // @review asap (mainnet)
     // Unstable static data: this is reset when the canister is upgraded.
     // pub static UNSTABLE_METRICS: RefCell<Metrics> = RefCell::new(Metrics::default());
     // pub static UNSTABLE_SUBNET_SIZE: RefCell<u32> = RefCell::new(NODES_IN_FIDUCIARY_SUBNET);
+    pub static PROVIDER_HEALTH: RefCell<BTreeMap<u64, ProviderHealth>> = RefCell::new(BTreeMap::new());
+    static PROVIDER_SELECTIONS: RefCell<u64> = const { RefCell::new(0) };
 
     // Stable static data: this is preserved when the canister is upgraded.
-    #[cfg(not(target_arch = "wasm32"))]
-    pub static MEMORY_MANAGER: RefCell<MemoryManager<VectorMemory>> =
-        RefCell::new(MemoryManager::init(VectorMemory::new(RefCell::new(vec![]))));
-    #[cfg(target_arch = "wasm32")]
-    pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    // @dev the memories come from the memory manager of the storage module, shared with the event logs
     pub static METADATA: RefCell<Cell<Metadata, Memory>> = RefCell::new(Cell::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
+            memory(PROVIDER_METADATA_MEMORY_ID),
             Metadata::default()).unwrap());
+    pub static AUTHORIZED_PRINCIPALS: RefCell<StableBTreeMap<PrincipalStorable, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(AUTHORIZED_PRINCIPALS_MEMORY_ID)));
     pub static PROVIDERS: RefCell<StableBTreeMap<u64, Provider, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))));
+        StableBTreeMap::init(memory(PROVIDERS_MEMORY_ID)));
     pub static SERVICE_PROVIDER_MAP: RefCell<StableBTreeMap<StorableServiceProvider, u64, Memory>> = RefCell::new(
-        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))));
+        StableBTreeMap::init(memory(SERVICE_PROVIDER_MAP_MEMORY_ID)));
+    pub static ALLOWED_HOSTNAMES: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ALLOWED_HOSTNAMES_MEMORY_ID)));
 }
 
 pub fn init_service_provider() {
+    for hostname in get_default_allowed_hostnames() {
+        ALLOWED_HOSTNAMES.with(|hostnames| hostnames.borrow_mut().insert(hostname.to_string(), ()));
+    }
     for provider in get_default_providers() {
-        register_provider(provider);
+        register_provider(provider).expect("BUG: default provider must be valid");
     }
     for (service, hostname) in get_default_service_provider_hostnames() {
         let provider = find_provider(|p| {
@@ -68,16 +67,94 @@ pub fn get_default_providers() -> Vec<RegisterProviderArgs> {
             hostname: TYRON_MAINNET_HOSTNAME.to_string(),
             credential_path: TYRON_CREDENTIAL_PATH.to_string(),
             credential_headers: None,
+            credential_path_secret: None,
             cycles_per_call: 0,
             cycles_per_message_byte: 0,
+            weight: None,
         }
     ]
 }
 
-pub fn register_provider(args: RegisterProviderArgs) -> u64 {
-    // @review (mainnet)
-    // validate_hostname(&args.hostname).unwrap();
-    // validate_credential_path(&args.credential_path).unwrap();
+pub fn get_default_allowed_hostnames() -> Vec<&'static str> {
+    vec![TYRON_MAINNET_HOSTNAME]
+}
+
+// @governance
+pub fn set_allowed_hostnames(hostnames: Vec<String>) {
+    ALLOWED_HOSTNAMES.with(|allowed| {
+        let mut allowed = allowed.borrow_mut();
+        let current: Vec<String> = allowed.iter().map(|(hostname, _)| hostname).collect();
+        for hostname in current {
+            allowed.remove(&hostname);
+        }
+        for hostname in hostnames {
+            allowed.insert(hostname, ());
+        }
+    });
+}
+
+pub fn get_allowed_hostnames() -> Vec<String> {
+    ALLOWED_HOSTNAMES.with(|allowed| allowed.borrow().iter().map(|(hostname, _)| hostname).collect())
+}
+
+// @dev the canister attaches credentials to outcalls, so only allowlisted hosts can be registered
+pub fn validate_hostname(hostname: &str) -> Result<(), ValidationError> {
+    if ALLOWED_HOSTNAMES.with(|allowed| allowed.borrow().contains_key(&hostname.to_string())) {
+        Ok(())
+    } else {
+        Err(ValidationError::HostNotAllowed(hostname.to_string()))
+    }
+}
+
+// @dev the credential path is appended to the hostname, so it must not be able to alter the URL authority
+pub fn validate_credential_path(credential_path: &str) -> Result<(), ValidationError> {
+    let is_allowed_char = |c: char| c.is_ascii_alphanumeric() || "-._~/?=&%".contains(c);
+    if credential_path.starts_with('.')
+        || credential_path.contains("..")
+        || !credential_path.chars().all(is_allowed_char)
+    {
+        return Err(ValidationError::CredentialPathNotAllowed);
+    }
+    Ok(())
+}
+
+// @dev principals allowed to call the provider admin endpoints, in addition to the controllers
+fn is_authorized_principal(principal: &Principal) -> bool {
+    AUTHORIZED_PRINCIPALS.with(|authorized| authorized.borrow().contains_key(&PrincipalStorable(*principal)))
+}
+
+pub fn is_authorized(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal) || is_authorized_principal(principal)
+}
+
+// @dev the provider admin endpoints call this first; governance endpoints are controller-only
+pub fn check_authorized() -> Result<(), ProviderError> {
+    if is_authorized(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(ProviderError::NoPermission)
+    }
+}
+
+pub fn authorize(principal: Principal) {
+    AUTHORIZED_PRINCIPALS.with(|authorized| authorized.borrow_mut().insert(PrincipalStorable(principal), ()));
+}
+
+pub fn deauthorize(principal: Principal) {
+    AUTHORIZED_PRINCIPALS.with(|authorized| authorized.borrow_mut().remove(&PrincipalStorable(principal)));
+}
+
+pub fn get_authorized_principals() -> Vec<Principal> {
+    AUTHORIZED_PRINCIPALS.with(|authorized| authorized.borrow().iter().map(|(principal, _)| principal.0).collect())
+}
+
+pub fn register_provider(args: RegisterProviderArgs) -> Result<u64, ValidationError> {
+    do_register_provider(ic_cdk::caller(), args)
+}
+
+fn do_register_provider(caller: Principal, args: RegisterProviderArgs) -> Result<u64, ValidationError> {
+    validate_hostname(&args.hostname)?;
+    validate_credential_path(&args.credential_path)?;
     
     let provider_id = METADATA.with(|m| {
         let mut metadata = m.borrow().get().clone();
@@ -96,19 +173,336 @@ pub fn register_provider(args: RegisterProviderArgs) -> u64 {
             provider_id,
             Provider {
                 provider_id,
-                owner: ic_cdk::caller(),
+                owner: caller,
                 chain_id: args.chain_id,
                 hostname: args.hostname,
                 credential_path: args.credential_path,
                 credential_headers: args.credential_headers.unwrap_or_default(),
+                credential_path_secret: args.credential_path_secret,
                 cycles_per_call: args.cycles_per_call,
                 cycles_per_message_byte: args.cycles_per_message_byte,
                 cycles_owed: 0,
                 primary: false,
+                weight: args.weight,
+                health: None,
             },
         )
     });
-    provider_id
+    Ok(provider_id)
+}
+
+// @dev only the provider owner can update it; the chain id can only change if it keeps every service binding consistent
+pub fn update_provider(provider_id: u64, args: UpdateProviderArgs) -> ServiceResult<()> {
+    let caller = ic_cdk::caller();
+    do_update_provider(caller, ic_cdk::api::is_controller(&caller), provider_id, args)
+}
+
+fn do_update_provider(caller: Principal, is_controller: bool, provider_id: u64, args: UpdateProviderArgs) -> ServiceResult<()> {
+    let mut provider = PROVIDERS
+        .with(|providers| providers.borrow().get(&provider_id))
+        .ok_or(ProviderError::ProviderNotFound)?;
+
+    if provider.owner != caller {
+        return Err(ProviderError::NoPermission.into());
+    }
+    // @dev the pricing sets the cycles that the minter owes to the provider, so only the controllers can change it
+    if (args.cycles_per_call.is_some() || args.cycles_per_message_byte.is_some()) && !is_controller {
+        return Err(ProviderError::NoPermission.into());
+    }
+
+    if let Some(chain_id) = args.chain_id {
+        for service in get_provider_services(provider_id) {
+            check_service_chain_id(&service, chain_id);
+        }
+        provider.chain_id = chain_id;
+    }
+    if let Some(hostname) = args.hostname {
+        validate_hostname(&hostname)?;
+        provider.hostname = hostname;
+    }
+    if let Some(credential_path) = args.credential_path {
+        validate_credential_path(&credential_path)?;
+        provider.credential_path = credential_path;
+    }
+    if let Some(credential_headers) = args.credential_headers {
+        provider.credential_headers = credential_headers;
+    }
+    if let Some(credential_path_secret) = args.credential_path_secret {
+        provider.credential_path_secret = Some(credential_path_secret);
+    }
+    if let Some(cycles_per_call) = args.cycles_per_call {
+        provider.cycles_per_call = cycles_per_call;
+    }
+    if let Some(cycles_per_message_byte) = args.cycles_per_message_byte {
+        provider.cycles_per_message_byte = cycles_per_message_byte;
+    }
+    if let Some(weight) = args.weight {
+        provider.weight = Some(weight);
+    }
+
+    PROVIDERS.with(|providers| providers.borrow_mut().insert(provider_id, provider));
+    Ok(())
+}
+
+// @dev services currently mapped to the given provider
+fn get_provider_services(provider_id: u64) -> Vec<ServiceProvider> {
+    SERVICE_PROVIDER_MAP.with(|mappings| {
+        mappings
+            .borrow()
+            .iter()
+            .filter(|(_, id)| *id == provider_id)
+            .filter_map(|(service, _)| ServiceProvider::try_from(service).ok())
+            .collect()
+    })
+}
+
+const REDACTED: &str = "<redacted>";
+
+// @dev credential header values are secrets, so only their names are exposed, as is the credential path if marked as a secret
+fn redact_credentials(mut provider: Provider) -> Provider {
+    for header in provider.credential_headers.iter_mut() {
+        header.value = REDACTED.to_string();
+    }
+    if provider.is_credential_path_secret() {
+        provider.credential_path = REDACTED.to_string();
+    }
+    provider
+}
+
+// @dev the health of the providers is not persisted, so it is only attached to the returned providers
+fn with_health(mut provider: Provider) -> Provider {
+    provider.health = PROVIDER_HEALTH.with(|health| health.borrow().get(&provider.provider_id).copied());
+    provider
+}
+
+pub fn get_providers() -> Vec<Provider> {
+    PROVIDERS.with(|providers| {
+        providers
+            .borrow()
+            .iter()
+            .map(|(_, provider)| with_health(redact_credentials(provider)))
+            .collect()
+    })
+}
+
+pub fn get_provider(provider_id: u64) -> Option<Provider> {
+    PROVIDERS.with(|providers| {
+        providers
+            .borrow()
+            .get(&provider_id)
+            .map(|provider| with_health(redact_credentials(provider)))
+    })
+}
+
+// @governance
+// @dev marks the provider as the primary one for its chain, so that it gets selected first
+pub fn set_primary_provider(provider_id: u64) -> Result<(), ProviderError> {
+    PROVIDERS.with(|providers| {
+        let mut providers = providers.borrow_mut();
+        let chain_id = providers
+            .get(&provider_id)
+            .ok_or(ProviderError::ProviderNotFound)?
+            .chain_id;
+
+        let updated: Vec<Provider> = providers
+            .iter()
+            .filter(|(_, p)| p.chain_id == chain_id)
+            .map(|(id, mut p)| {
+                p.primary = id == provider_id;
+                p
+            })
+            .collect();
+
+        for provider in updated {
+            providers.insert(provider.provider_id, provider);
+        }
+        Ok(())
+    })
+}
+
+// @governance
+// @dev sets how the provider of a chain is picked among the registered ones
+pub fn set_provider_selection(policy: ProviderSelection) {
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        metadata.provider_selection = Some(policy);
+        m.borrow_mut().set(metadata).unwrap();
+    });
+}
+
+pub fn get_provider_selection() -> ProviderSelection {
+    METADATA.with(|m| m.borrow().get().provider_selection.unwrap_or_default())
+}
+
+/// The time (in nanoseconds) during which a provider whose last outcall failed is skipped.
+const DEGRADED_PROVIDER_COOLDOWN: u64 = 5 * 60 * 1_000_000_000;
+
+/// The share of the recent outcalls (in basis points) above which a failing provider is skipped.
+const MAX_PROVIDER_FAILURE_RATE_BPS: u64 = 5_000;
+
+// @dev records the outcome of an outcall, so that the selection can skip the failing providers
+pub fn record_provider_outcome(provider_id: u64, success: bool, now: u64) {
+    PROVIDER_HEALTH.with(|health| health.borrow_mut().entry(provider_id).or_default().record(success, now));
+}
+
+fn is_degraded(provider: &Provider) -> bool {
+    PROVIDER_HEALTH.with(|health| {
+        health.borrow().get(&provider.provider_id).is_some_and(|health| {
+            health.last_failure_time.is_some()
+                && health.is_degraded(ic_cdk::api::time(), DEGRADED_PROVIDER_COOLDOWN, MAX_PROVIDER_FAILURE_RATE_BPS)
+        })
+    })
+}
+
+// @dev the primary provider, or the first one
+fn pick_primary(providers: Vec<Provider>) -> Option<Provider> {
+    let position = providers.iter().position(|p| p.primary).unwrap_or(0);
+    providers.into_iter().nth(position)
+}
+
+// @dev picks one of the candidates according to the policy, where `turn` is the number of previous
+// selections; the degraded providers are skipped unless all of them are, in which case the primary
+// provider is picked so that the fallback is deterministic
+fn select_provider(
+    candidates: Vec<Provider>,
+    policy: ProviderSelection,
+    turn: u64,
+    is_degraded: impl Fn(&Provider) -> bool,
+) -> Option<Provider> {
+    let (healthy, degraded): (Vec<Provider>, Vec<Provider>) = candidates.into_iter().partition(|p| !is_degraded(p));
+    if healthy.is_empty() {
+        return pick_primary(degraded);
+    }
+    match policy {
+        ProviderSelection::Primary => pick_primary(healthy),
+        ProviderSelection::RoundRobin => {
+            let position = (turn % healthy.len() as u64) as usize;
+            healthy.into_iter().nth(position)
+        }
+        ProviderSelection::Weighted => {
+            let total_weight: u64 = healthy.iter().map(Provider::weight).sum();
+            if total_weight == 0 {
+                return pick_primary(healthy);
+            }
+            let mut point = turn % total_weight;
+            healthy.into_iter().find(|p| {
+                if point < p.weight() {
+                    true
+                } else {
+                    point -= p.weight();
+                    false
+                }
+            })
+        }
+    }
+}
+
+// @dev picks one of the providers matching the filter according to the selection policy
+fn choose_provider(f: impl Fn(&Provider) -> bool) -> Option<Provider> {
+    let candidates: Vec<Provider> = PROVIDERS.with(|providers| {
+        providers.borrow().iter().map(|(_, p)| p).filter(|p| f(p)).collect()
+    });
+    let turn = PROVIDER_SELECTIONS.with(|selections| {
+        let mut selections = selections.borrow_mut();
+        *selections = selections.wrapping_add(1);
+        *selections - 1
+    });
+    select_provider(candidates, get_provider_selection(), turn, is_degraded)
+}
+
+// @dev returns the providers with their credentials and the service bindings, for snapshots
+pub fn export_providers() -> (Vec<Provider>, Vec<(ServiceProvider, u64)>) {
+    let providers = PROVIDERS.with(|providers| providers.borrow().iter().map(|(_, p)| p).collect());
+    let service_providers = SERVICE_PROVIDER_MAP.with(|mappings| {
+        mappings
+            .borrow()
+            .iter()
+            .filter_map(|(service, provider_id)| {
+                ServiceProvider::try_from(service).ok().map(|service| (service, provider_id))
+            })
+            .collect()
+    });
+    (providers, service_providers)
+}
+
+// @dev restores the providers of a snapshot, keeping their ids
+pub fn import_providers(providers: Vec<Provider>, service_providers: Vec<(ServiceProvider, u64)>) {
+    let next_provider_id = providers.iter().map(|p| p.provider_id + 1).max().unwrap_or(0);
+    PROVIDERS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for provider in providers {
+            stored.insert(provider.provider_id, provider);
+        }
+    });
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        metadata.next_provider_id = metadata.next_provider_id.max(next_provider_id);
+        m.borrow_mut().set(metadata).unwrap();
+    });
+    for (service, provider_id) in service_providers {
+        if let Some(provider) = PROVIDERS.with(|stored| stored.borrow().get(&provider_id)) {
+            set_service_provider(&service, &provider);
+        }
+    }
+}
+
+// @dev credits the provider for an outcall served by its endpoint
+pub fn add_cycles_owed(provider_id: u64, response_size: u64) {
+    PROVIDERS.with(|providers| {
+        let mut providers = providers.borrow_mut();
+        if let Some(mut provider) = providers.get(&provider_id) {
+            let cycles = provider.cycles_per_call as u128
+                + provider.cycles_per_message_byte as u128 * response_size as u128;
+            provider.cycles_owed = provider.cycles_owed.saturating_add(cycles);
+            providers.insert(provider_id, provider);
+        }
+    });
+}
+
+fn set_cycles_owed(provider_id: u64, cycles_owed: u128) {
+    PROVIDERS.with(|providers| {
+        let mut providers = providers.borrow_mut();
+        if let Some(mut provider) = providers.get(&provider_id) {
+            provider.cycles_owed = cycles_owed;
+            providers.insert(provider_id, provider);
+        }
+    });
+}
+
+/// The maximum number of cycles that a provider can withdraw at once.
+pub const MAX_CYCLES_PER_WITHDRAWAL: u128 = 1_000_000_000_000;
+
+// @dev deposits the cycles owed to the provider, up to MAX_CYCLES_PER_WITHDRAWAL, into the given canister
+pub async fn withdraw_owed_cycles(provider_id: u64, canister_id: Principal) -> ServiceResult<u128> {
+    use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
+
+    let provider = PROVIDERS
+        .with(|providers| providers.borrow().get(&provider_id))
+        .ok_or(ProviderError::ProviderNotFound)?;
+    if provider.owner != ic_cdk::caller() {
+        return Err(ProviderError::NoPermission.into());
+    }
+
+    let cycles = provider.cycles_owed.min(MAX_CYCLES_PER_WITHDRAWAL);
+    if cycles == 0 {
+        return Ok(0);
+    }
+
+    // @dev deduct before the call so that concurrent withdrawals cannot claim the same cycles
+    set_cycles_owed(provider_id, provider.cycles_owed - cycles);
+    match deposit_cycles(CanisterIdRecord { canister_id }, cycles).await {
+        Ok(()) => Ok(cycles),
+        Err((code, message)) => {
+            PROVIDERS.with(|providers| {
+                let mut providers = providers.borrow_mut();
+                if let Some(mut provider) = providers.get(&provider_id) {
+                    provider.cycles_owed = provider.cycles_owed.saturating_add(cycles);
+                    providers.insert(provider_id, provider);
+                }
+            });
+            Err(HttpOutcallError::IcError { code, message }.into())
+        }
+    }
 }
 
 // @dev set default service provider hostnames
@@ -122,17 +516,21 @@ pub fn get_default_service_provider_hostnames() -> Vec<(ServiceProvider, &'stati
 }
 
 pub fn find_provider(f: impl Fn(&Provider) -> bool) -> Option<Provider> {
+    choose_provider(f)
+}
+
+// @dev the ids of the providers registered for the given chain, in ascending order
+pub fn get_chain_provider_ids(chain_id: u64) -> Vec<u64> {
     PROVIDERS.with(|providers| {
-        let providers = providers.borrow();
-        Some(
-            providers
-                .iter()
-                .find(|(_, p)| p.primary && f(p))
-                .or_else(|| providers.iter().find(|(_, p)| f(p)))?
-                .1,
-        )
+        providers
+            .borrow()
+            .iter()
+            .filter(|(_, p)| p.chain_id == chain_id)
+            .map(|(id, _)| id)
+            .collect()
     })
 }
+
 pub fn get_known_chain_id(service: &ServiceProvider) -> Option<u64> {
     match service {
         // RpcService::EthMainnet(_) => Some(ETH_MAINNET_CHAIN_ID),
@@ -150,32 +548,30 @@ pub fn set_service_provider(service: &ServiceProvider, provider: &Provider) {
     //     service,
     //     provider.provider_id
     // );
+    check_service_chain_id(service, provider.chain_id);
+    SERVICE_PROVIDER_MAP.with(|mappings| {
+        mappings
+            .borrow_mut()
+            .insert(StorableServiceProvider::new(service), provider.provider_id);
+    });
+}
+
+fn check_service_chain_id(service: &ServiceProvider, provider_chain_id: u64) {
     if let Some(chain_id) = get_known_chain_id(service) {
-        if chain_id != provider.chain_id {
+        if chain_id != provider_chain_id {
             ic_cdk::trap(&format!(
                 "Mismatch between service and provider chain ids ({} != {})",
-                chain_id, provider.chain_id
+                chain_id, provider_chain_id
             ))
         }
     }
-    SERVICE_PROVIDER_MAP.with(|mappings| {
-        mappings
-            .borrow_mut()
-            .insert(StorableServiceProvider::new(service), provider.provider_id);
-    });
 }
 
 pub fn resolve_service_provider(service: ServiceProvider) -> Result<ResolvedServiceProvider, ProviderError> {
     Ok(match service {
-        ServiceProvider::Chain(id) => ResolvedServiceProvider::Provider(PROVIDERS.with(|providers| {
-            let providers = providers.borrow();
-            Ok(providers
-                .iter()
-                .find(|(_, p)| p.primary && p.chain_id == id)
-                .or_else(|| providers.iter().find(|(_, p)| p.chain_id == id))
-                .ok_or(ProviderError::ProviderNotFound)?
-                .1)
-        })?),
+        ServiceProvider::Chain(id) => ResolvedServiceProvider::Provider(
+            choose_provider(|p| p.chain_id == id).ok_or(ProviderError::ProviderNotFound)?,
+        ),
         ServiceProvider::Provider(id) => ResolvedServiceProvider::Provider({
             PROVIDERS.with(|providers| {
                 providers
@@ -186,3 +582,231 @@ pub fn resolve_service_provider(service: ServiceProvider) -> Result<ResolvedServ
         }),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::https::types::RECENT_OUTCALLS_WINDOW;
+
+    #[test]
+    fn should_only_allow_allowlisted_hostnames() {
+        set_allowed_hostnames(vec![TYRON_MAINNET_HOSTNAME.to_string()]);
+
+        assert_eq!(validate_hostname(TYRON_MAINNET_HOSTNAME), Ok(()));
+        assert_eq!(
+            validate_hostname("evil.com/"),
+            Err(ValidationError::HostNotAllowed("evil.com/".to_string()))
+        );
+        assert_eq!(
+            validate_hostname("main.tyron.io"),
+            Err(ValidationError::HostNotAllowed("main.tyron.io".to_string()))
+        );
+
+        set_allowed_hostnames(vec!["evil.com/".to_string()]);
+        assert_eq!(validate_hostname("evil.com/"), Ok(()));
+        assert!(validate_hostname(TYRON_MAINNET_HOSTNAME).is_err());
+    }
+
+    #[test]
+    fn should_authorize_and_deauthorize_principals() {
+        let principal = Principal::from_slice(&[1, 2, 3]);
+        assert!(!is_authorized_principal(&principal));
+
+        authorize(principal);
+        assert!(is_authorized_principal(&principal));
+        assert!(get_authorized_principals().contains(&principal));
+
+        deauthorize(principal);
+        assert!(!is_authorized_principal(&principal));
+    }
+
+    #[test]
+    fn only_controllers_should_change_the_provider_pricing() {
+        set_allowed_hostnames(vec![TYRON_MAINNET_HOSTNAME.to_string()]);
+        let owner = Principal::from_slice(&[1]);
+        let provider_id = do_register_provider(
+            owner,
+            RegisterProviderArgs {
+                chain_id: 0,
+                hostname: TYRON_MAINNET_HOSTNAME.to_string(),
+                credential_path: TYRON_CREDENTIAL_PATH.to_string(),
+                credential_headers: None,
+                credential_path_secret: None,
+                cycles_per_call: 10,
+                cycles_per_message_byte: 1,
+                weight: None,
+            },
+        )
+        .unwrap();
+        let pricing = UpdateProviderArgs {
+            cycles_per_call: Some(u64::MAX),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            do_update_provider(owner, false, provider_id, pricing.clone()),
+            Err(ProviderError::NoPermission.into())
+        );
+        assert_eq!(
+            do_update_provider(Principal::anonymous(), true, provider_id, UpdateProviderArgs::default()),
+            Err(ProviderError::NoPermission.into())
+        );
+        assert_eq!(do_update_provider(owner, false, provider_id, UpdateProviderArgs::default()), Ok(()));
+        assert_eq!(get_provider(provider_id).unwrap().cycles_per_call, 10);
+
+        assert_eq!(do_update_provider(owner, true, provider_id, pricing), Ok(()));
+        assert_eq!(get_provider(provider_id).unwrap().cycles_per_call, u64::MAX);
+    }
+
+    #[test]
+    fn should_find_primary_provider() {
+        set_allowed_hostnames(vec![TYRON_MAINNET_HOSTNAME.to_string()]);
+        let args = RegisterProviderArgs {
+            chain_id: 0,
+            hostname: TYRON_MAINNET_HOSTNAME.to_string(),
+            credential_path: TYRON_CREDENTIAL_PATH.to_string(),
+            credential_headers: None,
+            credential_path_secret: None,
+            cycles_per_call: 0,
+            cycles_per_message_byte: 0,
+            weight: None,
+        };
+        let first = do_register_provider(Principal::anonymous(), args.clone()).unwrap();
+        let second = do_register_provider(Principal::anonymous(), args).unwrap();
+
+        let found = |f: fn(&Provider) -> bool| find_provider(f).map(|p| p.provider_id);
+        assert_eq!(found(|p| p.chain_id == 0), Some(first));
+
+        set_primary_provider(second).unwrap();
+        assert_eq!(found(|p| p.chain_id == 0), Some(second));
+        assert!(!get_provider(first).unwrap().primary);
+
+        set_primary_provider(first).unwrap();
+        assert_eq!(found(|p| p.chain_id == 0), Some(first));
+        assert!(!get_provider(second).unwrap().primary);
+
+        assert_eq!(set_primary_provider(42), Err(ProviderError::ProviderNotFound));
+    }
+
+    fn provider(provider_id: u64, weight: Option<u64>, primary: bool) -> Provider {
+        Provider {
+            provider_id,
+            owner: Principal::anonymous(),
+            chain_id: 0,
+            hostname: TYRON_MAINNET_HOSTNAME.to_string(),
+            credential_path: TYRON_CREDENTIAL_PATH.to_string(),
+            credential_headers: vec![],
+            credential_path_secret: None,
+            cycles_per_call: 0,
+            cycles_per_message_byte: 0,
+            cycles_owed: 0,
+            primary,
+            weight,
+            health: None,
+        }
+    }
+
+    #[test]
+    fn should_spread_selections_across_healthy_providers() {
+        let providers = vec![provider(0, None, true), provider(1, Some(2), false), provider(2, None, false)];
+        let selected = |policy, degraded: &[u64]| {
+            (0..8)
+                .map(|turn| {
+                    select_provider(providers.clone(), policy, turn, |p| degraded.contains(&p.provider_id))
+                        .unwrap()
+                        .provider_id
+                })
+                .collect::<Vec<u64>>()
+        };
+
+        assert_eq!(selected(ProviderSelection::Primary, &[]), vec![0; 8]);
+        assert_eq!(selected(ProviderSelection::RoundRobin, &[]), vec![0, 1, 2, 0, 1, 2, 0, 1]);
+        assert_eq!(selected(ProviderSelection::Weighted, &[]), vec![0, 1, 1, 2, 0, 1, 1, 2]);
+        assert_eq!(selected(ProviderSelection::Primary, &[0]), vec![1; 8]);
+        assert_eq!(selected(ProviderSelection::RoundRobin, &[1]), vec![0, 2, 0, 2, 0, 2, 0, 2]);
+        assert_eq!(selected(ProviderSelection::RoundRobin, &[0, 1, 2]), vec![0; 8]);
+    }
+
+    #[test]
+    fn should_only_degrade_providers_failing_recently() {
+        let cooldown = 100;
+        let health = |last_success_time, last_failure_time| ProviderHealth {
+            last_success_time,
+            last_failure_time,
+            ..Default::default()
+        };
+
+        assert!(!health(None, None).is_degraded(1_000, cooldown, 5_000));
+        assert!(health(None, Some(950)).is_degraded(1_000, cooldown, 5_000));
+        assert!(!health(None, Some(850)).is_degraded(1_000, cooldown, 5_000));
+        assert!(!health(Some(960), Some(950)).is_degraded(1_000, cooldown, 5_000));
+        assert!(health(Some(940), Some(950)).is_degraded(1_000, cooldown, 5_000));
+    }
+
+    #[test]
+    fn should_degrade_providers_above_the_failure_rate() {
+        let mut health = ProviderHealth::default();
+        // @dev the failures are older than the cooldown, so only the failure rate matters
+        for call in 0..10 {
+            health.record(call % 3 != 0, call);
+        }
+        assert_eq!((health.calls_total, health.failures_total, health.last_failure_time), (10, 4, Some(9)));
+        assert!(!health.is_degraded(1_000, 100, 5_000));
+        assert!(health.is_degraded(1_000, 100, 3_000));
+
+        for call in 10..30 {
+            health.record(false, call);
+        }
+        assert_eq!((health.calls_total, health.failures_total), (30, 24));
+        assert!(health.recent_calls < RECENT_OUTCALLS_WINDOW);
+        assert!(health.is_degraded(1_000, 100, 5_000));
+    }
+
+    #[test]
+    fn should_probe_providers_degraded_by_the_failure_rate() {
+        let mut health = ProviderHealth::default();
+        for call in 0..20 {
+            health.record(false, call);
+        }
+        assert!(health.is_degraded(1_000, 100, 5_000));
+        // @dev the last failure is older than the probe cooldowns
+        assert!(!health.is_degraded(1_019, 100, 5_000));
+
+        // @dev a failing probe degrades the provider again
+        let mut failing = health;
+        failing.record(false, 1_019);
+        assert!(failing.is_degraded(1_020, 100, 5_000));
+        assert!(failing.is_degraded(2_000, 100, 5_000));
+
+        // @dev a successful probe lets the provider recover
+        health.record(true, 1_019);
+        assert!(!health.is_degraded(1_020, 100, 5_000));
+        for call in 1_020..1_040 {
+            health.record(true, call);
+        }
+        assert!(health.recent_failures * 2 < health.recent_calls);
+    }
+
+    #[test]
+    fn should_validate_credential_path() {
+        for path in ["", TYRON_CREDENTIAL_PATH, "v1/api-key", "api/?key=abc%20d&x=1"] {
+            assert_eq!(validate_credential_path(path), Ok(()), "path: {}", path);
+        }
+        for path in [
+            "@evil.com/",
+            ".evil.com/",
+            "../admin",
+            "api/../../secret",
+            "api/#fragment",
+            "api key",
+            "://evil.com",
+        ] {
+            assert_eq!(
+                validate_credential_path(path),
+                Err(ValidationError::CredentialPathNotAllowed),
+                "path: {}",
+                path
+            );
+        }
+    }
+}
@@ -31,6 +31,8 @@ impl Storable for PrincipalStorable {
 pub struct Metadata {
     pub next_provider_id: u64,
     pub open_rpc_access: bool,
+    // @dev optional so that the metadata stored before the field was added still decodes
+    pub provider_selection: Option<ProviderSelection>,
 }
 
 impl Default for Metadata {
@@ -38,10 +40,84 @@ impl Default for Metadata {
         Self {
             next_provider_id: 0,
             open_rpc_access: true,
+            provider_selection: None,
         }
     }
 }
 
+/// How a provider is picked among the providers registered for the same chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ProviderSelection {
+    /// The primary provider, or the first registered one.
+    #[default]
+    Primary,
+    /// Each provider in turn.
+    RoundRobin,
+    /// Each provider in turn, as many times in a row as its weight.
+    Weighted,
+}
+
+/// The number of outcalls after which the recent counters of a [ProviderHealth] are halved,
+/// so that the recent failure rate mostly reflects the last outcalls.
+pub const RECENT_OUTCALLS_WINDOW: u64 = 20;
+
+/// The number of cooldowns after the last failure of a provider skipped for its failure rate
+/// at which it is probed again, since a skipped provider gets no outcalls to recover with.
+pub const FAILURE_RATE_PROBE_COOLDOWNS: u64 = 10;
+
+/// The outcomes of the outcalls to a provider, reset when the canister is upgraded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProviderHealth {
+    #[serde(rename = "callsTotal")]
+    pub calls_total: u64,
+    #[serde(rename = "failuresTotal")]
+    pub failures_total: u64,
+    #[serde(rename = "lastSuccessTime")]
+    pub last_success_time: Option<u64>,
+    #[serde(rename = "lastFailureTime")]
+    pub last_failure_time: Option<u64>,
+    /// The outcalls counted in the recent failure rate, see [RECENT_OUTCALLS_WINDOW].
+    #[serde(rename = "recentCalls")]
+    pub recent_calls: u64,
+    #[serde(rename = "recentFailures")]
+    pub recent_failures: u64,
+}
+
+impl ProviderHealth {
+    pub fn record(&mut self, success: bool, now: u64) {
+        self.calls_total = self.calls_total.saturating_add(1);
+        self.recent_calls += 1;
+        if success {
+            self.last_success_time = Some(now);
+        } else {
+            self.failures_total = self.failures_total.saturating_add(1);
+            self.recent_failures += 1;
+            self.last_failure_time = Some(now);
+        }
+        if self.recent_calls >= RECENT_OUTCALLS_WINDOW {
+            self.recent_calls /= 2;
+            self.recent_failures /= 2;
+        }
+    }
+
+    /// Returns whether the last outcall failed less than `cooldown` nanoseconds ago, or more
+    /// than `max_failure_rate_bps` of the recent outcalls failed and the last one less than
+    /// [FAILURE_RATE_PROBE_COOLDOWNS] cooldowns ago.
+    pub fn is_degraded(&self, now: u64, cooldown: u64, max_failure_rate_bps: u64) -> bool {
+        let Some(failed_at) = self.last_failure_time else {
+            return false;
+        };
+        let since_failure = now.saturating_sub(failed_at);
+        let failing_recently = since_failure < cooldown
+            && self.last_success_time.map_or(true, |succeeded_at| succeeded_at < failed_at);
+        // @dev a few outcalls are not enough to tell a failing provider from bad luck
+        let failure_rate_exceeded = since_failure < cooldown.saturating_mul(FAILURE_RATE_PROBE_COOLDOWNS)
+            && self.recent_calls >= RECENT_OUTCALLS_WINDOW / 2
+            && self.recent_failures * 10_000 > self.recent_calls * max_failure_rate_bps;
+        failing_recently || failure_rate_exceeded
+    }
+}
+
 impl Storable for Metadata {
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(Encode!(self).unwrap())
@@ -73,6 +149,9 @@ pub struct Provider {
     pub credential_path: String,
     #[serde(rename = "credentialHeaders")]
     pub credential_headers: Vec<HttpHeader>,
+    /// Whether the credential path embeds a secret (e.g. an API key), defaulting to false.
+    #[serde(rename = "credentialPathSecret")]
+    pub credential_path_secret: Option<bool>,
     #[serde(rename = "cyclesPerCall")]
     pub cycles_per_call: u64,
     #[serde(rename = "cyclesPerMessageByte")]
@@ -80,9 +159,22 @@ pub struct Provider {
     #[serde(rename = "cyclesOwed")]
     pub cycles_owed: u128,
     pub primary: bool,
+    /// The share of the outcalls served by the provider under [ProviderSelection::Weighted],
+    /// defaulting to 1.
+    pub weight: Option<u64>,
+    /// The outcomes of the outcalls to the provider, only set by the queries.
+    pub health: Option<ProviderHealth>,
 }
 
 impl Provider {
+    pub fn weight(&self) -> u64 {
+        self.weight.unwrap_or(1)
+    }
+
+    pub fn is_credential_path_secret(&self) -> bool {
+        self.credential_path_secret.unwrap_or(false)
+    }
+
     pub fn api(&self) -> ProviderApi {
         ProviderApi {
             url: format!("https://{}{}", self.hostname, self.credential_path),
@@ -118,10 +210,31 @@ pub struct RegisterProviderArgs {
     pub credential_path: String,
     #[serde(rename = "credentialHeaders")]
     pub credential_headers: Option<Vec<HttpHeader>>,
+    #[serde(rename = "credentialPathSecret")]
+    pub credential_path_secret: Option<bool>,
     #[serde(rename = "cyclesPerCall")]
     pub cycles_per_call: u64,
     #[serde(rename = "cyclesPerMessageByte")]
     pub cycles_per_message_byte: u64,
+    pub weight: Option<u64>,
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct UpdateProviderArgs {
+    #[serde(rename = "chainId")]
+    pub chain_id: Option<u64>,
+    pub hostname: Option<String>,
+    #[serde(rename = "credentialPath")]
+    pub credential_path: Option<String>,
+    #[serde(rename = "credentialHeaders")]
+    pub credential_headers: Option<Vec<HttpHeader>>,
+    #[serde(rename = "credentialPathSecret")]
+    pub credential_path_secret: Option<bool>,
+    #[serde(rename = "cyclesPerCall")]
+    pub cycles_per_call: Option<u64>,
+    #[serde(rename = "cyclesPerMessageByte")]
+    pub cycles_per_message_byte: Option<u64>,
+    pub weight: Option<u64>,
 }
 
 #[derive(Clone, PartialEq, Eq, Ord, PartialOrd, Hash, Serialize, Deserialize, CandidType)]
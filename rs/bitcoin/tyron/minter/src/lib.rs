@@ -6,7 +6,7 @@ use crate::runes::check_runes_minter_utxos;
 use crate::state::ReimbursementReason;
 use crate::tasks::schedule_after;
 use crate::tx::TxOut;
-use crate::updates::get_withdrawal_account::compute_subaccount;
+use crate::updates::get_withdrawal_account::{minter_account, SubaccountKind};
 use crate::updates::update_balance::update_runes_balance;
 use candid::{CandidType, Deserialize};
 use ic_btc_interface::{MillisatoshiPerByte, Network, OutPoint, Satoshi, Txid, Utxo};
@@ -33,6 +33,7 @@ pub mod memo;
 pub mod metrics;
 pub mod queries;
 pub mod signature;
+pub mod snapshot;
 pub mod state;
 pub mod storage;
 pub mod tasks;
@@ -102,6 +103,22 @@ pub struct MinterInfo {
     pub kyt_fee: u64,
 }
 
+/// The configuration of the minter, as returned by the [get_config] endpoint.
+#[derive(CandidType, Debug, Deserialize, Serialize)]
+pub struct MinterConfig {
+    pub btc_network: lifecycle::init::BtcNetwork,
+    pub min_confirmations: u32,
+    pub kyt_fee: u64,
+    pub min_btc_deposit: u64,
+    pub retrieve_btc_min_amount: u64,
+    pub ledger_id: candid::Principal,
+    pub susd_id: candid::Principal,
+    pub xrc_id: candid::Principal,
+    pub siwb_id: candid::Principal,
+    pub kyt_principal: Option<candid::Principal>,
+    pub xrc_id_fallback: Option<candid::Principal>,
+}
+
 #[derive(CandidType, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ECDSAPublicKey {
     pub public_key: Vec<u8>,
@@ -192,26 +209,35 @@ fn compute_min_withdrawal_amount(median_fee_rate_e3s: MillisatoshiPerByte) -> u6
         + 100_000
 }
 
-/// Returns an estimate for transaction fees in millisatoshi per vbyte. Returns
-/// None if the bitcoin canister is unavailable or does not have enough data for
-/// an estimate yet.
-pub async fn estimate_fee_per_vbyte() -> Option<MillisatoshiPerByte> {
-    /// The default fee we use on regtest networks if there are not enough data
-    /// to compute the median fee.
-    const DEFAULT_FEE: MillisatoshiPerByte = 5_000;
+/// The default fee we use on regtest networks, and for estimates if there are not
+/// enough data to compute the fee.
+pub const DEFAULT_FEE_PER_VBYTE: MillisatoshiPerByte = 5_000;
+
+/// Returns the fee at the given percentile of the current fee percentiles, or
+/// None if the Bitcoin canister did not report enough data points.
+pub fn fee_at_percentile(fees: &[MillisatoshiPerByte], percentile: u64) -> Option<MillisatoshiPerByte> {
+    if fees.len() < 100 {
+        return None;
+    }
+    fees.get(percentile as usize).copied()
+}
 
-    let btc_network = state::read_state(|s| s.btc_network);
+/// Returns an estimate for transaction fees in millisatoshi per vbyte at the
+/// configured fee percentile. Returns None if the bitcoin canister is unavailable
+/// or does not have enough data for an estimate yet.
+pub async fn estimate_fee_per_vbyte() -> Option<MillisatoshiPerByte> {
+    let (btc_network, fee_percentile) = state::read_state(|s| (s.btc_network, s.fee_percentile));
     match management::get_current_fees(btc_network).await {
         Ok(fees) => {
             if btc_network == Network::Regtest {
-                return Some(DEFAULT_FEE);
+                return Some(DEFAULT_FEE_PER_VBYTE);
             }
-            if fees.len() >= 100 {
+            if let Some(fee) = fee_at_percentile(&fees, fee_percentile) {
                 state::mutate_state(|s| {
                     s.last_fee_per_vbyte = fees.clone();
-                    s.retrieve_btc_min_amount = compute_min_withdrawal_amount(fees[50]);
+                    s.retrieve_btc_min_amount = compute_min_withdrawal_amount(fee);
                 });
-                Some(fees[50])
+                Some(fee)
             } else {
                 log!(
                     P0,
@@ -524,12 +550,7 @@ async fn finalize_requests() {
     &dao_addr[2]; // runes minter
 
     // runes minter subaccount
-    let subaccount = compute_subaccount(0, &main_address.display(btc_network));
-
-    let main_account = Account {
-        owner: ic_cdk::id(),
-        subaccount: Some(subaccount),
-    };
+    let main_account = minter_account(SubaccountKind::Swap, &main_address.display(btc_network));
 
     let new_utxos = fetch_main_utxos(&main_account, &main_address).await;
 
@@ -1620,6 +1641,7 @@ pub async fn distribute_kyt_fees() {
     enum MintError {
         TransferError(TransferError),
         CallError(i32, String),
+        BlockIndexOverflow(candid::Nat),
     }
 
     async fn mint(amount: u64, to: candid::Principal, memo: Memo) -> Result<u64, MintError> {
@@ -1644,7 +1666,7 @@ pub async fn distribute_kyt_fees() {
             .await
             .map_err(|(code, msg)| MintError::CallError(code, msg))?
             .map_err(MintError::TransferError)
-            .map(|n| n.0.to_u64().expect("nat does not fit into u64"))
+            .and_then(|n| n.0.to_u64().ok_or(MintError::BlockIndexOverflow(n)))
     }
 
     let fees_to_distribute = state::read_state(|s| s.owed_kyt_amount.clone());
@@ -1793,6 +1815,13 @@ pub fn timer() {
                 }
             });
         }
+        TaskType::RevertStaleRunesDeposits => {
+            ic_cdk::spawn(async {
+                const STALE_RUNES_DEPOSITS_PERIOD: Duration = Duration::from_secs(60 * 60);
+                updates::update_balance::revert_stale_runes_deposits().await;
+                schedule_after(STALE_RUNES_DEPOSITS_PERIOD, TaskType::RevertStaleRunesDeposits);
+            });
+        }
     }
 }
 
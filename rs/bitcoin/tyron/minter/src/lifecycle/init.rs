@@ -8,6 +8,33 @@ use serde::Serialize;
 
 pub const DEFAULT_MIN_CONFIRMATIONS: u32 = 6;
 pub const DEFAULT_KYT_FEE: u64 = 0;
+pub const DEFAULT_MAX_UTXOS_PER_UPDATE: u64 = 10;
+pub const DEFAULT_MIN_PAYMENT_SUSD: u64 = 20_000_000;
+pub const DEFAULT_MIN_PAYMENT_BTC: u64 = 200;
+pub const DEFAULT_PENDING_RUNES_TTL: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+pub const DEFAULT_DEBT_CEILING_SUSD: u64 = u64::MAX;
+pub const DEFAULT_FEE_PERCENTILE: u64 = 50;
+pub const DEFAULT_QUOTE_CURRENCY: &str = "USD";
+pub const DEFAULT_RATE_PROVIDERS: &[u64] = &[];
+pub const DEFAULT_RATE_MAX_DEVIATION_BPS: u64 = 500;
+pub const DEFAULT_CIRCUIT_BREAKER_BPS: u64 = 2_000;
+pub const DEFAULT_CIRCUIT_BREAKER_WINDOW: u64 = 60 * 60 * 1_000_000_000;
+pub const DEFAULT_UPDATE_BALANCE_INTERVAL: u64 = 10 * 1_000_000_000;
+pub const DEFAULT_MAX_PENDING_UTXOS: u64 = 100;
+pub const DEFAULT_INDEXER_MAX_RESPONSE_BYTES: u64 = 2_048;
+pub const DEFAULT_MAX_COLLATERAL_PER_ACCOUNT: u64 = u64::MAX;
+pub const DEFAULT_GUARD_TIMEOUT: u64 = 15 * 60 * 1_000_000_000;
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: u64 = 100;
+pub const DEFAULT_LIQUIDATION_BONUS_BPS: u64 = 500;
+pub const DEFAULT_LIQUIDATION_GRACE_PERIOD: u64 = 60 * 60 * 1_000_000_000;
+pub const DEFAULT_LIQUIDATION_THRESHOLD_BPS: u64 = 12_000;
+pub const DEFAULT_STABILITY_FEE_BPS_PER_YEAR: u64 = 0;
+pub const DEFAULT_REDEMPTION_FEE_BPS: u64 = 0;
+pub const DEFAULT_MIN_REDEEM_BTC: u64 = 1_000;
+pub const DEFAULT_MAX_REDEEM_BTC: u64 = u64::MAX;
+pub const DEFAULT_PIN_LEDGER_FEES: bool = false;
+pub const DEFAULT_XRC_CYCLES: u64 = 10_000_000_000;
+pub const DEFAULT_MIN_COLLATERAL_RATIO_BPS: u64 = 15_000;
 
 #[derive(CandidType, serde::Deserialize)]
 pub enum MinterArg {
@@ -98,10 +125,190 @@ pub struct InitArgs {
     /// NOTE: this field is optional for backward compatibility.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub min_deposit: Option<u64>,
+
+    /// Maximum number of UTXOs processed by a single balance update
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_utxos_per_update: Option<u64>,
+
+    /// Minimum amount of SUSD that can be sent in a payment
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_payment_susd: Option<u64>,
+
+    /// Minimum amount of bitcoin that can be credited in a payment
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_payment_btc: Option<u64>,
+
+    /// The time (in nanoseconds) after which an unfinalized runes deposit is reverted.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_runes_ttl: Option<u64>,
+
+    /// The maximum total amount of SUSD that the minter can lend.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debt_ceiling_susd: Option<u64>,
+
+    /// The percentile of the current Bitcoin fees used to build transactions (0-99).
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_percentile: Option<u64>,
+
+    /// The fiat currency the BTC collateral is priced in, one of USD, EUR or GBP.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_currency: Option<String>,
+
+    /// The service providers queried for the BTC price alongside the exchange rate canister.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_providers: Option<Vec<u64>>,
+
+    /// The maximum deviation (in basis points) of a BTC price source from the median before it is discarded.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_max_deviation_bps: Option<u64>,
+
+    /// The maximum move (in basis points) of the BTC exchange rate within the circuit breaker window before minting is halted.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_bps: Option<u64>,
+
+    /// The time (in nanoseconds) during which the last accepted exchange rate is the reference of the circuit breaker.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_window: Option<u64>,
+
+    /// The minimum time (in nanoseconds) between two accepted update_ssi_balance calls for the same SSI.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_balance_interval: Option<u64>,
+
+    /// The maximum number of pending UTXOs reported in a NoNewUtxos error.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pending_utxos: Option<u64>,
+
+    /// The initial maximum size (in bytes) of the runes indexer responses.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indexer_max_response_bytes: Option<u64>,
+
+    /// The maximum BTC collateral (in Satoshi) that a single SSI can deposit.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_collateral_per_account: Option<u64>,
+
+    /// The time (in nanoseconds) after which a per-account guard is considered stale and can be reclaimed.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guard_timeout: Option<u64>,
+
+    /// The maximum number of concurrent requests per kind (balance updates, retrievals).
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<u64>,
+
+    /// The bonus (in basis points of the repaid SUSD) of the collateral that a liquidator receives.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidation_bonus_bps: Option<u64>,
+
+    /// The time (in nanoseconds) that a loan must stay below the liquidation threshold before it can be liquidated.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidation_grace_period: Option<u64>,
+
+    /// The collateral ratio (in basis points) below which a loan can be liquidated. It cannot exceed the minimum collateral ratio of new loans.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidation_threshold_bps: Option<u64>,
+
+    /// The yearly stability fee (in basis points) accrued on the outstanding SUSD loans.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stability_fee_bps_per_year: Option<u64>,
+
+    /// The fee (in basis points of the redeemed SBTC) charged on bitcoin redemptions.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redemption_fee_bps: Option<u64>,
+
+    /// Minimum amount of bitcoin collateral (in Satoshi) that can be redeemed at once.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_redeem_btc: Option<u64>,
+
+    /// Maximum amount of bitcoin collateral (in Satoshi) that can be redeemed at once.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_redeem_btc: Option<u64>,
+
+    /// Whether the transfers of the minter state the ledger fee they expect instead of letting the ledger apply its default.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_ledger_fees: Option<bool>,
+
+    /// The CanisterId of a secondary Exchange Rate Canister, queried when the primary one fails.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xrc_id_fallback: Option<CanisterId>,
+
+    /// The cycles attached to each call to the Exchange Rate Canister.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xrc_cycles: Option<u64>,
+
+    /// The cycles attached to each bitcoin_get_utxos call, defaulting to the fee of the Bitcoin network.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub get_utxos_cycles: Option<u64>,
+
+    /// The minimum collateral ratio (in basis points) of an open loan, which gates new minting and partial redemptions.
+    /// NOTE: this field is optional for backward compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_collateral_ratio_bps: Option<u64>,
 }
 
 pub fn init(args: InitArgs) {
-    let state: MinterState = MinterState::from(args);
+    let mut state: MinterState = MinterState::from(args);
+    crate::lifecycle::upgrade::migrate(&mut state);
     state.validate_config();
     replace_state(state);
 }
+
+/// Returns the init arguments of a test minter, whose optional fields default to `None`.
+#[cfg(test)]
+pub(crate) fn test_init_args() -> InitArgs {
+    /// The init arguments without the optional fields.
+    #[derive(CandidType)]
+    struct RequiredInitArgs {
+        btc_network: BtcNetwork,
+        ecdsa_key_name: String,
+        retrieve_btc_min_amount: u64,
+        ledger_id: CanisterId,
+        susd_id: CanisterId,
+        xrc_id: CanisterId,
+        siwb_id: CanisterId,
+        max_time_in_queue_nanos: u64,
+        mode: Mode,
+    }
+
+    candid::decode_one(
+        &candid::encode_one(RequiredInitArgs {
+            btc_network: BtcNetwork::Regtest,
+            ecdsa_key_name: "some_key".to_string(),
+            retrieve_btc_min_amount: 2_000,
+            ledger_id: CanisterId::from_u64(1),
+            susd_id: CanisterId::from_u64(2),
+            xrc_id: CanisterId::from_u64(3),
+            siwb_id: CanisterId::from_u64(4),
+            max_time_in_queue_nanos: 0,
+            mode: Mode::GeneralAvailability,
+        })
+        .unwrap(),
+    )
+    .unwrap()
+}
@@ -1,6 +1,6 @@
 use crate::logs::P0;
 use crate::state::eventlog::{replay, Event};
-use crate::state::{replace_state, Mode};
+use crate::state::{replace_state, MinterState, Mode};
 use crate::storage::{count_events, events, record_event};
 use candid::{CandidType, Deserialize};
 use ic_base_types::CanisterId;
@@ -36,6 +36,145 @@ pub struct UpgradeArgs {
     /// Minimum amount of bitcoin that can be deposited
     #[serde(skip_serializing_if = "Option::is_none")]
     pub min_deposit: Option<u64>,
+
+    /// Maximum number of UTXOs processed by a single balance update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_utxos_per_update: Option<u64>,
+
+    /// Minimum amount of SUSD that can be sent in a payment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_payment_susd: Option<u64>,
+
+    /// Minimum amount of bitcoin that can be credited in a payment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_payment_btc: Option<u64>,
+
+    /// The time (in nanoseconds) after which an unfinalized runes deposit is reverted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_runes_ttl: Option<u64>,
+
+    /// The maximum total amount of SUSD that the minter can lend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debt_ceiling_susd: Option<u64>,
+
+    /// The percentile of the current Bitcoin fees used to build transactions (0-99).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_percentile: Option<u64>,
+
+    /// The fiat currency the BTC collateral is priced in, one of USD, EUR or GBP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_currency: Option<String>,
+
+    /// The service providers queried for the BTC price alongside the exchange rate canister.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_providers: Option<Vec<u64>>,
+
+    /// The maximum deviation (in basis points) of a BTC price source from the median before it is discarded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_max_deviation_bps: Option<u64>,
+
+    /// The maximum move (in basis points) of the BTC exchange rate within the circuit breaker window before minting is halted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_bps: Option<u64>,
+
+    /// The time (in nanoseconds) during which the last accepted exchange rate is the reference of the circuit breaker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_window: Option<u64>,
+
+    /// The minimum time (in nanoseconds) between two accepted update_ssi_balance calls for the same SSI.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_balance_interval: Option<u64>,
+
+    /// The maximum number of pending UTXOs reported in a NoNewUtxos error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pending_utxos: Option<u64>,
+
+    /// The initial maximum size (in bytes) of the runes indexer responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indexer_max_response_bytes: Option<u64>,
+
+    /// The maximum BTC collateral (in Satoshi) that a single SSI can deposit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_collateral_per_account: Option<u64>,
+
+    /// The time (in nanoseconds) after which a per-account guard is considered stale and can be reclaimed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guard_timeout: Option<u64>,
+
+    /// The maximum number of concurrent requests per kind (balance updates, retrievals).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<u64>,
+
+    /// The bonus (in basis points of the repaid SUSD) of the collateral that a liquidator receives.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidation_bonus_bps: Option<u64>,
+
+    /// The time (in nanoseconds) that a loan must stay below the liquidation threshold before it can be liquidated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidation_grace_period: Option<u64>,
+
+    /// The collateral ratio (in basis points) below which a loan can be liquidated. It cannot exceed the minimum collateral ratio of new loans.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidation_threshold_bps: Option<u64>,
+
+    /// The yearly stability fee (in basis points) accrued on the outstanding SUSD loans.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stability_fee_bps_per_year: Option<u64>,
+
+    /// The fee (in basis points of the redeemed SBTC) charged on bitcoin redemptions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redemption_fee_bps: Option<u64>,
+
+    /// Minimum amount of bitcoin collateral (in Satoshi) that can be redeemed at once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_redeem_btc: Option<u64>,
+
+    /// Maximum amount of bitcoin collateral (in Satoshi) that can be redeemed at once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_redeem_btc: Option<u64>,
+
+    /// Whether the transfers of the minter state the ledger fee they expect instead of letting the ledger apply its default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_ledger_fees: Option<bool>,
+
+    /// The CanisterId of a secondary Exchange Rate Canister, queried when the primary one fails.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xrc_id_fallback: Option<CanisterId>,
+
+    /// The cycles attached to each call to the Exchange Rate Canister.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xrc_cycles: Option<u64>,
+
+    /// The cycles attached to each bitcoin_get_utxos call, defaulting to the fee of the Bitcoin network.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub get_utxos_cycles: Option<u64>,
+
+    /// The minimum collateral ratio (in basis points) of an open loan, which gates new minting and partial redemptions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_collateral_ratio_bps: Option<u64>,
+}
+
+/// The current version of the state layout.
+///
+/// Bump it together with a new step in [MinterState::migrate_to] whenever a change needs
+/// more than the defaults of the init and upgrade arguments to upgrade an existing state.
+pub const STATE_VERSION: u32 = 1;
+
+/// Migrates the state to [STATE_VERSION] and records the migration in the event log,
+/// so that replaying the log yields the migrated state.
+pub fn migrate(state: &mut MinterState) {
+    if state.state_version < STATE_VERSION {
+        log!(
+            P0,
+            "[upgrade]: migrating the state from version {} to {}",
+            state.state_version,
+            STATE_VERSION
+        );
+        record_event(&Event::MigratedState {
+            state_version: STATE_VERSION,
+        });
+        state.migrate_to(STATE_VERSION);
+    }
 }
 
 pub fn post_upgrade(upgrade_args: Option<UpgradeArgs>) {
@@ -52,13 +191,15 @@ pub fn post_upgrade(upgrade_args: Option<UpgradeArgs>) {
 
     log!(P0, "[upgrade]: replaying {} events", count_events());
 
-    let state = replay(events()).unwrap_or_else(|e| {
+    let mut state = replay(events()).unwrap_or_else(|e| {
         ic_cdk::trap(&format!(
             "[upgrade]: failed to replay the event log: {:?}",
             e
         ))
     });
 
+    migrate(&mut state);
+
     state.validate_config();
 
     replace_state(state);
@@ -71,3 +212,140 @@ pub fn post_upgrade(upgrade_args: Option<UpgradeArgs>) {
         end - start
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::init::{self, BtcNetwork};
+
+    /// The init arguments of a minter released before the configuration fields were added.
+    #[derive(Serialize)]
+    struct OldInitArgs {
+        btc_network: BtcNetwork,
+        ecdsa_key_name: String,
+        retrieve_btc_min_amount: u64,
+        ledger_id: CanisterId,
+        susd_id: CanisterId,
+        xrc_id: CanisterId,
+        siwb_id: CanisterId,
+        max_time_in_queue_nanos: u64,
+    }
+
+    #[derive(Serialize)]
+    enum OldEvent {
+        #[serde(rename = "init")]
+        Init(OldInitArgs),
+    }
+
+    fn decode(old_event: &OldEvent) -> Event {
+        let mut buf = vec![];
+        ciborium::ser::into_writer(old_event, &mut buf).unwrap();
+        ciborium::de::from_reader(buf.as_slice()).expect("failed to decode an old event")
+    }
+
+    #[test]
+    fn old_state_should_default_the_new_fields_and_migrate() {
+        let init_event = decode(&OldEvent::Init(OldInitArgs {
+            btc_network: BtcNetwork::Regtest,
+            ecdsa_key_name: "key".to_string(),
+            retrieve_btc_min_amount: 10_000,
+            ledger_id: CanisterId::from_u64(1),
+            susd_id: CanisterId::from_u64(2),
+            xrc_id: CanisterId::from_u64(3),
+            siwb_id: CanisterId::from_u64(4),
+            max_time_in_queue_nanos: 1_000,
+        }));
+
+        let mut state = replay(vec![init_event.clone()].into_iter()).unwrap();
+        assert_eq!(state.state_version, 0);
+        assert_eq!(state.max_utxos_per_update, init::DEFAULT_MAX_UTXOS_PER_UPDATE);
+        assert_eq!(state.debt_ceiling_susd, init::DEFAULT_DEBT_CEILING_SUSD);
+        assert_eq!(state.quote_currency, init::DEFAULT_QUOTE_CURRENCY);
+        assert_eq!(state.circuit_breaker_window, init::DEFAULT_CIRCUIT_BREAKER_WINDOW);
+        assert_eq!(state.max_pending_utxos, init::DEFAULT_MAX_PENDING_UTXOS);
+
+        state.migrate_to(STATE_VERSION);
+        assert_eq!(state.state_version, STATE_VERSION);
+
+        let migrated = replay(
+            vec![
+                init_event,
+                Event::MigratedState {
+                    state_version: STATE_VERSION,
+                },
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        assert_eq!(migrated, state);
+    }
+
+    #[test]
+    fn upgrade_should_apply_the_config_fields() {
+        let mut state = MinterState::from(init::test_init_args());
+        state.upgrade(UpgradeArgs {
+            indexer_max_response_bytes: Some(4_096),
+            max_collateral_per_account: Some(1_000_000),
+            guard_timeout: Some(123),
+            max_concurrent_requests: Some(7),
+            liquidation_bonus_bps: Some(123),
+            liquidation_grace_period: Some(456),
+            liquidation_threshold_bps: Some(12_345),
+            min_collateral_ratio_bps: Some(16_000),
+            stability_fee_bps_per_year: Some(250),
+            redemption_fee_bps: Some(30),
+            min_redeem_btc: Some(1_000),
+            max_redeem_btc: Some(2_000),
+            pin_ledger_fees: Some(true),
+            xrc_id_fallback: Some(CanisterId::from_u64(5)),
+            xrc_cycles: Some(1_000_000_001),
+            get_utxos_cycles: Some(20_000_000_001),
+            ..Default::default()
+        });
+        assert_eq!(state.indexer_max_response_bytes, 4_096);
+        assert_eq!(state.max_collateral_per_account, 1_000_000);
+        assert_eq!(state.guard_timeout, 123);
+        assert_eq!(state.max_concurrent_requests, 7);
+        assert_eq!(state.liquidation_bonus_bps, 123);
+        assert_eq!(state.liquidation_grace_period, 456);
+        assert_eq!(state.liquidation_threshold_bps, 12_345);
+        assert_eq!(state.min_collateral_ratio_bps, 16_000);
+        assert_eq!(state.stability_fee_bps_per_year, 250);
+        assert_eq!(state.redemption_fee_bps, 30);
+        assert_eq!(state.min_redeem_btc, 1_000);
+        assert_eq!(state.max_redeem_btc, 2_000);
+        assert_eq!(state.pin_ledger_fees, true);
+        assert_eq!(state.xrc_id_fallback, Some(CanisterId::from_u64(5)));
+        assert_eq!(state.xrc_cycles, 1_000_000_001);
+        assert_eq!(state.get_utxos_cycles, 20_000_000_001);
+    }
+
+    #[test]
+    fn migration_should_fill_unusable_config_values() {
+        let mut state = replay(
+            vec![decode(&OldEvent::Init(OldInitArgs {
+                btc_network: BtcNetwork::Regtest,
+                ecdsa_key_name: "key".to_string(),
+                retrieve_btc_min_amount: 10_000,
+                ledger_id: CanisterId::from_u64(1),
+                susd_id: CanisterId::from_u64(2),
+                xrc_id: CanisterId::from_u64(3),
+                siwb_id: CanisterId::from_u64(4),
+                max_time_in_queue_nanos: 1_000,
+            }))]
+            .into_iter(),
+        )
+        .unwrap();
+        state.quote_currency = String::new();
+        state.max_pending_utxos = 0;
+
+        state.migrate_to(STATE_VERSION);
+        assert_eq!(state.quote_currency, init::DEFAULT_QUOTE_CURRENCY);
+        assert_eq!(state.max_pending_utxos, init::DEFAULT_MAX_PENDING_UTXOS);
+
+        // migrations never run twice
+        state.max_pending_utxos = 0;
+        state.migrate_to(STATE_VERSION);
+        assert_eq!(state.max_pending_utxos, 0);
+    }
+}
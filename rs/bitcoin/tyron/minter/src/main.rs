@@ -1,29 +1,41 @@
-// use candid::Principal;
+use candid::Principal;
+use ic_btc_interface::{OutPoint, Utxo};
 use ic_canister_log::export as export_logs;
 use ic_canisters_http_types::{HttpRequest, HttpResponse, HttpResponseBuilder};
 use ic_cdk_macros::{init, post_upgrade, query, update};
-use ic_ckbtc_minter_tyron::dashboard::build_dashboard;
+use ic_ckbtc_minter_tyron::dashboard::{build_dashboard, build_status};
+use ic_ckbtc_minter_tyron::https::{
+    provider,
+    types::{Provider, ProviderError, ProviderSelection, RegisterProviderArgs, ServiceResult, UpdateProviderArgs},
+};
 use ic_ckbtc_minter_tyron::lifecycle::upgrade::UpgradeArgs;
 use ic_ckbtc_minter_tyron::lifecycle::{self, init::MinterArg};
-use ic_ckbtc_minter_tyron::metrics::encode_metrics;
-use ic_ckbtc_minter_tyron::queries::{EstimateFeeArg, RetrieveBtcStatusRequest, WithdrawalFee};
+use ic_ckbtc_minter_tyron::metrics::{encode_metrics, MinterMetrics};
+use ic_ckbtc_minter_tyron::queries::{
+    EstimateFeeArg, RetrieveBtcStatusRequest, SystemStats, WithdrawalFee,
+};
 use ic_ckbtc_minter_tyron::state::{
-    read_state, BtcRetrievalStatusV2, RetrieveBtcStatus, RetrieveBtcStatusV2,
+    audit, mutate_state, read_state, BtcRetrievalStatusV2, Mode, RetrieveBtcStatus,
+    RetrieveBtcStatusV2,
 };
+use ic_ckbtc_minter_tyron::snapshot::{self, SnapshotBlob, SnapshotError};
 use ic_ckbtc_minter_tyron::tasks::{schedule_now, TaskType};
-use ic_ckbtc_minter_tyron::updates::get_withdrawal_account::compute_subaccount;
+use ic_ckbtc_minter_tyron::updates::get_withdrawal_account::{compute_ssi_subaccount, minter_account_with_nonce, SubaccountKind};
 // use ic_ckbtc_minter_tyron::updates::retrieve_btc::{
 //     RetrieveBtcArgs, RetrieveBtcError, RetrieveBtcOk, RetrieveBtcWithApprovalArgs, RetrieveBtcWithApprovalError
 // };
 use ic_ckbtc_minter_tyron::updates::{
     self,
-    get_btc_address::GetBoxAddressArgs,
-    update_balance::{UpdateBalanceError, UtxoStatus},
+    get_btc_address::{DepositAddresses, GetBoxAddressArgs, SsiAccounts},
+    update_balance::{
+        CloseLoanResult, LiquidationPreview, PendingUtxo, ReconcileResult, RepayLoanResult, UpdateBalanceError, UtxoStatus,
+    },
 };
-use ic_ckbtc_minter_tyron::MinterInfo;
+use ic_ckbtc_minter_tyron::{MinterConfig, MinterInfo};
 use ic_ckbtc_minter_tyron::{
     state::eventlog::{Event, GetEventsArg},
-    storage, {Log, LogEntry, Priority},
+    storage::{self, GetBlocksResult, ProtocolEvent},
+    {Log, LogEntry, Priority},
 };
 use icrc_ledger_types::icrc1::account::{Account, Subaccount};
 
@@ -31,11 +43,14 @@ use icrc_ledger_types::icrc1::account::{Account, Subaccount};
 fn init(args: MinterArg) {
     match args {
         MinterArg::Init(args) => {
+            // @dev the installing controller administers the providers from the start
+            provider::authorize(ic_cdk::caller());
             storage::record_event(&Event::Init(args.clone()));
             lifecycle::init::init(args);
             schedule_now(TaskType::ProcessLogic);
             schedule_now(TaskType::RefreshFeePercentiles);
             schedule_now(TaskType::DistributeKytFee);
+            schedule_now(TaskType::RevertStaleRunesDeposits);
 
             #[cfg(feature = "self_check")]
             ok_or_die(check_invariants())
@@ -105,6 +120,12 @@ fn check_postcondition<T>(t: T) -> T {
 //     }
 // }
 
+fn check_controller() {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap("only controllers can call this method")
+    }
+}
+
 #[export_name = "canister_global_timer"]
 fn timer() {
     #[cfg(feature = "self_check")]
@@ -126,6 +147,7 @@ fn post_upgrade(minter_arg: Option<MinterArg>) {
     schedule_now(TaskType::ProcessLogic);
     schedule_now(TaskType::RefreshFeePercentiles);
     schedule_now(TaskType::DistributeKytFee);
+    schedule_now(TaskType::RevertStaleRunesDeposits);
 }
 
 #[update]
@@ -134,6 +156,16 @@ async fn get_box_address(args: GetBoxAddressArgs) -> String {
     updates::get_btc_address::get_box_address(args).await
 }
 
+#[query]
+fn get_deposit_addresses(ssi: String) -> DepositAddresses {
+    updates::get_btc_address::get_deposit_addresses(ssi)
+}
+
+#[update]
+async fn get_ssi_accounts(ssi: String) -> SsiAccounts {
+    updates::get_btc_address::get_ssi_accounts(ssi).await
+}
+
 #[update]
 async fn get_withdrawal_account() -> Account {
     // check_anonymous_caller();
@@ -172,9 +204,66 @@ fn retrieve_btc_status_v2_by_account(target: Option<Account>) -> Vec<BtcRetrieva
 #[update]
 async fn update_ssi_balance(args: GetBoxAddressArgs) -> Result<Vec<UtxoStatus>, UpdateBalanceError> {
     // check_anonymous_caller();
+    updates::update_balance::check_balance_update_rate_limit(ic_cdk::caller(), &args.ssi)?;
     check_postcondition(updates::update_balance::update_ssi_balance(args).await)
 }
 
+#[update]
+async fn update_ssi_balance_batch(
+    args: Vec<GetBoxAddressArgs>,
+) -> Vec<Result<Vec<UtxoStatus>, UpdateBalanceError>> {
+    check_controller();
+    check_postcondition(updates::update_balance::update_ssi_balance_batch(args).await)
+}
+
+#[update]
+async fn simulate_liquidation(ssi: String, repay_amount: u64) -> Result<LiquidationPreview, UpdateBalanceError> {
+    updates::update_balance::simulate_liquidation(ssi, repay_amount).await
+}
+
+#[update]
+async fn simulate_mint(ssi: String, satoshis: u64) -> Result<u64, UpdateBalanceError> {
+    updates::update_balance::simulate_mint(ssi, satoshis).await
+}
+
+#[update]
+async fn max_borrowable_susd(ssi: String) -> Result<u64, UpdateBalanceError> {
+    updates::update_balance::max_borrowable_susd(ssi).await
+}
+
+#[update]
+async fn repay_loan(ssi: String, amount: u64) -> Result<RepayLoanResult, UpdateBalanceError> {
+    check_postcondition(updates::update_balance::repay_loan(ssi, amount).await)
+}
+
+#[update]
+async fn close_loan(ssi: String) -> Result<CloseLoanResult, UpdateBalanceError> {
+    check_postcondition(updates::update_balance::close_loan(ssi).await)
+}
+
+#[update]
+async fn credit_runes_deposit(ssi: String, amount: u64, outpoints: Vec<OutPoint>) -> Result<u64, UpdateBalanceError> {
+    check_controller();
+    updates::update_balance::syron_runes_deposit(&ssi, amount, false, outpoints).await
+}
+
+#[update]
+async fn seed_totals(ssis: Vec<String>) -> Result<(u64, u64), UpdateBalanceError> {
+    check_controller();
+    updates::update_balance::seed_totals(ssis).await
+}
+
+#[update]
+async fn reconcile_account(ssi: String) -> Result<ReconcileResult, UpdateBalanceError> {
+    check_controller();
+    updates::update_balance::reconcile_account(ssi).await
+}
+
+#[update]
+async fn get_pending_deposits(ssi: String) -> Result<Vec<PendingUtxo>, UpdateBalanceError> {
+    updates::update_balance::get_pending_deposits(ssi).await
+}
+
 #[update]
 async fn get_canister_status() -> ic_cdk::api::management_canister::main::CanisterStatusResponse {
     ic_cdk::api::management_canister::main::canister_status(
@@ -193,7 +282,8 @@ fn estimate_withdrawal_fee(arg: EstimateFeeArg) -> WithdrawalFee {
         ic_ckbtc_minter_tyron::estimate_fee(
             &s.available_utxos,
             arg.amount,
-            s.last_fee_per_vbyte[50],
+            ic_ckbtc_minter_tyron::fee_at_percentile(&s.last_fee_per_vbyte, s.fee_percentile)
+                .unwrap_or(ic_ckbtc_minter_tyron::DEFAULT_FEE_PER_VBYTE),
             s.kyt_fee,
         )
     })
@@ -208,11 +298,692 @@ fn get_minter_info() -> MinterInfo {
     })
 }
 
+#[query]
+fn get_config() -> MinterConfig {
+    read_state(|s| MinterConfig {
+        btc_network: s.btc_network.into(),
+        min_confirmations: s.min_confirmations,
+        kyt_fee: s.kyt_fee,
+        min_btc_deposit: s.min_btc_deposit,
+        retrieve_btc_min_amount: s.retrieve_btc_min_amount,
+        ledger_id: s.ledger_id.get().into(),
+        susd_id: s.susd_id.get().into(),
+        xrc_id: s.xrc_id.get().into(),
+        siwb_id: s.siwb_id.get().into(),
+        kyt_principal: s.kyt_principal.map(|id| id.get().into()),
+        xrc_id_fallback: s.xrc_id_fallback.map(|id| id.get().into()),
+    })
+}
+
+#[query]
+fn get_metrics() -> MinterMetrics {
+    ic_ckbtc_minter_tyron::metrics::get_metrics()
+}
+
+#[query]
+fn get_in_flight() -> Vec<(Account, u64)> {
+    let now = ic_cdk::api::time();
+    read_state(|s| ic_ckbtc_minter_tyron::guard::in_flight(s, now))
+}
+
+#[query]
+fn get_fee_per_vbyte() -> Option<u64> {
+    read_state(|s| ic_ckbtc_minter_tyron::fee_at_percentile(&s.last_fee_per_vbyte, s.fee_percentile))
+}
+
 #[query]
 fn get_deposit_fee() -> u64 {
     read_state(|s| s.kyt_fee)
 }
 
+#[query]
+fn get_pending_runes(ssi: String) -> (u64, u64) {
+    let now = ic_cdk::api::time();
+    read_state(|s| {
+        s.pending_runes_deposits
+            .get(&ssi)
+            .map(|deposit| (deposit.amount, now.saturating_sub(deposit.created_at)))
+            .unwrap_or((0, 0))
+    })
+}
+
+#[query]
+fn get_last_exchange_rate() -> Option<(u64, u64)> {
+    read_state(|s| s.last_exchange_rate)
+}
+
+#[query]
+fn get_system_stats() -> SystemStats {
+    read_state(|s| SystemStats {
+        total_collateral: s.total_collateral,
+        total_debt: s.total_debt,
+        exchange_rate: s.last_exchange_rate.map(|(exchange_rate, _)| exchange_rate),
+        collateral_ratio: match s.last_exchange_rate {
+            Some((exchange_rate, _)) if s.total_debt != 0 => Some(
+                (s.total_collateral as u128 * exchange_rate as u128 * 10_000
+                    / s.total_debt as u128) as u64,
+            ),
+            _ => None,
+        },
+    })
+}
+
+#[update]
+fn update_provider(provider_id: u64, args: UpdateProviderArgs) -> ServiceResult<()> {
+    provider::update_provider(provider_id, args)
+}
+
+#[query]
+fn get_providers() -> Vec<Provider> {
+    provider::get_providers()
+}
+
+#[query]
+fn get_provider(provider_id: u64) -> Option<Provider> {
+    provider::get_provider(provider_id)
+}
+
+#[update]
+async fn withdraw_owed_cycles(provider_id: u64, canister_id: Principal) -> ServiceResult<u128> {
+    provider::withdraw_owed_cycles(provider_id, canister_id).await
+}
+
+#[update]
+fn register_provider(args: RegisterProviderArgs) -> ServiceResult<u64> {
+    provider::check_authorized()?;
+    Ok(provider::register_provider(args)?)
+}
+
+#[update]
+fn set_primary_provider(provider_id: u64) -> Result<(), ProviderError> {
+    provider::check_authorized()?;
+    provider::set_primary_provider(provider_id)
+}
+
+#[update]
+fn set_provider_selection(policy: ProviderSelection) -> Result<(), ProviderError> {
+    provider::check_authorized()?;
+    provider::set_provider_selection(policy);
+    Ok(())
+}
+
+#[query]
+fn get_provider_selection() -> ProviderSelection {
+    provider::get_provider_selection()
+}
+
+#[update]
+fn set_allowed_hostnames(hostnames: Vec<String>) -> Result<(), ProviderError> {
+    provider::check_authorized()?;
+    provider::set_allowed_hostnames(hostnames);
+    Ok(())
+}
+
+#[update]
+fn add_authorized(principal: Principal) -> Result<(), ProviderError> {
+    provider::check_authorized()?;
+    provider::authorize(principal);
+    Ok(())
+}
+
+#[update]
+fn remove_authorized(principal: Principal) -> Result<(), ProviderError> {
+    provider::check_authorized()?;
+    provider::deauthorize(principal);
+    Ok(())
+}
+
+#[update]
+fn export_snapshot() -> SnapshotBlob {
+    check_controller();
+    snapshot::export_snapshot()
+}
+
+#[update]
+fn import_snapshot(blob: SnapshotBlob) -> Result<(), SnapshotError> {
+    check_controller();
+    snapshot::import_snapshot(&blob)
+}
+
+#[query]
+fn get_authorized_principals() -> Vec<Principal> {
+    provider::get_authorized_principals()
+}
+
+#[query]
+fn get_allowed_hostnames() -> Vec<String> {
+    provider::get_allowed_hostnames()
+}
+
+#[update]
+fn set_max_utxos_per_update(max_utxos_per_update: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.max_utxos_per_update;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                max_utxos_per_update: Some(max_utxos_per_update),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_min_btc_deposit(min_deposit: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        if s.kyt_fee >= min_deposit {
+            ic_cdk::trap("min_btc_deposit must be greater than kyt_fee");
+        }
+        let previous = s.min_btc_deposit;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                min_deposit: Some(min_deposit),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_kyt_fee(kyt_fee: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        if kyt_fee >= s.min_btc_deposit {
+            ic_cdk::trap("kyt_fee must be lower than min_btc_deposit");
+        }
+        let previous = s.kyt_fee;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                kyt_fee: Some(kyt_fee),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_min_payment_susd(min_payment_susd: u64) -> u64 {
+    check_controller();
+    if min_payment_susd == 0 {
+        ic_cdk::trap("min_payment_susd cannot be zero");
+    }
+    mutate_state(|s| {
+        let previous = s.min_payment_susd;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                min_payment_susd: Some(min_payment_susd),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_min_payment_btc(min_payment_btc: u64) -> u64 {
+    check_controller();
+    if min_payment_btc == 0 {
+        ic_cdk::trap("min_payment_btc cannot be zero");
+    }
+    mutate_state(|s| {
+        let previous = s.min_payment_btc;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                min_payment_btc: Some(min_payment_btc),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn override_circuit_breaker() -> Option<u64> {
+    check_controller();
+    ic_ckbtc_minter_tyron::updates::update_balance::override_circuit_breaker()
+}
+
+#[update]
+fn invalidate_siwb_principal(ssi: String) {
+    check_controller();
+    ic_ckbtc_minter_tyron::management::invalidate_siwb_principal(&ssi);
+}
+
+#[update]
+fn set_mode(mode: Mode) -> Mode {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.mode.clone();
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                mode: Some(mode),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_debt_ceiling_susd(debt_ceiling_susd: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.debt_ceiling_susd;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                debt_ceiling_susd: Some(debt_ceiling_susd),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_fee_percentile(fee_percentile: u64) -> u64 {
+    check_controller();
+    if fee_percentile > 99 {
+        ic_cdk::trap("fee_percentile cannot be greater than 99");
+    }
+    mutate_state(|s| {
+        let previous = s.fee_percentile;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                fee_percentile: Some(fee_percentile),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_quote_currency(quote_currency: String) -> String {
+    check_controller();
+    if !ic_ckbtc_minter_tyron::management::SUPPORTED_QUOTE_CURRENCIES.contains(&quote_currency.as_str()) {
+        ic_cdk::trap("quote_currency must be one of USD, EUR or GBP");
+    }
+    mutate_state(|s| {
+        let previous = s.quote_currency.clone();
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                quote_currency: Some(quote_currency),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_rate_providers(rate_providers: Vec<u64>) -> Vec<u64> {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.rate_providers.clone();
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                rate_providers: Some(rate_providers),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_rate_max_deviation_bps(rate_max_deviation_bps: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.rate_max_deviation_bps;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                rate_max_deviation_bps: Some(rate_max_deviation_bps),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_circuit_breaker_bps(circuit_breaker_bps: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.circuit_breaker_bps;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                circuit_breaker_bps: Some(circuit_breaker_bps),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_circuit_breaker_window(circuit_breaker_window: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.circuit_breaker_window;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                circuit_breaker_window: Some(circuit_breaker_window),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_update_balance_interval(update_balance_interval: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.update_balance_interval;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                update_balance_interval: Some(update_balance_interval),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_max_pending_utxos(max_pending_utxos: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.max_pending_utxos;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                max_pending_utxos: Some(max_pending_utxos),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_indexer_max_response_bytes(indexer_max_response_bytes: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.indexer_max_response_bytes;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                indexer_max_response_bytes: Some(indexer_max_response_bytes),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_max_collateral_per_account(max_collateral_per_account: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.max_collateral_per_account;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                max_collateral_per_account: Some(max_collateral_per_account),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_guard_timeout(guard_timeout: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.guard_timeout;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                guard_timeout: Some(guard_timeout),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_max_concurrent_requests(max_concurrent_requests: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.max_concurrent_requests;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                max_concurrent_requests: Some(max_concurrent_requests),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_liquidation_bonus_bps(liquidation_bonus_bps: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.liquidation_bonus_bps;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                liquidation_bonus_bps: Some(liquidation_bonus_bps),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_liquidation_grace_period(liquidation_grace_period: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.liquidation_grace_period;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                liquidation_grace_period: Some(liquidation_grace_period),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_liquidation_threshold_bps(liquidation_threshold_bps: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.liquidation_threshold_bps;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                liquidation_threshold_bps: Some(liquidation_threshold_bps),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_stability_fee_bps_per_year(stability_fee_bps_per_year: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.stability_fee_bps_per_year;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                stability_fee_bps_per_year: Some(stability_fee_bps_per_year),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_redemption_fee_bps(redemption_fee_bps: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.redemption_fee_bps;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                redemption_fee_bps: Some(redemption_fee_bps),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_min_redeem_btc(min_redeem_btc: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.min_redeem_btc;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                min_redeem_btc: Some(min_redeem_btc),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_max_redeem_btc(max_redeem_btc: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.max_redeem_btc;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                max_redeem_btc: Some(max_redeem_btc),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_pin_ledger_fees(pin_ledger_fees: bool) -> bool {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.pin_ledger_fees;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                pin_ledger_fees: Some(pin_ledger_fees),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_xrc_cycles(xrc_cycles: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.xrc_cycles;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                xrc_cycles: Some(xrc_cycles),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_get_utxos_cycles(get_utxos_cycles: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.get_utxos_cycles;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                get_utxos_cycles: Some(get_utxos_cycles),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_min_collateral_ratio_bps(min_collateral_ratio_bps: u64) -> u64 {
+    check_controller();
+    mutate_state(|s| {
+        let previous = s.min_collateral_ratio_bps;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                min_collateral_ratio_bps: Some(min_collateral_ratio_bps),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
+#[update]
+fn set_pending_runes_ttl(pending_runes_ttl: u64) -> u64 {
+    check_controller();
+    if pending_runes_ttl == 0 {
+        ic_cdk::trap("pending_runes_ttl cannot be zero");
+    }
+    mutate_state(|s| {
+        let previous = s.pending_runes_ttl;
+        audit::update_config(
+            s,
+            UpgradeArgs {
+                pending_runes_ttl: Some(pending_runes_ttl),
+                ..Default::default()
+            },
+        );
+        previous
+    })
+}
+
 #[query(hidden = true)]
 fn http_request(req: HttpRequest) -> HttpResponse {
     if ic_cdk::api::data_certificate().is_none() {
@@ -239,6 +1010,11 @@ fn http_request(req: HttpRequest) -> HttpResponse {
             .header("Content-Type", "text/html; charset=utf-8")
             .with_body_and_content_length(dashboard)
             .build()
+    } else if req.path() == "/status" {
+        HttpResponseBuilder::ok()
+            .header("Content-Type", "application/json; charset=utf-8")
+            .with_body_and_content_length(build_status())
+            .build()
     } else if req.path() == "/logs" {
         use serde_json;
         use std::str::FromStr;
@@ -298,6 +1074,20 @@ fn get_events(args: GetEventsArg) -> Vec<Event> {
         .collect()
 }
 
+#[query]
+fn get_protocol_events(args: GetEventsArg) -> (Vec<ProtocolEvent>, u64) {
+    const MAX_PROTOCOL_EVENTS_PER_QUERY: u64 = 2000;
+
+    storage::protocol_events(args.start, MAX_PROTOCOL_EVENTS_PER_QUERY.min(args.length))
+}
+
+#[query]
+fn get_blocks(args: GetEventsArg) -> GetBlocksResult {
+    const MAX_BLOCKS_PER_QUERY: u64 = 2000;
+
+    storage::blocks(args.start, MAX_BLOCKS_PER_QUERY.min(args.length))
+}
+
 #[cfg(feature = "self_check")]
 #[query]
 fn self_check() -> Result<(), String> {
@@ -309,9 +1099,15 @@ fn __get_candid_interface_tmp_hack() -> &'static str {
     include_str!(env!("MINTER_DID_PATH"))
 }
 
+#[query]
+fn get_known_utxos(ssi: String, nonce: u64) -> Vec<Utxo> {
+    let account = minter_account_with_nonce(nonce, &ssi);
+    read_state(|s| s.known_utxos_for_account(&account))
+}
+
 #[update]
 async fn get_subaccount(ssi: String) -> Subaccount {
-    compute_subaccount(1, &ssi)
+    compute_ssi_subaccount(SubaccountKind::Box, &ssi)
 }
 
 fn main() {}
@@ -3,7 +3,7 @@
 use crate::logs::P0;
 use crate::tx;
 use crate::ECDSAPublicKey;
-use crate::state::read_state;
+use crate::state::{mutate_state, read_state};
 use candid::{CandidType, Principal};
 use ic_btc_interface::{
     Address, GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse,
@@ -17,6 +17,7 @@ use ic_management_canister_types::{
     SignWithECDSAArgs, SignWithECDSAReply,
 };
 use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
 use std::fmt;
 use ic_xrc_types::{Asset, AssetClass, GetExchangeRateRequest, GetExchangeRateResult, ExchangeRateError};
 use serde_bytes::ByteBuf;
@@ -51,6 +52,9 @@ impl fmt::Display for CallError {
     }
 }
 
+// @dev the failure reasons only carry messages, so there is no underlying error to expose as a source
+impl std::error::Error for CallError {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// The reason for the management call failure.
 pub enum Reason {
@@ -64,6 +68,8 @@ pub enum Reason {
     /// The management canister rejected the signature request (not enough
     /// cycles, the ECDSA subnet is overloaded, etc.).
     Rejected(String),
+    /// The caller is not the principal linked to the SSI by the SIWB canister.
+    Unauthorized,
     Other(String)
 }
 
@@ -76,6 +82,9 @@ impl fmt::Display for Reason {
             Self::Rejected(msg) => {
                 write!(fmt, "the management canister rejected the call: {}", msg)
             },
+            Self::Unauthorized => {
+                write!(fmt, "the caller is not linked to the given Bitcoin address")
+            },
             Self::Other(msg) => {
                 write!(fmt, " call rejected - reason: {}", msg)
             }
@@ -93,20 +102,6 @@ impl Reason {
             _ => Self::Other(reject_message),
         }
     }
-
-    fn to_string(&self) -> String {
-        match self {
-            Self::QueueIsFull => "the canister queue is full".to_string(),
-            Self::OutOfCycles => "the canister is out of cycles".to_string(),
-            Self::CanisterError(msg) => format!("canister error: {}", msg),
-            Self::Rejected(msg) => {
-                format!("the management canister rejected the call: {}", msg)
-            },
-            Self::Other(msg) => {
-                format!(" call rejected - reason: {}", msg)
-            }
-        }
-    }
 }
 
 async fn call<I, O>(method: &str, payment: u64, input: &I) -> Result<O, CallError>
@@ -155,6 +150,17 @@ pub enum CallSource {
     Minter,
 }
 
+/// Returns the default cycles attached to a bitcoin_get_utxos call on the given network.
+pub fn get_utxos_cost_cycles(network: Network) -> u64 {
+    // NB. The minimum number of cycles that need to be sent with the call is 10B (4B) for
+    // Bitcoin mainnet (Bitcoin testnet):
+    // https://internetcomputer.org/docs/current/developer-docs/integrations/bitcoin/bitcoin-how-it-works#api-fees--pricing
+    match network {
+        Network::Mainnet => 10_000_000_000,
+        Network::Testnet | Network::Regtest => 4_000_000_000,
+    }
+}
+
 /// Fetches the full list of UTXOs for the specified address.
 pub async fn get_utxos(
     network: Network,
@@ -162,13 +168,7 @@ pub async fn get_utxos(
     min_confirmations: u32,
     source: CallSource,
 ) -> Result<GetUtxosResponse, CallError> {
-    // NB. The minimum number of cycles that need to be sent with the call is 10B (4B) for
-    // Bitcoin mainnet (Bitcoin testnet):
-    // https://internetcomputer.org/docs/current/developer-docs/integrations/bitcoin/bitcoin-how-it-works#api-fees--pricing
-    let get_utxos_cost_cycles = match network {
-        Network::Mainnet => 10_000_000_000,
-        Network::Testnet | Network::Regtest => 4_000_000_000,
-    };
+    let get_utxos_cost_cycles = read_state(|s| s.get_utxos_cycles);
 
     // Calls "bitcoin_get_utxos" method with the specified argument on the
     // management canister.
@@ -182,7 +182,18 @@ pub async fn get_utxos(
             CallSource::Minter => &crate::metrics::GET_UTXOS_MINTER_CALLS,
         }
         .with(|cell| cell.set(cell.get() + 1));
-        call("bitcoin_get_utxos", cycles, req).await
+        call("bitcoin_get_utxos", cycles, req).await.inspect_err(|e| {
+            if let Reason::Rejected(msg) = e.reason() {
+                if msg.contains("cycles") {
+                    log!(
+                        P0,
+                        "[get_utxos]: the call was rejected with {} cycles attached, see set_get_utxos_cycles: {}",
+                        cycles,
+                        msg
+                    );
+                }
+            }
+        })
     }
 
     let mut response = bitcoin_get_utxos(
@@ -199,6 +210,8 @@ pub async fn get_utxos(
     let mut utxos = std::mem::take(&mut response.utxos);
 
     // Continue fetching until there are no more pages.
+    // NB. The pages cannot be fetched concurrently: `next_page` is an opaque cursor
+    // that the Bitcoin canister only returns with the previous page.
     while let Some(page) = response.next_page {
         response = bitcoin_get_utxos(
             &GetUtxosRequest {
@@ -366,7 +379,66 @@ pub async fn fetch_withdrawal_alerts(
     Ok(res)
 }
 
-pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<GetExchangeRateResult, CallError> {
+/// The fiat currencies the BTC collateral can be priced in.
+pub const SUPPORTED_QUOTE_CURRENCIES: [&str; 3] = ["USD", "EUR", "GBP"];
+
+/// The Exchange Rate Canister that produced an exchange rate.
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum XrcSource {
+    /// The Exchange Rate Canister configured as `xrc_id`.
+    Primary,
+    /// The Exchange Rate Canister configured as `xrc_id_fallback`.
+    Fallback,
+}
+
+/// Returns whether a failed call to the Exchange Rate Canister is worth retrying against
+/// another one, i.e., whether it does not stem from the request or from the minter itself.
+fn is_retryable_xrc_result(res: &Result<GetExchangeRateResult, CallError>) -> bool {
+    match res {
+        Ok(Ok(_)) => false,
+        Ok(Err(e)) => matches!(
+            e,
+            ExchangeRateError::Pending
+                | ExchangeRateError::RateLimited
+                | ExchangeRateError::InconsistentRatesReceived
+                | ExchangeRateError::CryptoBaseAssetNotFound
+                | ExchangeRateError::CryptoQuoteAssetNotFound
+                | ExchangeRateError::ForexAssetsNotFound
+                | ExchangeRateError::Other(_)
+        ),
+        Err(e) => !matches!(e.reason(), Reason::OutOfCycles),
+    }
+}
+
+/// Fetches the BTC exchange rate from the Exchange Rate Canister, retrying against the fallback
+/// one (if configured) when the primary fails with a retryable error or times out.
+pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<(GetExchangeRateResult, XrcSource), CallError> {
+    if !SUPPORTED_QUOTE_CURRENCIES.contains(&symbol.as_str()) {
+        return Err(CallError {
+            method: "get_exchange_rate".to_string(),
+            reason: Reason::Other(format!("unsupported quote currency {}", symbol)),
+        });
+    }
+
+    let (primary, fallback) = read_state(|s| (s.xrc_id.get().into(), s.xrc_id_fallback.map(|id| id.get().into())));
+    let res = call_xrc(primary, symbol.clone()).await;
+    match fallback {
+        Some(fallback) if is_retryable_xrc_result(&res) => {
+            log!(
+                P0,
+                "[fetch_btc_exchange_rate]: retrying against the fallback XRC {} after {:?}",
+                fallback,
+                res
+            );
+            call_xrc(fallback, symbol).await.map(|rate| (rate, XrcSource::Fallback))
+        }
+        _ => res.map(|rate| (rate, XrcSource::Primary)),
+    }
+}
+
+async fn call_xrc(xrc_id: Principal, symbol: String) -> Result<GetExchangeRateResult, CallError> {
+    let method = "get_exchange_rate";
+
     let btc = Asset {
         symbol: "BTC".to_string(),
         class: AssetClass::Cryptocurrency,
@@ -382,7 +454,6 @@ pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<GetExchangeRateRe
         timestamp: None,
     };
 
-    let method = "get_exchange_rate";
     // let (res,): (GetExchangeRateResult,) = ic_cdk::api::call::call(
     //     read_state(|s| s.xrc_id.get().into()),
     //     method,
@@ -395,7 +466,7 @@ pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<GetExchangeRateRe
     //     })?;
     // Ok(res);
 
-    let payment = 10_000_000_000;
+    let payment = read_state(|s| s.xrc_cycles);
 
     let balance = ic_cdk::api::canister_balance128();
     
@@ -415,7 +486,7 @@ pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<GetExchangeRateRe
     }
 
     let res: Result<(GetExchangeRateResult,), _> = ic_cdk::api::call::call_with_payment(
-        read_state(|s| s.xrc_id.get().into()),
+        xrc_id,
         method,
         (request,),
         payment,
@@ -423,6 +494,15 @@ pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<GetExchangeRateRe
     .await;
 
     match res {
+        Ok((Err(ExchangeRateError::NotEnoughCycles),)) => {
+            log!(
+                P0,
+                "[call_xrc]: the XRC {} requires more than the {} cycles attached, see set_xrc_cycles",
+                xrc_id,
+                payment
+            );
+            Ok(Err(ExchangeRateError::NotEnoughCycles))
+        }
         Ok((output,)) => Ok(output),
         Err((code, msg)) => Err(CallError {
             method: method.to_string(),
@@ -431,9 +511,89 @@ pub async fn fetch_btc_exchange_rate(symbol: String) -> Result<GetExchangeRateRe
     }
 }
 
+/// The time (in nanoseconds) after which a cached SIWB principal is fetched again.
+const SIWB_PRINCIPAL_TTL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+/// The maximum number of cached SIWB principals.
+const MAX_CACHED_SIWB_PRINCIPALS: usize = 10_000;
+
+/// Returns the principal linked to the given SSI by the SIWB canister, which must be the caller.
+///
+/// The resolved principals are cached in the state for [SIWB_PRINCIPAL_TTL_NANOS], but the
+/// caller is validated on every call.
 pub(crate) async fn get_siwb_principal(ssi: &str) -> Result<Principal, CallError> {
     let caller = ic_cdk::caller();
+    let now = ic_cdk::api::time();
+
+    if read_state(|s| cached_siwb_principal(&s.siwb_principals, ssi, now, SIWB_PRINCIPAL_TTL_NANOS)) == Some(caller) {
+        return Ok(caller);
+    }
+
+    // @dev the cached principal is either missing, expired or stale
+    invalidate_siwb_principal(ssi);
+    let principal = fetch_siwb_principal(ssi).await?;
+    mutate_state(|s| {
+        cache_siwb_principal(
+            &mut s.siwb_principals,
+            ssi.to_string(),
+            principal,
+            now,
+            SIWB_PRINCIPAL_TTL_NANOS,
+            MAX_CACHED_SIWB_PRINCIPALS,
+        )
+    });
+
+    // @dev the principal must be equal to the caller or throw an error
+    ensure_siwb_caller(principal, caller)
+}
+
+/// Returns the SIWB principal of an SSI if it is the caller, or an Unauthorized error.
+fn ensure_siwb_caller(principal: Principal, caller: Principal) -> Result<Principal, CallError> {
+    if principal == caller {
+        Ok(principal)
+    } else {
+        Err(CallError {
+            method: "get_principal".to_string(),
+            reason: Reason::Unauthorized,
+        })
+    }
+}
+
+/// Returns the cached SIWB principal of the SSI if it was fetched less than `ttl` ago.
+fn cached_siwb_principal(cache: &BTreeMap<String, (Principal, u64)>, ssi: &str, now: u64, ttl: u64) -> Option<Principal> {
+    cache
+        .get(ssi)
+        .filter(|(_, fetched_at)| now.saturating_sub(*fetched_at) < ttl)
+        .map(|(principal, _)| *principal)
+}
+
+/// Caches the SIWB principal of the SSI. A full cache first drops its expired entries, then
+/// the oldest one.
+fn cache_siwb_principal(
+    cache: &mut BTreeMap<String, (Principal, u64)>,
+    ssi: String,
+    principal: Principal,
+    now: u64,
+    ttl: u64,
+    max_entries: usize,
+) {
+    if cache.len() >= max_entries && !cache.contains_key(&ssi) {
+        cache.retain(|_, (_, fetched_at)| now.saturating_sub(*fetched_at) < ttl);
+        if cache.len() >= max_entries {
+            if let Some(oldest) = cache.iter().min_by_key(|(_, (_, fetched_at))| *fetched_at).map(|(ssi, _)| ssi.clone()) {
+                cache.remove(&oldest);
+            }
+        }
+    }
+    cache.insert(ssi, (principal, now));
+}
+
+/// Removes the cached SIWB principal of the given SSI.
+pub fn invalidate_siwb_principal(ssi: &str) {
+    mutate_state(|s| s.siwb_principals.remove(ssi));
+}
 
+async fn fetch_siwb_principal(ssi: &str) -> Result<Principal, CallError> {
     let method = "get_principal";
 
     let res: Result<(Result<ByteBuf, String>,), _> = ic_cdk::api::call::call(
@@ -448,17 +608,7 @@ pub(crate) async fn get_siwb_principal(ssi: &str) -> Result<Principal, CallError
             match output {
                 Ok(byte_buf) => {
                     match Principal::try_from_slice(&byte_buf) {
-                        Ok(principal) => {
-                            // @dev the principal must be equal to the caller or throw an error
-                            if principal == caller {
-                                Ok(principal)
-                            } else {
-                                Err(CallError {
-                                    method: method.to_string(),
-                                    reason: Reason::Other("Invalid caller".to_string()),
-                                })
-                            }
-                        }
+                        Ok(principal) => Ok(principal),
                         Err(e) => {
                             let err = format!("Failed to decode principal with error: {:?}", e); 
                             ic_cdk::println!("{}", &err);
@@ -485,3 +635,53 @@ pub(crate) async fn get_siwb_principal(ssi: &str) -> Result<Principal, CallError
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xrc_failures_should_be_retried_unless_caused_by_the_minter() {
+        let call_error = |reason| Err(CallError { method: "get_exchange_rate".to_string(), reason });
+
+        assert!(is_retryable_xrc_result(&Ok(Err(ExchangeRateError::RateLimited))));
+        assert!(is_retryable_xrc_result(&Ok(Err(ExchangeRateError::Pending))));
+        assert!(is_retryable_xrc_result(&call_error(Reason::Rejected("timeout".to_string()))));
+        assert!(!is_retryable_xrc_result(&call_error(Reason::OutOfCycles)));
+        assert!(!is_retryable_xrc_result(&Ok(Err(ExchangeRateError::NotEnoughCycles))));
+    }
+
+    #[test]
+    fn siwb_principal_should_only_authorize_the_owner() {
+        let owner = Principal::from_slice(&[1]);
+        let other = Principal::from_slice(&[2]);
+
+        assert_eq!(ensure_siwb_caller(owner, owner), Ok(owner));
+        assert_eq!(
+            ensure_siwb_caller(owner, other),
+            Err(CallError {
+                method: "get_principal".to_string(),
+                reason: Reason::Unauthorized,
+            })
+        );
+    }
+
+    #[test]
+    fn siwb_principal_cache_should_expire_and_stay_bounded() {
+        let owner = Principal::from_slice(&[1]);
+        let mut cache = BTreeMap::new();
+
+        cache_siwb_principal(&mut cache, "a".to_string(), owner, 1_000, 100, 2);
+        assert_eq!(cached_siwb_principal(&cache, "a", 1_099, 100), Some(owner));
+        assert_eq!(cached_siwb_principal(&cache, "a", 1_100, 100), None);
+
+        cache_siwb_principal(&mut cache, "b".to_string(), owner, 1_050, 100, 2);
+        // @dev the oldest entry is evicted when none has expired
+        cache_siwb_principal(&mut cache, "c".to_string(), owner, 1_060, 100, 2);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), vec!["b", "c"]);
+
+        // @dev the expired entries are dropped first
+        cache_siwb_principal(&mut cache, "d".to_string(), owner, 1_155, 100, 2);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), vec!["c", "d"]);
+    }
+}
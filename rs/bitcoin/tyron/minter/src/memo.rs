@@ -1,6 +1,16 @@
 use minicbor::Encoder;
 use minicbor::{Decode, Encode};
 
+/// The maximum size of a memo accepted by the Syron ledgers.
+pub const MAX_MEMO_SIZE: usize = 32;
+
+/// The number of leading transaction ID bytes kept in a [MintMemo::ConvertCompact] memo.
+pub const TXID_PREFIX_LEN: usize = 16;
+
+/// The number of leading transaction ID bytes kept in a [MintMemo::Convert] memo, the longest
+/// prefix that keeps the memo within [MAX_MEMO_SIZE] for any output index.
+pub const CONVERT_TXID_PREFIX_LEN: usize = 22;
+
 /// Encodes minter memo as a binary blob.
 pub fn encode<T: minicbor::Encode<()>>(t: &T) -> Vec<u8> {
     let mut encoder = Encoder::new(Vec::new());
@@ -52,6 +62,38 @@ pub enum MintMemo<'a> {
         #[n(2)]
         associated_burn_index: Option<u64>,
     },
+    #[n(3)]
+    /// The minter converted a single UTXO, with a memo that fits the ledger memo limit.
+    /// Superseded by [MintMemo::convert], kept to decode the memos of earlier mints.
+    ConvertCompact {
+        #[cbor(n(0), with = "minicbor::bytes")]
+        /// The first [TXID_PREFIX_LEN] bytes of the transaction ID of the accepted UTXO.
+        txid_prefix: Option<&'a [u8]>,
+        #[n(1)]
+        /// UTXO's output index within the BTC transaction.
+        vout: Option<u32>,
+    },
+}
+
+impl<'a> MintMemo<'a> {
+    /// Returns a memo in the ckBTC `Convert` layout for the UTXO with the given transaction ID
+    /// and output index, so that ckBTC-aware indexers can decode it. The transaction ID is
+    /// truncated to [CONVERT_TXID_PREFIX_LEN] bytes to fit the ledger memo limit.
+    pub fn convert(txid: &'a [u8], vout: u32) -> Self {
+        MintMemo::Convert {
+            txid: Some(&txid[..CONVERT_TXID_PREFIX_LEN.min(txid.len())]),
+            vout: Some(vout),
+            kyt_fee: None,
+        }
+    }
+
+    /// Returns a compact memo for the UTXO with the given transaction ID and output index.
+    pub fn compact(txid: &'a [u8], vout: u32) -> Self {
+        MintMemo::ConvertCompact {
+            txid_prefix: Some(&txid[..TXID_PREFIX_LEN.min(txid.len())]),
+            vout: Some(vout),
+        }
+    }
 }
 
 #[derive(Decode, Encode, Debug, Eq, PartialEq)]
@@ -70,3 +112,55 @@ pub enum BurnMemo<'a> {
         status: Option<Status>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_mint_memo_should_fit_the_ledger_limit() {
+        for (txid, vout) in [([0; 32], 0), ([0xff; 32], 1), ([0xab; 32], u32::MAX)] {
+            let memo = MintMemo::convert(&txid, vout);
+            assert!(encode(&memo).len() <= MAX_MEMO_SIZE);
+        }
+    }
+
+    #[test]
+    fn convert_mint_memo_should_round_trip_in_the_ckbtc_layout() {
+        let txid = [9; 32];
+        let encoded = encode(&MintMemo::convert(&txid, 5));
+        let decoded: MintMemo = minicbor::decode(&encoded).unwrap();
+        assert_eq!(
+            decoded,
+            MintMemo::Convert {
+                txid: Some(&txid[..CONVERT_TXID_PREFIX_LEN]),
+                vout: Some(5),
+                kyt_fee: None,
+            }
+        );
+        // The ckBTC minter encodes its conversions as variant 0.
+        assert_eq!(&encoded[..2], &[0x82, 0x00]);
+    }
+
+    #[test]
+    fn compact_mint_memo_should_fit_the_ledger_limit() {
+        for (txid, vout) in [([0; 32], 0), ([0xff; 32], 1), ([0xab; 32], u32::MAX)] {
+            let memo = MintMemo::compact(&txid, vout);
+            assert!(encode(&memo).len() <= MAX_MEMO_SIZE);
+        }
+    }
+
+    #[test]
+    fn compact_mint_memo_should_round_trip() {
+        let txid = [7; 32];
+        let encoded = encode(&MintMemo::compact(&txid, 3));
+        let decoded: MintMemo = minicbor::decode(&encoded).unwrap();
+        assert_eq!(
+            decoded,
+            MintMemo::ConvertCompact {
+                txid_prefix: Some(&txid[..TXID_PREFIX_LEN]),
+                vout: Some(3),
+            }
+        );
+    }
+}
@@ -1,9 +1,56 @@
 use crate::state;
+use candid::{CandidType, Deserialize};
 use std::cell::Cell;
 
 thread_local! {
     pub static GET_UTXOS_CLIENT_CALLS: Cell<u64> = Cell::default();
     pub static GET_UTXOS_MINTER_CALLS: Cell<u64> = Cell::default();
+    pub static MINTED_TOTAL: Cell<u64> = Cell::default();
+    pub static MINT_FAILURES_TOTAL: Cell<u64> = Cell::default();
+}
+
+pub fn increment(counter: &'static std::thread::LocalKey<Cell<u64>>) {
+    counter.with(|cell| cell.set(cell.get() + 1));
+}
+
+/// The counters of the minter, as returned by the [get_metrics] endpoint.
+/// The counters are reset when the canister is upgraded.
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq, serde::Serialize)]
+pub struct MinterMetrics {
+    /// The number of get_utxos calls issued on behalf of clients.
+    pub get_utxos_client_calls: u64,
+    /// The number of get_utxos calls issued by the minter itself.
+    pub get_utxos_minter_calls: u64,
+    /// The number of deposits for which the minter minted tokens.
+    pub minted_total: u64,
+    /// The number of deposits for which minting failed.
+    pub mint_failures_total: u64,
+    /// The number of balance updates in flight.
+    pub in_flight_update_balance: u64,
+    /// The number of retrievals in flight.
+    pub in_flight_retrieve_btc: u64,
+    /// The maximum number of concurrent requests per kind.
+    pub max_concurrent_requests: u64,
+}
+
+pub fn get_metrics() -> MinterMetrics {
+    let (in_flight_update_balance, in_flight_retrieve_btc, max_concurrent_requests) =
+        state::read_state(|s| {
+            (
+                (s.update_balance_accounts.len() + s.update_balance_operations.len()) as u64,
+                s.retrieve_btc_accounts.len() as u64,
+                s.max_concurrent_requests,
+            )
+        });
+    MinterMetrics {
+        get_utxos_client_calls: GET_UTXOS_CLIENT_CALLS.with(|cell| cell.get()),
+        get_utxos_minter_calls: GET_UTXOS_MINTER_CALLS.with(|cell| cell.get()),
+        minted_total: MINTED_TOTAL.with(|cell| cell.get()),
+        mint_failures_total: MINT_FAILURES_TOTAL.with(|cell| cell.get()),
+        in_flight_update_balance,
+        in_flight_retrieve_btc,
+        max_concurrent_requests,
+    }
 }
 
 pub fn encode_metrics(
@@ -154,6 +201,14 @@ pub fn encode_metrics(
             GET_UTXOS_MINTER_CALLS.with(|cell| cell.get()) as f64,
         )?;
 
+    metrics
+        .counter_vec(
+            "ckbtc_minter_deposits",
+            "Number of deposits the minter processed, labeled by outcome.",
+        )?
+        .value(&[("outcome", "minted")], MINTED_TOTAL.with(|cell| cell.get()) as f64)?
+        .value(&[("outcome", "mint_failed")], MINT_FAILURES_TOTAL.with(|cell| cell.get()) as f64)?;
+
     metrics.encode_gauge(
         "minter_btc_balance",
         state::read_state(|s| {
@@ -222,7 +277,7 @@ pub fn encode_metrics(
 
     metrics.encode_gauge(
         "ckbtc_minter_median_fee_per_vbyte",
-        state::read_state(|s| s.last_fee_per_vbyte[50]) as f64,
+        state::read_state(|s| s.last_fee_per_vbyte.get(50).copied().unwrap_or_default()) as f64,
         "Median Bitcoin transaction fee per vbyte in Satoshi.",
     )?;
 
@@ -16,3 +16,15 @@ pub struct WithdrawalFee {
     pub minter_fee: u64,
     pub bitcoin_fee: u64,
 }
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SystemStats {
+    /// The total amount of BTC (in Satoshi) locked as collateral.
+    pub total_collateral: u64,
+    /// The total amount of SUSD lent against the collateral.
+    pub total_debt: u64,
+    /// The last known BTC/USD exchange rate, if any.
+    pub exchange_rate: Option<u64>,
+    /// The global collateral ratio in basis points, if there is any debt and a known exchange rate.
+    pub collateral_ratio: Option<u64>,
+}
@@ -1,33 +1,54 @@
 use crate::guard::balance_update_guard;
 use crate::state;
 use crate::management;
-use crate::updates::get_withdrawal_account::compute_subaccount;
+use crate::updates::get_withdrawal_account::{minter_account, SubaccountKind};
 use crate::updates::UpdateBalanceError;
-use crate::https::outcall::call_indexer_runes_balance;
+use crate::https::outcall::call_indexer_runes_balance_consensus;
+use crate::https::provider::get_chain_provider_ids;
 use crate::Utxo;
 use icrc_ledger_types::icrc1::account::Account;
-use serde_json::Value;
+
+/// The maximum number of indexer outcalls in flight while checking the runes minter UTXOs.
+const MAX_CONCURRENT_RUNES_OUTCALLS: usize = 10;
+
+/// The chain of the providers that index the runes of the runes minter (Bitcoin mainnet).
+const RUNES_INDEXER_CHAIN_ID: u64 = 0;
 
 /// Update runes minter balance
 pub async fn check_runes_minter_utxos() -> Result<(Vec<Utxo>, Vec<Utxo>), UpdateBalanceError> {
     // @dev get minter utxos
     let (runes_minter, network, min_confirmations) = state::read_state(|s: &state::MinterState| (s.dao_addr[2].display(s.btc_network), s.btc_network, s.min_confirmations));
     let utxos_response = management::get_utxos(network, &runes_minter, min_confirmations, management::CallSource::Client).await?;
-    let mut minter_utxos: Vec<Utxo> = utxos_response.utxos;
+    let minter_utxos: Vec<Utxo> = utxos_response.utxos;
+
+    // @dev send the transaction ids to the indexer concurrently, in bounded batches to limit the cycles in flight
 
-    // @dev iterate over the utxos and send each transaction id to the outcall
+    let max_response_bytes = state::read_state(|s| s.indexer_max_response_bytes);
+
+    // @dev a majority of the indexers of the chain must agree on each runes amount
+    let providers = get_chain_provider_ids(RUNES_INDEXER_CHAIN_ID);
+    let threshold = providers.len() / 2 + 1;
 
     let mut utxos1: Vec<Utxo> = Vec::new();
     let mut utxos2: Vec<Utxo> = Vec::new();
     
-    for utxo in &mut minter_utxos {
-        let amount_u64 = call_indexer_runes_balance(utxo.clone(), 72_000_000, 0).await?; // @dev review (alpha) cycles_cost and provider
+    for batch in minter_utxos.chunks(MAX_CONCURRENT_RUNES_OUTCALLS) {
+        // @dev every outcall of the batch completes before a failure is reported
+        let amounts = futures::future::join_all(
+            batch.iter().map(|utxo| {
+                call_indexer_runes_balance_consensus(utxo.clone(), 72_000_000, providers.clone(), threshold, max_response_bytes) // @dev review (alpha) cycles_cost
+            }),
+        )
+        .await;
+
+        for (utxo, amount) in batch.iter().zip(amounts) {
+            let amount_u64 = amount?;
 
-        if amount_u64 == 0 {
-            utxos1.push(utxo.clone());
-        } else {
-            utxo.value = amount_u64;
-            utxos2.push(utxo.clone());
+            if amount_u64 == 0 {
+                utxos1.push(utxo.clone());
+            } else {
+                utxos2.push(Utxo { value: amount_u64, ..utxo.clone() });
+            }
         }
     }
 
@@ -37,10 +58,7 @@ pub async fn check_runes_minter_utxos() -> Result<(Vec<Utxo>, Vec<Utxo>), Update
 pub async fn is_new_runes_minter_utxos() -> Result<Vec<Utxo>, UpdateBalanceError> {
     // @dev only check runes minter utxos if there are unregistered utxos to process
     let (treasury_addr, runes_minter, network, min_confirmations) = state::read_state(|s: &state::MinterState| (s.dao_addr[1].display(s.btc_network), s.dao_addr[2].display(s.btc_network), s.btc_network, s.min_confirmations));
-    let runes_minter_account = Account {
-        owner: ic_cdk::id(),
-        subaccount: Some(compute_subaccount(1, &treasury_addr)),
-    };
+    let runes_minter_account = minter_account(SubaccountKind::Box, &treasury_addr);
     
     state::read_state(|s| s.mode.is_deposit_available_for(&runes_minter_account))
         .map_err(UpdateBalanceError::TemporarilyUnavailable)?;
@@ -0,0 +1,249 @@
+use crate::https::{
+    provider,
+    types::{Provider, ServiceProvider},
+};
+use crate::lifecycle::upgrade::UpgradeArgs;
+use crate::state::{self, audit, mutate_state, read_state, MinterState, Mode, UtxoCheckStatus};
+use candid::{CandidType, Deserialize, Principal};
+use ic_btc_interface::Utxo;
+use icrc_ledger_types::icrc1::account::Account;
+
+/// The version of the snapshot layout. Snapshots of other versions are rejected on import.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A candid-encoded [Snapshot].
+pub type SnapshotBlob = Vec<u8>;
+
+/// A KYT check result, see [MinterState::checked_utxos].
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct CheckedUtxo {
+    pub utxo: Utxo,
+    pub uuid: String,
+    pub clean: bool,
+    pub kyt_provider: Principal,
+}
+
+/// The recoverable portions of the minter state.
+///
+/// NOTE: the snapshot includes the provider credentials.
+#[derive(CandidType, Clone, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub config: UpgradeArgs,
+    pub checked_utxos: Vec<CheckedUtxo>,
+    pub finalized_utxos: Vec<(Account, Vec<Utxo>)>,
+    pub providers: Vec<Provider>,
+    pub service_providers: Vec<(ServiceProvider, u64)>,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Snapshots can only be imported while the minter is in read-only mode.
+    NotPaused,
+    /// The snapshot was exported with another layout.
+    UnsupportedVersion { expected: u32, found: u32 },
+    /// The blob is not a valid snapshot.
+    Malformed(String),
+}
+
+/// Returns the configuration of the given state as upgrade arguments, leaving the mode unset.
+fn config_of(s: &MinterState) -> UpgradeArgs {
+    UpgradeArgs {
+        retrieve_btc_min_amount: Some(s.retrieve_btc_min_amount),
+        min_confirmations: Some(s.min_confirmations),
+        max_time_in_queue_nanos: Some(s.max_time_in_queue_nanos),
+        mode: None,
+        kyt_fee: Some(s.kyt_fee),
+        kyt_principal: s.kyt_principal,
+        xrc_id_fallback: s.xrc_id_fallback,
+        min_deposit: Some(s.min_btc_deposit),
+        max_utxos_per_update: Some(s.max_utxos_per_update),
+        min_payment_susd: Some(s.min_payment_susd),
+        min_payment_btc: Some(s.min_payment_btc),
+        pending_runes_ttl: Some(s.pending_runes_ttl),
+        debt_ceiling_susd: Some(s.debt_ceiling_susd),
+        fee_percentile: Some(s.fee_percentile),
+        quote_currency: Some(s.quote_currency.clone()),
+        rate_providers: Some(s.rate_providers.clone()),
+        rate_max_deviation_bps: Some(s.rate_max_deviation_bps),
+        circuit_breaker_bps: Some(s.circuit_breaker_bps),
+        circuit_breaker_window: Some(s.circuit_breaker_window),
+        update_balance_interval: Some(s.update_balance_interval),
+        max_pending_utxos: Some(s.max_pending_utxos),
+        indexer_max_response_bytes: Some(s.indexer_max_response_bytes),
+        max_collateral_per_account: Some(s.max_collateral_per_account),
+        guard_timeout: Some(s.guard_timeout),
+        max_concurrent_requests: Some(s.max_concurrent_requests),
+        liquidation_bonus_bps: Some(s.liquidation_bonus_bps),
+        liquidation_grace_period: Some(s.liquidation_grace_period),
+        liquidation_threshold_bps: Some(s.liquidation_threshold_bps),
+        stability_fee_bps_per_year: Some(s.stability_fee_bps_per_year),
+        redemption_fee_bps: Some(s.redemption_fee_bps),
+        min_redeem_btc: Some(s.min_redeem_btc),
+        max_redeem_btc: Some(s.max_redeem_btc),
+        pin_ledger_fees: Some(s.pin_ledger_fees),
+        xrc_cycles: Some(s.xrc_cycles),
+        get_utxos_cycles: Some(s.get_utxos_cycles),
+        min_collateral_ratio_bps: Some(s.min_collateral_ratio_bps),
+    }
+}
+
+pub fn encode_snapshot(snapshot: &Snapshot) -> SnapshotBlob {
+    candid::encode_one(snapshot).expect("failed to encode a snapshot")
+}
+
+pub fn decode_snapshot(blob: &[u8]) -> Result<Snapshot, SnapshotError> {
+    let snapshot: Snapshot =
+        candid::decode_one(blob).map_err(|e| SnapshotError::Malformed(e.to_string()))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion {
+            expected: SNAPSHOT_VERSION,
+            found: snapshot.version,
+        });
+    }
+    Ok(snapshot)
+}
+
+/// Serializes the configuration, the KYT check results, the finalized UTXOs and the
+/// service providers.
+pub fn export_snapshot() -> SnapshotBlob {
+    let (config, checked_utxos, finalized_utxos) = read_state(|s| {
+        (
+            config_of(s),
+            s.checked_utxos
+                .iter()
+                .map(|(utxo, (uuid, status, kyt_provider))| CheckedUtxo {
+                    utxo: utxo.clone(),
+                    uuid: uuid.clone(),
+                    clean: status.is_clean(),
+                    kyt_provider: *kyt_provider,
+                })
+                .collect(),
+            s.finalized_utxos
+                .iter()
+                .map(|(account, utxos)| (*account, utxos.iter().cloned().collect()))
+                .collect(),
+        )
+    });
+    let (providers, service_providers) = provider::export_providers();
+
+    encode_snapshot(&Snapshot {
+        version: SNAPSHOT_VERSION,
+        config,
+        checked_utxos,
+        finalized_utxos,
+        providers,
+        service_providers,
+    })
+}
+
+/// Restores a snapshot exported by [export_snapshot].
+///
+/// The configuration and the KYT check results go through the event log, so that they
+/// survive upgrades. The minter stays in read-only mode until the controllers resume it.
+pub fn import_snapshot(blob: &[u8]) -> Result<(), SnapshotError> {
+    if read_state(|s| s.mode != Mode::ReadOnly) {
+        return Err(SnapshotError::NotPaused);
+    }
+    let snapshot = decode_snapshot(blob)?;
+
+    mutate_state(|s| {
+        audit::update_config(s, snapshot.config);
+        for checked in snapshot.checked_utxos {
+            if !s.checked_utxos.contains_key(&checked.utxo) {
+                audit::mark_utxo_checked(
+                    s,
+                    &checked.utxo,
+                    checked.uuid,
+                    UtxoCheckStatus::from_clean_flag(checked.clean),
+                    checked.kyt_provider,
+                );
+            }
+        }
+        for (account, utxos) in snapshot.finalized_utxos {
+            audit::import_finalized_utxos(s, account, utxos);
+        }
+    });
+    provider::import_providers(snapshot.providers, snapshot.service_providers);
+
+    state::read_state(|s| s.validate_config());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_btc_interface::OutPoint;
+
+    fn snapshot(version: u32) -> Snapshot {
+        Snapshot {
+            version,
+            config: UpgradeArgs {
+                max_pending_utxos: Some(50),
+                ..Default::default()
+            },
+            checked_utxos: vec![CheckedUtxo {
+                utxo: Utxo {
+                    outpoint: OutPoint {
+                        txid: [1; 32].into(),
+                        vout: 0,
+                    },
+                    value: 100_000,
+                    height: 10,
+                },
+                uuid: "uuid".to_string(),
+                clean: true,
+                kyt_provider: Principal::anonymous(),
+            }],
+            finalized_utxos: vec![],
+            providers: vec![],
+            service_providers: vec![(ServiceProvider::Chain(0), 1)],
+        }
+    }
+
+    #[test]
+    fn snapshot_should_round_trip() {
+        let decoded = decode_snapshot(&encode_snapshot(&snapshot(SNAPSHOT_VERSION))).unwrap();
+        assert_eq!(decoded.config, snapshot(SNAPSHOT_VERSION).config);
+        assert_eq!(decoded.checked_utxos, snapshot(SNAPSHOT_VERSION).checked_utxos);
+        assert_eq!(decoded.service_providers.len(), 1);
+    }
+
+    #[test]
+    fn imported_finalized_utxos_should_survive_a_replay() {
+        use crate::state::eventlog::{replay, Event};
+
+        let account = Account {
+            owner: Principal::anonymous(),
+            subaccount: None,
+        };
+        let utxos = vec![snapshot(SNAPSHOT_VERSION).checked_utxos[0].utxo.clone()];
+        let state = replay(
+            vec![
+                Event::Init(crate::lifecycle::init::test_init_args()),
+                Event::ImportedFinalizedUtxos { account, utxos: utxos.clone() },
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            state.finalized_utxos.get(&account).map(|utxos| utxos.iter().cloned().collect::<Vec<_>>()),
+            Some(utxos)
+        );
+    }
+
+    #[test]
+    fn snapshot_of_another_version_should_be_rejected() {
+        assert_eq!(
+            decode_snapshot(&encode_snapshot(&snapshot(SNAPSHOT_VERSION + 1))).err(),
+            Some(SnapshotError::UnsupportedVersion {
+                expected: SNAPSHOT_VERSION,
+                found: SNAPSHOT_VERSION + 1,
+            })
+        );
+        assert!(matches!(
+            decode_snapshot(b"not a snapshot"),
+            Err(SnapshotError::Malformed(_))
+        ));
+    }
+}
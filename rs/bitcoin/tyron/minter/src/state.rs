@@ -13,7 +13,9 @@ pub mod eventlog;
 
 use crate::{lifecycle::init::InitArgs};
 use crate::lifecycle::upgrade::UpgradeArgs;
+use crate::updates::get_btc_address::SyronOperation;
 use crate::logs::P0;
+use crate::management::XrcSource;
 use crate::{address::BitcoinAddress, ECDSAPublicKey};
 use candid::{CandidType, Deserialize, Principal};
 use ic_base_types::CanisterId;
@@ -31,6 +33,17 @@ const MAX_FINALIZED_REQUESTS: usize = 100;
 /// Default minimum deposit amount in satoshis
 const DEFAULT_MIN_DEPOSIT: u64 = 1000; // 1000 sats = 0.00001 BTC
 
+/// The first identifier of the redemptions to a bitcoin address. The ledger block indices
+/// that identify the retrieve_btc requests never reach this range.
+pub const REDEMPTION_REQUEST_ID_OFFSET: u64 = 1 << 63;
+
+/// The period during which retries of a mint reuse the `created_at_time` of the first attempt.
+///
+/// The ledgers deduplicate identical transfers within their transaction window, which we
+/// assume to be the ICRC-1 default of 24 hours. We keep a one-hour margin so that a reused
+/// timestamp is never rejected as too old.
+pub const MINT_DEDUP_WINDOW_NANOS: u64 = 23 * 60 * 60 * 1_000_000_000;
+
 thread_local! {
     static __STATE: RefCell<Option<MinterState>> = RefCell::default();
 }
@@ -206,6 +219,9 @@ pub enum Mode {
     DepositsRestrictedTo(Vec<Account>),
     /// No restrictions on the minter interactions.
     GeneralAvailability,
+    /// The minter does not issue new SUSD, but users can still repay their loans
+    /// and redeem their bitcoin.
+    RedeemOnly,
 }
 
 impl Mode {
@@ -214,6 +230,7 @@ impl Mode {
         match self {
             Self::GeneralAvailability => Ok(()),
             Self::ReadOnly => Err("the minter is in read-only mode".to_string()),
+            Self::RedeemOnly => Err("the minter only accepts redemptions".to_string()),
             Self::RestrictedTo(allow_list) => {
                 if !allow_list.contains(p) {
                     return Err("access to the minter is temporarily restricted".to_string());
@@ -232,7 +249,7 @@ impl Mode {
     /// Returns Ok if the specified principal can convert ckBTC to BTC.
     pub fn is_withdrawal_available_for(&self, p: &Account) -> Result<(), String> {
         match self {
-            Self::GeneralAvailability | Self::DepositsRestrictedTo(_) => Ok(()),
+            Self::GeneralAvailability | Self::DepositsRestrictedTo(_) | Self::RedeemOnly => Ok(()),
             Self::ReadOnly => Err("the minter is in read-only mode".to_string()),
             Self::RestrictedTo(allow_list) => {
                 if !allow_list.contains(p) {
@@ -299,11 +316,14 @@ pub struct MinterState {
     /// Maximum time of nanoseconds that a transaction should spend in the queue before being sent.
     pub max_time_in_queue_nanos: u64,
 
-    /// Per-user lock for update_balance
-    pub update_balance_accounts: BTreeSet<Account>,
+    /// Per-user lock for update_balance, with the time the lock was acquired
+    pub update_balance_accounts: BTreeMap<Account, u64>,
+
+    /// Per-user lock for retrieve_btc, with the time the lock was acquired
+    pub retrieve_btc_accounts: BTreeMap<Account, u64>,
 
-    /// Per-user lock for retrieve_btc
-    pub retrieve_btc_accounts: BTreeSet<Account>,
+    /// Per-user and per-operation lock for update_ssi_balance, with the time the lock was acquired
+    pub update_balance_operations: BTreeMap<(Account, SyronOperation), u64>,
 
     /// Minimum amount of bitcoin that can be retrieved
     pub retrieve_btc_min_amount: u64,
@@ -315,6 +335,10 @@ pub struct MinterState {
     /// Maps Account to its retrieve_btc requests burn block indices.
     pub retrieve_btc_account_to_block_indices: BTreeMap<Account, Vec<u64>>,
 
+    /// The number of redemptions sent to a bitcoin address, which allocates their request ids.
+    /// It is derived from the accepted requests when the event log is replayed.
+    pub redemption_requests: u64,
+
     /// The identifiers of retrieve_btc requests which we're currently signing a
     /// transaction or sending to the Bitcoin network.
     pub requests_in_flight: BTreeMap<u64, InFlightStatus>,
@@ -404,6 +428,9 @@ pub struct MinterState {
     // @review upgrade state - dao
     pub min_btc_deposit: u64,
 
+    /// Maximum number of UTXOs processed by a single balance update.
+    pub max_utxos_per_update: u64,
+
     /// The total amount of fees we owe to the KYT provider.
     pub owed_kyt_amount: BTreeMap<Principal, u64>,
 
@@ -423,6 +450,180 @@ pub struct MinterState {
     /// Map from burn block index to the the reimbursed request.
     pub reimbursed_transactions: BTreeMap<u64, ReimbursedDeposit>,
 
+    /// Minimum amount of SUSD that can be sent in a payment.
+    pub min_payment_susd: u64,
+
+    /// Minimum amount of bitcoin that can be credited in a payment.
+    pub min_payment_btc: u64,
+
+    /// The total amount of BTC (in Satoshi) locked as collateral.
+    pub total_collateral: u64,
+
+    /// The total amount of SUSD lent against the collateral.
+    pub total_debt: u64,
+
+    /// The SUSD reserved against the debt ceiling by the mints in flight.
+    #[serde(skip)]
+    pub reserved_debt: u64,
+
+    /// The last BTC exchange rate fetched by the minter and the time it was fetched.
+    pub last_exchange_rate: Option<(u64, u64)>,
+
+    /// The last exchange rate accepted by the circuit breaker and the time it was accepted.
+    pub accepted_exchange_rate: Option<(u64, u64)>,
+
+    /// The last exchange rate rejected by the circuit breaker, pending re-confirmation.
+    pub unconfirmed_exchange_rate: Option<u64>,
+
+    /// Map from caller and SSI to the time of its last accepted update_ssi_balance call.
+    #[serde(skip)]
+    pub last_balance_updates: BTreeMap<(Principal, String), u64>,
+
+    /// Cache of the transfer fees of the ledgers, queried once per canister session.
+    #[serde(skip)]
+    pub ledger_fees: BTreeMap<Principal, u64>,
+
+    /// Map from SSI to the time its loan was first observed below the liquidation threshold.
+    pub underwater_since: BTreeMap<String, u64>,
+
+    /// A cache of the principals linked to SSIs by the SIWB canister and the times they were
+    /// fetched, see [crate::management::get_siwb_principal].
+    #[serde(skip)]
+    pub siwb_principals: BTreeMap<String, (Principal, u64)>,
+
+    /// Map from SSI to its runes deposit pending finalization.
+    pub pending_runes_deposits: BTreeMap<String, PendingRunesDeposit>,
+
+    /// Map from SSI to the stability fee accrued on its loan.
+    pub stability_fees: BTreeMap<String, StabilityFee>,
+
+    /// Map from UTXO outpoint to the first attempt to mint for it. Retries reuse its
+    /// `created_at_time` and amounts, so that the ledgers deduplicate them.
+    pub mint_attempts: BTreeMap<OutPoint, MintAttempt>,
+
+    /// The time (in nanoseconds) after which an unfinalized runes deposit is reverted.
+    pub pending_runes_ttl: u64,
+
+    /// The maximum total amount of SUSD that the minter can lend.
+    pub debt_ceiling_susd: u64,
+
+    /// The percentile of the current Bitcoin fees used to build transactions.
+    pub fee_percentile: u64,
+
+    /// The fiat currency the BTC collateral is priced in.
+    pub quote_currency: String,
+
+    /// The service providers queried for the BTC price alongside the exchange rate canister.
+    pub rate_providers: Vec<u64>,
+
+    /// The maximum deviation (in basis points) of a BTC price source from the median before it is discarded.
+    pub rate_max_deviation_bps: u64,
+
+    /// The maximum move (in basis points) of the BTC exchange rate within the circuit breaker window before minting is halted.
+    pub circuit_breaker_bps: u64,
+
+    /// The time (in nanoseconds) during which the last accepted exchange rate is the reference of the circuit breaker.
+    pub circuit_breaker_window: u64,
+
+    /// The minimum time (in nanoseconds) between two accepted update_ssi_balance calls for the same SSI.
+    pub update_balance_interval: u64,
+
+    /// The maximum number of pending UTXOs reported in a NoNewUtxos error.
+    pub max_pending_utxos: u64,
+
+    /// The version of the state layout, see [crate::lifecycle::upgrade::STATE_VERSION].
+    /// States replayed from logs that predate versioning start at zero.
+    #[serde(default)]
+    pub state_version: u32,
+
+    /// The initial maximum size (in bytes) of the runes indexer responses, grown on demand up to a bounded limit.
+    pub indexer_max_response_bytes: u64,
+
+    /// The maximum BTC collateral (in Satoshi) that a single SSI can deposit.
+    pub max_collateral_per_account: u64,
+
+    /// The time (in nanoseconds) after which a per-account guard is considered stale and can be reclaimed.
+    pub guard_timeout: u64,
+
+    /// The maximum number of concurrent requests per kind (balance updates, retrievals).
+    pub max_concurrent_requests: u64,
+
+    /// The bonus (in basis points of the repaid SUSD) of the collateral that a liquidator receives.
+    pub liquidation_bonus_bps: u64,
+
+    /// The time (in nanoseconds) that a loan must stay below the liquidation threshold before it can be liquidated.
+    pub liquidation_grace_period: u64,
+
+    /// The collateral ratio (in basis points) below which a loan can be liquidated.
+    pub liquidation_threshold_bps: u64,
+
+    /// The yearly stability fee (in basis points) accrued on the outstanding SUSD loans.
+    pub stability_fee_bps_per_year: u64,
+
+    /// The fee (in basis points of the redeemed SBTC) charged on bitcoin redemptions.
+    pub redemption_fee_bps: u64,
+
+    /// Minimum amount of bitcoin collateral (in Satoshi) that can be redeemed at once.
+    pub min_redeem_btc: u64,
+
+    /// Maximum amount of bitcoin collateral (in Satoshi) that can be redeemed at once.
+    pub max_redeem_btc: u64,
+
+    /// Whether the transfers of the minter state the ledger fee they expect instead of letting the ledger apply its default.
+    pub pin_ledger_fees: bool,
+
+    /// The CanisterId of a secondary Exchange Rate Canister, queried when the primary one fails.
+    pub xrc_id_fallback: Option<CanisterId>,
+
+    /// The Exchange Rate Canister that produced the last fetched rate.
+    pub last_xrc_source: Option<XrcSource>,
+
+    /// The cycles attached to each call to the Exchange Rate Canister.
+    pub xrc_cycles: u64,
+
+    /// The cycles attached to each bitcoin_get_utxos call.
+    pub get_utxos_cycles: u64,
+
+    /// The minimum collateral ratio (in basis points) of an open loan, which gates new minting and partial redemptions.
+    /// Loans are only liquidated below the lower liquidation threshold.
+    pub min_collateral_ratio_bps: u64,
+
+}
+
+/// A runes deposit credited under the pending subaccount (nonce 5) that the
+/// user did not finalize yet.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, serde::Deserialize, Serialize)]
+pub struct PendingRunesDeposit {
+    /// The pending amount of SUSD.
+    pub amount: u64,
+    /// The time of the first pending deposit.
+    pub created_at: u64,
+    /// The runes UTXOs backing the deposit.
+    #[serde(default)]
+    pub outpoints: Vec<OutPoint>,
+}
+
+/// The first attempt to mint for a deposited UTXO, which its retries repeat.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, serde::Deserialize, Serialize)]
+pub struct MintAttempt {
+    /// The ledger `created_at_time` of the mint transfers.
+    pub created_at_time: u64,
+    /// The SUSD lent against the UTXO.
+    pub susd: u64,
+    /// Whether every mint transfer carries a [crate::memo::MintMemo::convert] memo. The earlier
+    /// attempts carry a [crate::memo::MintMemo::compact] memo on the collateral transfer only,
+    /// which their retries must repeat for the ledgers to deduplicate them.
+    #[serde(default)]
+    pub convert_memo: bool,
+}
+
+/// The stability fee accrued on the loan of an SSI.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, serde::Deserialize, Serialize)]
+pub struct StabilityFee {
+    /// The accrued fee in SUSD that was not paid yet.
+    pub accrued: u64,
+    /// The time of the last accrual.
+    pub accrued_at: u64,
 }
 
 #[derive(CandidType, Clone, Debug, PartialEq, Eq, serde::Deserialize, Serialize)]
@@ -466,6 +667,35 @@ impl MinterState {
             kyt_fee,
             kyt_principal,
             min_deposit,
+            max_utxos_per_update,
+            min_payment_susd,
+            min_payment_btc,
+            pending_runes_ttl,
+            debt_ceiling_susd,
+            fee_percentile,
+            quote_currency,
+            rate_providers,
+            rate_max_deviation_bps,
+            circuit_breaker_bps,
+            circuit_breaker_window,
+            update_balance_interval,
+            max_pending_utxos,
+            indexer_max_response_bytes,
+            max_collateral_per_account,
+            guard_timeout,
+            max_concurrent_requests,
+            liquidation_bonus_bps,
+            liquidation_grace_period,
+            liquidation_threshold_bps,
+            stability_fee_bps_per_year,
+            redemption_fee_bps,
+            min_redeem_btc,
+            max_redeem_btc,
+            pin_ledger_fees,
+            xrc_id_fallback,
+            xrc_cycles,
+            get_utxos_cycles,
+            min_collateral_ratio_bps,
         }: InitArgs,
     ) {
         self.btc_network = btc_network.into();
@@ -487,6 +717,93 @@ impl MinterState {
         if let Some(min_deposit) = min_deposit {
             self.min_btc_deposit = min_deposit;
         }
+        if let Some(max_utxos_per_update) = max_utxos_per_update {
+            self.max_utxos_per_update = max_utxos_per_update;
+        }
+        if let Some(min_payment_susd) = min_payment_susd {
+            self.min_payment_susd = min_payment_susd;
+        }
+        if let Some(min_payment_btc) = min_payment_btc {
+            self.min_payment_btc = min_payment_btc;
+        }
+        if let Some(pending_runes_ttl) = pending_runes_ttl {
+            self.pending_runes_ttl = pending_runes_ttl;
+        }
+        if let Some(debt_ceiling_susd) = debt_ceiling_susd {
+            self.debt_ceiling_susd = debt_ceiling_susd;
+        }
+        if let Some(fee_percentile) = fee_percentile {
+            self.fee_percentile = fee_percentile;
+        }
+        if let Some(quote_currency) = quote_currency {
+            self.quote_currency = quote_currency;
+        }
+        if let Some(rate_providers) = rate_providers {
+            self.rate_providers = rate_providers;
+        }
+        if let Some(rate_max_deviation_bps) = rate_max_deviation_bps {
+            self.rate_max_deviation_bps = rate_max_deviation_bps;
+        }
+        if let Some(circuit_breaker_bps) = circuit_breaker_bps {
+            self.circuit_breaker_bps = circuit_breaker_bps;
+        }
+        if let Some(circuit_breaker_window) = circuit_breaker_window {
+            self.circuit_breaker_window = circuit_breaker_window;
+        }
+        if let Some(update_balance_interval) = update_balance_interval {
+            self.update_balance_interval = update_balance_interval;
+        }
+        if let Some(max_pending_utxos) = max_pending_utxos {
+            self.max_pending_utxos = max_pending_utxos;
+        }
+        if let Some(indexer_max_response_bytes) = indexer_max_response_bytes {
+            self.indexer_max_response_bytes = indexer_max_response_bytes;
+        }
+        if let Some(max_collateral_per_account) = max_collateral_per_account {
+            self.max_collateral_per_account = max_collateral_per_account;
+        }
+        if let Some(guard_timeout) = guard_timeout {
+            self.guard_timeout = guard_timeout;
+        }
+        if let Some(max_concurrent_requests) = max_concurrent_requests {
+            self.max_concurrent_requests = max_concurrent_requests;
+        }
+        if let Some(liquidation_bonus_bps) = liquidation_bonus_bps {
+            self.liquidation_bonus_bps = liquidation_bonus_bps;
+        }
+        if let Some(liquidation_grace_period) = liquidation_grace_period {
+            self.liquidation_grace_period = liquidation_grace_period;
+        }
+        if let Some(liquidation_threshold_bps) = liquidation_threshold_bps {
+            self.liquidation_threshold_bps = liquidation_threshold_bps;
+        }
+        if let Some(stability_fee_bps_per_year) = stability_fee_bps_per_year {
+            self.stability_fee_bps_per_year = stability_fee_bps_per_year;
+        }
+        if let Some(redemption_fee_bps) = redemption_fee_bps {
+            self.redemption_fee_bps = redemption_fee_bps;
+        }
+        if let Some(min_redeem_btc) = min_redeem_btc {
+            self.min_redeem_btc = min_redeem_btc;
+        }
+        if let Some(max_redeem_btc) = max_redeem_btc {
+            self.max_redeem_btc = max_redeem_btc;
+        }
+        if let Some(pin_ledger_fees) = pin_ledger_fees {
+            self.pin_ledger_fees = pin_ledger_fees;
+        }
+        if let Some(xrc_id_fallback) = xrc_id_fallback {
+            self.xrc_id_fallback = Some(xrc_id_fallback);
+        }
+        if let Some(xrc_cycles) = xrc_cycles {
+            self.xrc_cycles = xrc_cycles;
+        }
+        if let Some(get_utxos_cycles) = get_utxos_cycles {
+            self.get_utxos_cycles = get_utxos_cycles;
+        }
+        if let Some(min_collateral_ratio_bps) = min_collateral_ratio_bps {
+            self.min_collateral_ratio_bps = min_collateral_ratio_bps;
+        }
     }
 
     pub fn upgrade(
@@ -499,6 +816,35 @@ impl MinterState {
             kyt_principal,
             kyt_fee,
             min_deposit,
+            max_utxos_per_update,
+            min_payment_susd,
+            min_payment_btc,
+            pending_runes_ttl,
+            debt_ceiling_susd,
+            fee_percentile,
+            quote_currency,
+            rate_providers,
+            rate_max_deviation_bps,
+            circuit_breaker_bps,
+            circuit_breaker_window,
+            update_balance_interval,
+            max_pending_utxos,
+            indexer_max_response_bytes,
+            max_collateral_per_account,
+            guard_timeout,
+            max_concurrent_requests,
+            liquidation_bonus_bps,
+            liquidation_grace_period,
+            liquidation_threshold_bps,
+            stability_fee_bps_per_year,
+            redemption_fee_bps,
+            min_redeem_btc,
+            max_redeem_btc,
+            pin_ledger_fees,
+            xrc_id_fallback,
+            xrc_cycles,
+            get_utxos_cycles,
+            min_collateral_ratio_bps,
         }: UpgradeArgs,
     ) {
         if let Some(retrieve_btc_min_amount) = retrieve_btc_min_amount {
@@ -530,6 +876,127 @@ impl MinterState {
         if let Some(min_deposit) = min_deposit {
             self.min_btc_deposit = min_deposit;
         }
+        if let Some(max_utxos_per_update) = max_utxos_per_update {
+            self.max_utxos_per_update = max_utxos_per_update;
+        }
+        if let Some(min_payment_susd) = min_payment_susd {
+            self.min_payment_susd = min_payment_susd;
+        }
+        if let Some(min_payment_btc) = min_payment_btc {
+            self.min_payment_btc = min_payment_btc;
+        }
+        if let Some(pending_runes_ttl) = pending_runes_ttl {
+            self.pending_runes_ttl = pending_runes_ttl;
+        }
+        if let Some(debt_ceiling_susd) = debt_ceiling_susd {
+            self.debt_ceiling_susd = debt_ceiling_susd;
+        }
+        if let Some(fee_percentile) = fee_percentile {
+            self.fee_percentile = fee_percentile;
+        }
+        if let Some(quote_currency) = quote_currency {
+            self.quote_currency = quote_currency;
+        }
+        if let Some(rate_providers) = rate_providers {
+            self.rate_providers = rate_providers;
+        }
+        if let Some(rate_max_deviation_bps) = rate_max_deviation_bps {
+            self.rate_max_deviation_bps = rate_max_deviation_bps;
+        }
+        if let Some(circuit_breaker_bps) = circuit_breaker_bps {
+            self.circuit_breaker_bps = circuit_breaker_bps;
+        }
+        if let Some(circuit_breaker_window) = circuit_breaker_window {
+            self.circuit_breaker_window = circuit_breaker_window;
+        }
+        if let Some(update_balance_interval) = update_balance_interval {
+            self.update_balance_interval = update_balance_interval;
+        }
+        if let Some(max_pending_utxos) = max_pending_utxos {
+            self.max_pending_utxos = max_pending_utxos;
+        }
+        if let Some(indexer_max_response_bytes) = indexer_max_response_bytes {
+            self.indexer_max_response_bytes = indexer_max_response_bytes;
+        }
+        if let Some(max_collateral_per_account) = max_collateral_per_account {
+            self.max_collateral_per_account = max_collateral_per_account;
+        }
+        if let Some(guard_timeout) = guard_timeout {
+            self.guard_timeout = guard_timeout;
+        }
+        if let Some(max_concurrent_requests) = max_concurrent_requests {
+            self.max_concurrent_requests = max_concurrent_requests;
+        }
+        if let Some(liquidation_bonus_bps) = liquidation_bonus_bps {
+            self.liquidation_bonus_bps = liquidation_bonus_bps;
+        }
+        if let Some(liquidation_grace_period) = liquidation_grace_period {
+            self.liquidation_grace_period = liquidation_grace_period;
+        }
+        if let Some(liquidation_threshold_bps) = liquidation_threshold_bps {
+            self.liquidation_threshold_bps = liquidation_threshold_bps;
+        }
+        if let Some(min_collateral_ratio_bps) = min_collateral_ratio_bps {
+            self.min_collateral_ratio_bps = min_collateral_ratio_bps;
+        }
+        if let Some(stability_fee_bps_per_year) = stability_fee_bps_per_year {
+            self.stability_fee_bps_per_year = stability_fee_bps_per_year;
+        }
+        if let Some(redemption_fee_bps) = redemption_fee_bps {
+            self.redemption_fee_bps = redemption_fee_bps;
+        }
+        if let Some(min_redeem_btc) = min_redeem_btc {
+            self.min_redeem_btc = min_redeem_btc;
+        }
+        if let Some(max_redeem_btc) = max_redeem_btc {
+            self.max_redeem_btc = max_redeem_btc;
+        }
+        if let Some(pin_ledger_fees) = pin_ledger_fees {
+            self.pin_ledger_fees = pin_ledger_fees;
+        }
+        if let Some(xrc_id_fallback) = xrc_id_fallback {
+            self.xrc_id_fallback = Some(xrc_id_fallback);
+        }
+        if let Some(xrc_cycles) = xrc_cycles {
+            self.xrc_cycles = xrc_cycles;
+        }
+        if let Some(get_utxos_cycles) = get_utxos_cycles {
+            self.get_utxos_cycles = get_utxos_cycles;
+        }
+    }
+
+    /// Migrates the state to the given version by filling the configuration fields
+    /// introduced since the current version that hold no usable value.
+    pub fn migrate_to(&mut self, state_version: u32) {
+        use crate::lifecycle::init::*;
+
+        if self.state_version < 1 && state_version >= 1 {
+            if self.max_utxos_per_update == 0 {
+                self.max_utxos_per_update = DEFAULT_MAX_UTXOS_PER_UPDATE;
+            }
+            if self.min_payment_susd == 0 {
+                self.min_payment_susd = DEFAULT_MIN_PAYMENT_SUSD;
+            }
+            if self.min_payment_btc == 0 {
+                self.min_payment_btc = DEFAULT_MIN_PAYMENT_BTC;
+            }
+            if self.pending_runes_ttl == 0 {
+                self.pending_runes_ttl = DEFAULT_PENDING_RUNES_TTL;
+            }
+            if self.quote_currency.is_empty() {
+                self.quote_currency = DEFAULT_QUOTE_CURRENCY.to_string();
+            }
+            if self.rate_max_deviation_bps == 0 {
+                self.rate_max_deviation_bps = DEFAULT_RATE_MAX_DEVIATION_BPS;
+            }
+            if self.circuit_breaker_window == 0 {
+                self.circuit_breaker_window = DEFAULT_CIRCUIT_BREAKER_WINDOW;
+            }
+            if self.max_pending_utxos == 0 {
+                self.max_pending_utxos = DEFAULT_MAX_PENDING_UTXOS;
+            }
+        }
+        self.state_version = self.state_version.max(state_version);
     }
 
     pub fn validate_config(&self) {
@@ -539,6 +1006,67 @@ impl MinterState {
         if self.ecdsa_key_name.is_empty() {
             ic_cdk::trap("ecdsa_key_name is not set");
         }
+        if self.max_utxos_per_update == 0 {
+            ic_cdk::trap("max_utxos_per_update cannot be zero");
+        }
+        if self.min_payment_susd == 0 {
+            ic_cdk::trap("min_payment_susd cannot be zero");
+        }
+        if self.min_payment_btc == 0 {
+            ic_cdk::trap("min_payment_btc cannot be zero");
+        }
+        if self.pending_runes_ttl == 0 {
+            ic_cdk::trap("pending_runes_ttl cannot be zero");
+        }
+        if self.fee_percentile > 99 {
+            ic_cdk::trap("fee_percentile cannot be greater than 99");
+        }
+        if !crate::management::SUPPORTED_QUOTE_CURRENCIES.contains(&self.quote_currency.as_str()) {
+            ic_cdk::trap("quote_currency must be one of USD, EUR or GBP");
+        }
+        if self.indexer_max_response_bytes == 0 {
+            ic_cdk::trap("indexer_max_response_bytes cannot be zero");
+        }
+        if self.guard_timeout == 0 {
+            ic_cdk::trap("guard_timeout cannot be zero");
+        }
+        if self.max_concurrent_requests == 0
+            || self.max_concurrent_requests > crate::guard::MAX_CONCURRENT_REQUESTS_LIMIT
+        {
+            ic_cdk::trap(&format!(
+                "max_concurrent_requests must be between 1 and {}",
+                crate::guard::MAX_CONCURRENT_REQUESTS_LIMIT
+            ));
+        }
+        // @dev borrowers have a buffer between the minimum collateral ratio and the liquidation threshold
+        if self.liquidation_threshold_bps > self.min_collateral_ratio_bps {
+            ic_cdk::trap(&format!(
+                "liquidation_threshold_bps cannot exceed the minimum collateral ratio ({})",
+                self.min_collateral_ratio_bps
+            ));
+        }
+        // @dev a liquidation at the threshold must not seize more than the entire collateral
+        if 10_000 + self.liquidation_bonus_bps > self.liquidation_threshold_bps {
+            ic_cdk::trap(&format!(
+                "liquidation_bonus_bps cannot exceed {}",
+                self.liquidation_threshold_bps.saturating_sub(10_000)
+            ));
+        }
+        if self.redemption_fee_bps > 10_000 {
+            ic_cdk::trap("redemption_fee_bps cannot exceed 10000");
+        }
+        if self.min_redeem_btc > self.max_redeem_btc {
+            ic_cdk::trap("min_redeem_btc cannot exceed max_redeem_btc");
+        }
+        if self.xrc_cycles == 0 {
+            ic_cdk::trap("xrc_cycles cannot be zero");
+        }
+        if self.get_utxos_cycles == 0 {
+            ic_cdk::trap("get_utxos_cycles cannot be zero");
+        }
+        if self.min_collateral_ratio_bps <= 10_000 {
+            ic_cdk::trap("min_collateral_ratio_bps must exceed 10000 (100%)");
+        }
         // if self.kyt_principal.is_none() {
         //     ic_cdk::trap("KYT principal is not set");
         // }
@@ -660,6 +1188,7 @@ impl MinterState {
             ic_cdk::println!("DEBUG: Adding UTXO: outpoint={:?}, value={}, account={:?}", utxo.outpoint, utxo.value, account);
             
             self.outpoint_account.insert(utxo.outpoint.clone(), account);
+            self.mint_attempts.remove(&utxo.outpoint);
 
             match &ssi_address {
                 Some(address) => {
@@ -849,9 +1378,18 @@ impl MinterState {
             .any(|req| req.block_index == block_index)
     }
 
+    /// Returns true if the given account holds a balance update or operation guard.
+    fn is_updating_balance(&self, account: &Account) -> bool {
+        self.update_balance_accounts.contains_key(account)
+            || self
+                .update_balance_operations
+                .keys()
+                .any(|(guarded, _)| guarded == account)
+    }
+
     fn forget_utxo(&mut self, utxo: &Utxo) {
         if let Some(account) = self.outpoint_account.remove(&utxo.outpoint) {
-            if self.update_balance_accounts.contains(&account) {
+            if self.is_updating_balance(&account) {
                 self.finalized_utxos
                     .entry(account)
                     .or_default()
@@ -1047,6 +1585,7 @@ impl MinterState {
         if let Some(last_req) = self.pending_retrieve_btc_requests.last() {
             assert!(last_req.received_at <= request.received_at);
         }
+        self.count_redemption_request(request.block_index);
         self.tokens_burned += request.amount;
         if let Some(kyt_provider) = request.kyt_provider {
             *self.owed_kyt_amount.entry(kyt_provider).or_insert(0) += self.kyt_fee;
@@ -1054,6 +1593,18 @@ impl MinterState {
         self.pending_retrieve_btc_requests.push(request);
     }
 
+    /// Returns the request id of the next redemption to a bitcoin address.
+    pub fn next_redemption_request_id(&self) -> u64 {
+        REDEMPTION_REQUEST_ID_OFFSET + self.redemption_requests
+    }
+
+    /// Advances the redemption counter past the given request id if it is a redemption.
+    pub fn count_redemption_request(&mut self, request_id: u64) {
+        if request_id >= REDEMPTION_REQUEST_ID_OFFSET {
+            self.redemption_requests = self.redemption_requests.max(request_id - REDEMPTION_REQUEST_ID_OFFSET + 1);
+        }
+    }
+
     /// Records a BTC transaction as submitted and updates statuses of all
     /// requests involved.
     ///
@@ -1084,6 +1635,22 @@ impl MinterState {
         self.finalized_requests.push_back(req)
     }
 
+    /// Returns the UTXOs of the given account that the minter already processed,
+    /// i.e. the recorded UTXOs and the UTXOs of finalized transactions.
+    ///
+    /// The ignored and quarantined UTXOs are not tracked per account, so they are not included.
+    pub fn known_utxos_for_account(&self, account: &Account) -> Vec<Utxo> {
+        let mut known: BTreeSet<Utxo> = self
+            .utxos_state_addresses
+            .get(account)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(finalized) = self.finalized_utxos.get(account) {
+            known.extend(finalized.iter().cloned());
+        }
+        known.into_iter().collect()
+    }
+
     /// Filters out known UTXOs of the given account from the given UTXO list.
     pub fn new_utxos_for_account(&self, mut utxos: Vec<Utxo>, account: &Account) -> Vec<Utxo> {
         let maybe_existing_utxos = self.utxos_state_addresses.get(account);
@@ -1121,13 +1688,137 @@ impl MinterState {
         // The minter account must have a specific subaccount computed with nonce 1 and treasury address as SSI
         if let Some(subaccount) = account.subaccount {
             let treasury_addr = self.dao_addr[1].display(self.btc_network);
-            let expected_subaccount = crate::updates::get_withdrawal_account::compute_subaccount(1, &treasury_addr);
+            let expected_subaccount = crate::updates::get_withdrawal_account::compute_ssi_subaccount(
+                crate::updates::get_withdrawal_account::SubaccountKind::Box,
+                &treasury_addr,
+            );
             return subaccount == expected_subaccount;
         }
         
         false
     }
 
+    /// Records a loan of `debt` SUSD against `collateral` satoshis.
+    fn add_loan(&mut self, collateral: u64, debt: u64) {
+        self.total_collateral = self.total_collateral.saturating_add(collateral);
+        self.total_debt = self.total_debt.saturating_add(debt);
+    }
+
+    /// Records the release of `collateral` satoshis and the repayment of `debt` SUSD.
+    fn remove_loan(&mut self, collateral: u64, debt: u64) {
+        self.total_collateral = self.total_collateral.saturating_sub(collateral);
+        self.total_debt = self.total_debt.saturating_sub(debt);
+    }
+
+    /// Records the exchange rate accepted by the circuit breaker at `timestamp`, which
+    /// confirms any rate pending re-confirmation.
+    fn accept_exchange_rate(&mut self, rate: u64, timestamp: u64) {
+        self.accepted_exchange_rate = Some((rate, timestamp));
+        self.unconfirmed_exchange_rate = None;
+    }
+
+    /// Records the exchange rate rejected by the circuit breaker, pending re-confirmation.
+    fn reject_exchange_rate(&mut self, rate: u64) {
+        self.unconfirmed_exchange_rate = Some(rate);
+    }
+
+    /// Adds the given UTXOs to the finalized UTXOs of the account.
+    fn add_finalized_utxos(&mut self, account: Account, utxos: Vec<Utxo>) {
+        self.finalized_utxos.entry(account).or_default().extend(utxos);
+    }
+
+    /// Replaces the totals of the loans with the ones seeded from the ledger balances.
+    fn seed_totals(&mut self, total_collateral: u64, total_debt: u64) {
+        self.total_collateral = total_collateral;
+        self.total_debt = total_debt;
+    }
+
+    /// Records a runes deposit of `amount` pending finalization.
+    /// Subsequent deposits keep the creation time of the first one.
+    fn add_pending_runes_deposit(
+        &mut self,
+        ssi: String,
+        amount: u64,
+        timestamp: u64,
+        outpoints: Vec<OutPoint>,
+    ) {
+        let deposit = self
+            .pending_runes_deposits
+            .entry(ssi)
+            .or_insert(PendingRunesDeposit {
+                amount: 0,
+                created_at: timestamp,
+                outpoints: vec![],
+            });
+        deposit.amount = deposit.amount.saturating_add(amount);
+        deposit.outpoints.extend(outpoints);
+    }
+
+    /// Records that `amount` of the pending runes deposit was finalized or reverted.
+    fn settle_pending_runes_deposit(&mut self, ssi: &str, amount: u64) {
+        if let Some(deposit) = self.pending_runes_deposits.get_mut(ssi) {
+            deposit.amount = deposit.amount.saturating_sub(amount);
+            if deposit.amount == 0 {
+                self.pending_runes_deposits.remove(ssi);
+            }
+        }
+    }
+
+    /// Records that `fee` accrued on the loan of the given SSI at `timestamp`.
+    fn accrue_stability_fee(&mut self, ssi: String, fee: u64, timestamp: u64) {
+        let stability_fee = self.stability_fees.entry(ssi).or_insert(StabilityFee {
+            accrued: 0,
+            accrued_at: timestamp,
+        });
+        stability_fee.accrued = stability_fee.accrued.saturating_add(fee);
+        stability_fee.accrued_at = timestamp;
+    }
+
+    /// Records the time the loan of the given SSI fell below the liquidation threshold, or
+    /// clears it if `since` is None.
+    fn set_underwater_since(&mut self, ssi: String, since: Option<u64>) {
+        match since {
+            Some(since) => self.underwater_since.insert(ssi, since),
+            None => self.underwater_since.remove(&ssi),
+        };
+    }
+
+    /// Records the first attempt to mint for the given outpoint and drops the attempts that
+    /// are past the deduplication window.
+    fn add_mint_attempt(&mut self, outpoint: OutPoint, attempt: MintAttempt) {
+        let now = attempt.created_at_time;
+        self.mint_attempts
+            .retain(|_, first| now.saturating_sub(first.created_at_time) < MINT_DEDUP_WINDOW_NANOS);
+        self.mint_attempts.insert(outpoint, attempt);
+    }
+
+    /// Records that `fee` of the accrued stability fee of the given SSI was paid.
+    /// The accrual stops once the fee is fully paid.
+    fn settle_stability_fee(&mut self, ssi: &str, fee: u64) {
+        if let Some(stability_fee) = self.stability_fees.get_mut(ssi) {
+            stability_fee.accrued = stability_fee.accrued.saturating_sub(fee);
+            if stability_fee.accrued == 0 {
+                self.stability_fees.remove(ssi);
+            }
+        }
+    }
+
+    /// Returns whether the runes UTXO already backs a pending runes deposit.
+    pub fn is_backing_runes_deposit(&self, outpoint: &OutPoint) -> bool {
+        self.pending_runes_deposits
+            .values()
+            .any(|deposit| deposit.outpoints.contains(outpoint))
+    }
+
+    /// Returns the SSIs and amounts of the pending runes deposits older than the TTL.
+    pub fn stale_runes_deposits(&self, now: u64) -> Vec<(String, u64)> {
+        self.pending_runes_deposits
+            .iter()
+            .filter(|(_, deposit)| now.saturating_sub(deposit.created_at) >= self.pending_runes_ttl)
+            .map(|(ssi, deposit)| (ssi.clone(), deposit.amount))
+            .collect()
+    }
+
     /// Adds given UTXO to the set of ignored UTXOs.
     fn ignore_utxo(&mut self, utxo: Utxo) {
         // assert!(utxo.value <= self.kyt_fee);
@@ -1285,6 +1976,50 @@ impl MinterState {
 
         ensure_eq!(self.kyt_fee, other.kyt_fee, "kyt_fee does not match");
 
+        ensure_eq!(
+            self.total_collateral,
+            other.total_collateral,
+            "total_collateral does not match"
+        );
+
+        ensure_eq!(self.total_debt, other.total_debt, "total_debt does not match");
+
+        ensure_eq!(
+            self.pending_runes_deposits,
+            other.pending_runes_deposits,
+            "pending_runes_deposits does not match"
+        );
+
+        ensure_eq!(
+            self.stability_fees,
+            other.stability_fees,
+            "stability_fees does not match"
+        );
+
+        ensure_eq!(
+            self.mint_attempts,
+            other.mint_attempts,
+            "mint_attempts does not match"
+        );
+
+        ensure_eq!(
+            self.accepted_exchange_rate,
+            other.accepted_exchange_rate,
+            "accepted_exchange_rate does not match"
+        );
+
+        ensure_eq!(
+            self.unconfirmed_exchange_rate,
+            other.unconfirmed_exchange_rate,
+            "unconfirmed_exchange_rate does not match"
+        );
+
+        ensure_eq!(
+            self.underwater_since,
+            other.underwater_since,
+            "underwater_since does not match"
+        );
+
         ensure_eq!(
             self.owed_kyt_amount,
             other.owed_kyt_amount,
@@ -1297,12 +2032,24 @@ impl MinterState {
             "kyt_principal does not match"
         );
 
+        ensure_eq!(
+            self.xrc_id_fallback,
+            other.xrc_id_fallback,
+            "xrc_id_fallback does not match"
+        );
+
         ensure_eq!(
             self.retrieve_btc_account_to_block_indices,
             other.retrieve_btc_account_to_block_indices,
             "retrieve_btc_account_to_block_indices does not match"
         );
 
+        ensure_eq!(
+            self.redemption_requests,
+            other.redemption_requests,
+            "redemption_requests does not match"
+        );
+
         let my_txs = as_sorted_vec(self.submitted_transactions.iter().cloned(), |tx| tx.txid);
         let other_txs = as_sorted_vec(other.submitted_transactions.iter().cloned(), |tx| tx.txid);
         ensure_eq!(my_txs, other_txs, "submitted_transactions do not match");
@@ -1360,6 +2107,7 @@ impl From<InitArgs> for MinterState {
                 .unwrap_or(crate::lifecycle::init::DEFAULT_MIN_CONFIRMATIONS),
             max_time_in_queue_nanos: args.max_time_in_queue_nanos,
             update_balance_accounts: Default::default(),
+            update_balance_operations: Default::default(),
             retrieve_btc_accounts: Default::default(),
             retrieve_btc_min_amount: args.retrieve_btc_min_amount,
             pending_retrieve_btc_requests: Default::default(),
@@ -1368,6 +2116,7 @@ impl From<InitArgs> for MinterState {
             submitted_transactions: Default::default(),
             replacement_txid: Default::default(),
             retrieve_btc_account_to_block_indices: Default::default(),
+            redemption_requests: 0,
             rev_replacement_txid: Default::default(),
             stuck_transactions: Default::default(),
             finalized_requests: VecDeque::with_capacity(MAX_FINALIZED_REQUESTS),
@@ -1401,6 +2150,106 @@ impl From<InitArgs> for MinterState {
             min_btc_deposit: args
                 .min_deposit
                 .unwrap_or(DEFAULT_MIN_DEPOSIT),
+            max_utxos_per_update: args
+                .max_utxos_per_update
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MAX_UTXOS_PER_UPDATE),
+            min_payment_susd: args
+                .min_payment_susd
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MIN_PAYMENT_SUSD),
+            min_payment_btc: args
+                .min_payment_btc
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MIN_PAYMENT_BTC),
+            total_collateral: 0,
+            total_debt: 0,
+            reserved_debt: 0,
+            last_exchange_rate: None,
+            accepted_exchange_rate: None,
+            unconfirmed_exchange_rate: None,
+            last_balance_updates: Default::default(),
+            ledger_fees: Default::default(),
+            underwater_since: Default::default(),
+            siwb_principals: Default::default(),
+            pending_runes_deposits: Default::default(),
+            stability_fees: Default::default(),
+            mint_attempts: Default::default(),
+            pending_runes_ttl: args
+                .pending_runes_ttl
+                .unwrap_or(crate::lifecycle::init::DEFAULT_PENDING_RUNES_TTL),
+            debt_ceiling_susd: args
+                .debt_ceiling_susd
+                .unwrap_or(crate::lifecycle::init::DEFAULT_DEBT_CEILING_SUSD),
+            fee_percentile: args
+                .fee_percentile
+                .unwrap_or(crate::lifecycle::init::DEFAULT_FEE_PERCENTILE),
+            quote_currency: args
+                .quote_currency
+                .unwrap_or_else(|| crate::lifecycle::init::DEFAULT_QUOTE_CURRENCY.to_string()),
+            rate_providers: args
+                .rate_providers
+                .unwrap_or_else(|| crate::lifecycle::init::DEFAULT_RATE_PROVIDERS.to_vec()),
+            rate_max_deviation_bps: args
+                .rate_max_deviation_bps
+                .unwrap_or(crate::lifecycle::init::DEFAULT_RATE_MAX_DEVIATION_BPS),
+            circuit_breaker_bps: args
+                .circuit_breaker_bps
+                .unwrap_or(crate::lifecycle::init::DEFAULT_CIRCUIT_BREAKER_BPS),
+            circuit_breaker_window: args
+                .circuit_breaker_window
+                .unwrap_or(crate::lifecycle::init::DEFAULT_CIRCUIT_BREAKER_WINDOW),
+            update_balance_interval: args
+                .update_balance_interval
+                .unwrap_or(crate::lifecycle::init::DEFAULT_UPDATE_BALANCE_INTERVAL),
+            max_pending_utxos: args
+                .max_pending_utxos
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MAX_PENDING_UTXOS),
+            state_version: 0,
+            indexer_max_response_bytes: args
+                .indexer_max_response_bytes
+                .unwrap_or(crate::lifecycle::init::DEFAULT_INDEXER_MAX_RESPONSE_BYTES),
+            max_collateral_per_account: args
+                .max_collateral_per_account
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MAX_COLLATERAL_PER_ACCOUNT),
+            guard_timeout: args
+                .guard_timeout
+                .unwrap_or(crate::lifecycle::init::DEFAULT_GUARD_TIMEOUT),
+            max_concurrent_requests: args
+                .max_concurrent_requests
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MAX_CONCURRENT_REQUESTS),
+            liquidation_bonus_bps: args
+                .liquidation_bonus_bps
+                .unwrap_or(crate::lifecycle::init::DEFAULT_LIQUIDATION_BONUS_BPS),
+            liquidation_grace_period: args
+                .liquidation_grace_period
+                .unwrap_or(crate::lifecycle::init::DEFAULT_LIQUIDATION_GRACE_PERIOD),
+            liquidation_threshold_bps: args
+                .liquidation_threshold_bps
+                .unwrap_or(crate::lifecycle::init::DEFAULT_LIQUIDATION_THRESHOLD_BPS),
+            stability_fee_bps_per_year: args
+                .stability_fee_bps_per_year
+                .unwrap_or(crate::lifecycle::init::DEFAULT_STABILITY_FEE_BPS_PER_YEAR),
+            redemption_fee_bps: args
+                .redemption_fee_bps
+                .unwrap_or(crate::lifecycle::init::DEFAULT_REDEMPTION_FEE_BPS),
+            min_redeem_btc: args
+                .min_redeem_btc
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MIN_REDEEM_BTC),
+            max_redeem_btc: args
+                .max_redeem_btc
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MAX_REDEEM_BTC),
+            pin_ledger_fees: args
+                .pin_ledger_fees
+                .unwrap_or(crate::lifecycle::init::DEFAULT_PIN_LEDGER_FEES),
+            xrc_id_fallback: args.xrc_id_fallback,
+            last_xrc_source: None,
+            xrc_cycles: args
+                .xrc_cycles
+                .unwrap_or(crate::lifecycle::init::DEFAULT_XRC_CYCLES),
+            get_utxos_cycles: args
+                .get_utxos_cycles
+                .unwrap_or_else(|| crate::management::get_utxos_cost_cycles(args.btc_network.into())),
+            min_collateral_ratio_bps: args
+                .min_collateral_ratio_bps
+                .unwrap_or(crate::lifecycle::init::DEFAULT_MIN_COLLATERAL_RATIO_BPS),
         }
     }
 }
@@ -1,18 +1,20 @@
 //! State modifications that should end up in the event log.
 
 use super::{
-    eventlog::Event, MinterState, FinalizedBtcRetrieval, FinalizedStatus, RetrieveBtcRequest,
-    SubmittedBtcTransaction, UtxoCheckStatus,
+    eventlog::Event, MinterState, FinalizedBtcRetrieval, FinalizedStatus, MintAttempt,
+    RetrieveBtcRequest, SubmittedBtcTransaction, UtxoCheckStatus,
 };
+use crate::lifecycle::upgrade::UpgradeArgs;
 use crate::state::{ReimburseDepositTask, ReimbursedDeposit};
 use crate::storage::record_event;
 use crate::ReimbursementReason;
 use candid::Principal;
-use ic_btc_interface::{Txid, Utxo};
+use ic_btc_interface::{OutPoint, Txid, Utxo};
 use icrc_ledger_types::icrc1::account::Account;
 
 pub fn accept_retrieve_btc_request(state: &mut MinterState, request: RetrieveBtcRequest) {
     record_event(&Event::AcceptedRetrieveBtcRequest(request.clone()));
+    state.count_redemption_request(request.block_index);
     state.pending_retrieve_btc_requests.push(request.clone());
     if let Some(account) = request.reimbursement_account {
         state
@@ -196,3 +198,114 @@ pub fn reimbursed_failed_deposit(
         },
     );
 }
+
+/// Updates the minter configuration outside of a canister upgrade.
+/// The change is recorded as an upgrade event so that it survives the event log replay.
+pub fn update_config(state: &mut MinterState, args: UpgradeArgs) {
+    record_event(&Event::Upgrade(args.clone()));
+    state.upgrade(args);
+    state.validate_config();
+}
+
+pub fn borrowed(state: &mut MinterState, ssi: String, collateral: u64, debt: u64) {
+    record_event(&Event::Borrowed {
+        ssi,
+        collateral,
+        debt,
+    });
+    state.add_loan(collateral, debt);
+}
+
+pub fn repaid(state: &mut MinterState, ssi: String, collateral: u64, debt: u64) {
+    record_event(&Event::Repaid {
+        ssi,
+        collateral,
+        debt,
+    });
+    state.remove_loan(collateral, debt);
+}
+
+pub fn accept_exchange_rate(state: &mut MinterState, rate: u64, timestamp: u64) {
+    record_event(&Event::AcceptedExchangeRate { rate, timestamp });
+    state.accept_exchange_rate(rate, timestamp);
+}
+
+pub fn reject_exchange_rate(state: &mut MinterState, rate: u64) {
+    record_event(&Event::RejectedExchangeRate { rate });
+    state.reject_exchange_rate(rate);
+}
+
+pub fn import_finalized_utxos(state: &mut MinterState, account: Account, utxos: Vec<Utxo>) {
+    record_event(&Event::ImportedFinalizedUtxos {
+        account,
+        utxos: utxos.clone(),
+    });
+    state.add_finalized_utxos(account, utxos);
+}
+
+pub fn seed_totals(state: &mut MinterState, total_collateral: u64, total_debt: u64) {
+    record_event(&Event::SeededTotals {
+        total_collateral,
+        total_debt,
+    });
+    state.seed_totals(total_collateral, total_debt);
+}
+
+pub fn add_pending_runes_deposit(
+    state: &mut MinterState,
+    ssi: String,
+    amount: u64,
+    timestamp: u64,
+    outpoints: Vec<OutPoint>,
+) {
+    record_event(&Event::PendingRunesDeposit {
+        ssi: ssi.clone(),
+        amount,
+        timestamp,
+        outpoints: outpoints.clone(),
+    });
+    state.add_pending_runes_deposit(ssi, amount, timestamp, outpoints);
+}
+
+pub fn settle_pending_runes_deposit(state: &mut MinterState, ssi: String, amount: u64) {
+    record_event(&Event::SettledRunesDeposit {
+        ssi: ssi.clone(),
+        amount,
+    });
+    state.settle_pending_runes_deposit(&ssi, amount);
+}
+
+pub fn accrue_stability_fee(state: &mut MinterState, ssi: String, fee: u64, timestamp: u64) {
+    record_event(&Event::AccruedStabilityFee {
+        ssi: ssi.clone(),
+        fee,
+        timestamp,
+    });
+    state.accrue_stability_fee(ssi, fee, timestamp);
+}
+
+pub fn set_underwater_since(state: &mut MinterState, ssi: String, since: Option<u64>) {
+    record_event(&Event::Underwater {
+        ssi: ssi.clone(),
+        since,
+    });
+    state.set_underwater_since(ssi, since);
+}
+
+pub fn attempt_mint(state: &mut MinterState, outpoint: OutPoint, attempt: MintAttempt) {
+    record_event(&Event::MintAttempted {
+        outpoint: outpoint.clone(),
+        created_at_time: attempt.created_at_time,
+        susd: attempt.susd,
+        convert_memo: attempt.convert_memo,
+    });
+    state.add_mint_attempt(outpoint, attempt);
+}
+
+pub fn settle_stability_fee(state: &mut MinterState, ssi: String, fee: u64) {
+    record_event(&Event::SettledStabilityFee {
+        ssi: ssi.clone(),
+        fee,
+    });
+    state.settle_stability_fee(&ssi, fee);
+}
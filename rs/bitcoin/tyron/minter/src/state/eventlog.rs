@@ -1,12 +1,12 @@
 use crate::lifecycle::init::InitArgs;
 use crate::lifecycle::upgrade::UpgradeArgs;
 use crate::state::{
-    ChangeOutput, MinterState, FinalizedBtcRetrieval, FinalizedStatus, Overdraft,
+    ChangeOutput, MinterState, FinalizedBtcRetrieval, FinalizedStatus, MintAttempt, Overdraft,
     RetrieveBtcRequest, SubmittedBtcTransaction, UtxoCheckStatus,
 };
 use crate::state::{ReimburseDepositTask, ReimbursedDeposit, ReimbursementReason};
 use candid::Principal;
-use ic_btc_interface::{Txid, Utxo};
+use ic_btc_interface::{OutPoint, Txid, Utxo};
 use icrc_ledger_types::icrc1::account::Account;
 use serde::{Deserialize, Serialize};
 
@@ -27,6 +27,13 @@ pub enum Event {
     #[serde(rename = "upgrade")]
     Upgrade(UpgradeArgs),
 
+    /// Indicates that the minter migrated its state to the specified version.
+    #[serde(rename = "migrated_state")]
+    MigratedState {
+        /// The version of the state after the migration.
+        state_version: u32,
+    },
+
     /// Indicates that the minter received new UTXOs to the specified account.
     /// The minter emits this event _after_ it minted ckBTC.
     #[serde(rename = "received_utxos")]
@@ -175,6 +182,130 @@ pub enum Event {
         burn_block_index: u64,
     },
 
+    /// Indicates that the minter lent SUSD against BTC collateral.
+    #[serde(rename = "borrowed")]
+    Borrowed {
+        /// The SSI of the borrower.
+        ssi: String,
+        /// The BTC collateral (in Satoshi) locked.
+        collateral: u64,
+        /// The SUSD lent.
+        debt: u64,
+    },
+
+    /// Indicates that BTC collateral was released and/or SUSD debt was repaid.
+    #[serde(rename = "repaid")]
+    Repaid {
+        /// The SSI of the borrower.
+        ssi: String,
+        /// The BTC collateral (in Satoshi) released.
+        collateral: u64,
+        /// The SUSD repaid.
+        debt: u64,
+    },
+
+    /// Indicates that the circuit breaker accepted an exchange rate.
+    #[serde(rename = "accepted_exchange_rate")]
+    AcceptedExchangeRate {
+        /// The BTC exchange rate.
+        rate: u64,
+        /// The time the rate was accepted.
+        timestamp: u64,
+    },
+
+    /// Indicates that the circuit breaker rejected an exchange rate, pending re-confirmation.
+    #[serde(rename = "rejected_exchange_rate")]
+    RejectedExchangeRate {
+        /// The BTC exchange rate.
+        rate: u64,
+    },
+
+    /// Indicates that the finalized UTXOs of an account were imported from a snapshot.
+    #[serde(rename = "imported_finalized_utxos")]
+    ImportedFinalizedUtxos {
+        /// The account of the UTXOs.
+        account: Account,
+        /// The imported UTXOs.
+        utxos: Vec<Utxo>,
+    },
+
+    /// Indicates that the totals of the loans were seeded from the ledger balances of the
+    /// boxes, so that they include the loans made before the totals were tracked.
+    #[serde(rename = "seeded_totals")]
+    SeededTotals {
+        /// The BTC collateral (in Satoshi) locked in the boxes.
+        total_collateral: u64,
+        /// The SUSD lent from the boxes.
+        total_debt: u64,
+    },
+
+    /// Indicates that a runes deposit was credited pending finalization.
+    #[serde(rename = "pending_runes_deposit")]
+    PendingRunesDeposit {
+        /// The SSI of the depositor.
+        ssi: String,
+        /// The pending amount of SUSD.
+        amount: u64,
+        /// The time of the deposit.
+        timestamp: u64,
+        /// The runes UTXOs backing the deposit.
+        #[serde(default)]
+        outpoints: Vec<OutPoint>,
+    },
+
+    /// Indicates that (part of) a pending runes deposit was finalized or reverted.
+    #[serde(rename = "settled_runes_deposit")]
+    SettledRunesDeposit {
+        /// The SSI of the depositor.
+        ssi: String,
+        /// The settled amount of SUSD.
+        amount: u64,
+    },
+
+    /// Indicates that a stability fee accrued on the loan of an SSI.
+    #[serde(rename = "accrued_stability_fee")]
+    AccruedStabilityFee {
+        /// The SSI of the borrower.
+        ssi: String,
+        /// The accrued fee in SUSD.
+        fee: u64,
+        /// The time of the accrual.
+        timestamp: u64,
+    },
+
+    /// Indicates that (part of) the accrued stability fee of an SSI was paid.
+    #[serde(rename = "settled_stability_fee")]
+    SettledStabilityFee {
+        /// The SSI of the borrower.
+        ssi: String,
+        /// The paid fee in SUSD.
+        fee: u64,
+    },
+
+    /// Indicates that the loan of an SSI was first observed below the liquidation threshold,
+    /// or that it recovered if `since` is None.
+    #[serde(rename = "underwater")]
+    Underwater {
+        /// The SSI of the borrower.
+        ssi: String,
+        /// The time the loan was first observed below the liquidation threshold.
+        since: Option<u64>,
+    },
+
+    /// Indicates the first attempt to mint for a deposited UTXO.
+    #[serde(rename = "mint_attempted")]
+    MintAttempted {
+        /// The outpoint of the deposited UTXO.
+        outpoint: OutPoint,
+        /// The ledger `created_at_time` of the mint transfers.
+        created_at_time: u64,
+        /// The SUSD lent against the UTXO.
+        susd: u64,
+        /// Whether every mint transfer carries a convert memo, see [MintAttempt::convert_memo].
+        #[serde(default)]
+        convert_memo: bool,
+    },
+
     /// Indicates that a reimbursement has been executed.
     #[serde(rename = "reimbursed_failed_deposit")]
     ReimbursedFailedDeposit {
@@ -212,6 +343,7 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<MinterState, Re
                 state.reinit(args);
             }
             Event::Upgrade(args) => state.upgrade(args),
+            Event::MigratedState { state_version } => state.migrate_to(state_version),
             Event::ReceivedUtxos {
                 is_runes, to_account, utxos, ssi_address, ..
             } => state.add_utxos(is_runes, to_account, utxos, ssi_address),
@@ -360,6 +492,52 @@ pub fn replay(mut events: impl Iterator<Item = Event>) -> Result<MinterState, Re
                     },
                 );
             }
+            Event::Borrowed {
+                collateral, debt, ..
+            } => state.add_loan(collateral, debt),
+            Event::Repaid {
+                collateral, debt, ..
+            } => state.remove_loan(collateral, debt),
+            Event::AcceptedExchangeRate { rate, timestamp } => {
+                state.accept_exchange_rate(rate, timestamp)
+            }
+            Event::RejectedExchangeRate { rate } => state.reject_exchange_rate(rate),
+            Event::ImportedFinalizedUtxos { account, utxos } => {
+                state.add_finalized_utxos(account, utxos)
+            }
+            Event::SeededTotals {
+                total_collateral,
+                total_debt,
+            } => state.seed_totals(total_collateral, total_debt),
+            Event::PendingRunesDeposit {
+                ssi,
+                amount,
+                timestamp,
+                outpoints,
+            } => state.add_pending_runes_deposit(ssi, amount, timestamp, outpoints),
+            Event::SettledRunesDeposit { ssi, amount } => {
+                state.settle_pending_runes_deposit(&ssi, amount)
+            }
+            Event::AccruedStabilityFee {
+                ssi,
+                fee,
+                timestamp,
+            } => state.accrue_stability_fee(ssi, fee, timestamp),
+            Event::SettledStabilityFee { ssi, fee } => state.settle_stability_fee(&ssi, fee),
+            Event::Underwater { ssi, since } => state.set_underwater_since(ssi, since),
+            Event::MintAttempted {
+                outpoint,
+                created_at_time,
+                susd,
+                convert_memo,
+            } => state.add_mint_attempt(
+                outpoint,
+                MintAttempt {
+                    created_at_time,
+                    susd,
+                    convert_memo,
+                },
+            ),
             Event::ReimbursedFailedDeposit {
                 burn_block_index,
                 mint_block_index,
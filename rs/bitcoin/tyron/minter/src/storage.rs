@@ -1,15 +1,31 @@
 use crate::state::eventlog::Event;
+use candid::CandidType;
+use ic_btc_interface::OutPoint;
 use ic_stable_structures::{
     log::{Log as StableLog, NoSuchEntry},
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
     DefaultMemoryImpl,
 };
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
+// @dev every stable structure of the canister takes its memory from the MEMORY_MANAGER below,
+// so the ids must stay disjoint across modules
 const LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(0);
 const LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(1);
+const PROTOCOL_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(2);
+const PROTOCOL_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub(crate) const PROVIDER_METADATA_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub(crate) const AUTHORIZED_PRINCIPALS_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub(crate) const PROVIDERS_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub(crate) const SERVICE_PROVIDER_MAP_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub(crate) const ALLOWED_HOSTNAMES_MEMORY_ID: MemoryId = MemoryId::new(8);
 
-type VMem = VirtualMemory<DefaultMemoryImpl>;
+/// The version of the [ProtocolEvent] layout returned by [blocks], bumped whenever the
+/// layout changes in a way that indexers must account for.
+pub const BLOCK_SCHEMA_VERSION: u32 = 1;
+
+pub(crate) type VMem = VirtualMemory<DefaultMemoryImpl>;
 type EventLog = StableLog<Vec<u8>, VMem, VMem>;
 
 thread_local! {
@@ -27,6 +43,66 @@ thread_local! {
                   ).expect("failed to initialize stable log")
               )
         );
+
+    /// The append-only log of the mints, redemptions and repayments for off-chain indexers.
+    static PROTOCOL_EVENTS: RefCell<EventLog> = MEMORY_MANAGER
+        .with(|m|
+              RefCell::new(
+                  StableLog::init(
+                      m.borrow().get(PROTOCOL_LOG_INDEX_MEMORY_ID),
+                      m.borrow().get(PROTOCOL_LOG_DATA_MEMORY_ID)
+                  ).expect("failed to initialize the protocol event log")
+              )
+        );
+}
+
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProtocolEventKind {
+    /// SUSD was minted against a bitcoin deposit.
+    Mint,
+    /// The bitcoin collateral was redeemed.
+    Redeem,
+    /// SUSD was burned to repay a loan.
+    Repay,
+    /// A liquidator repaid (part of) a loan below the liquidation threshold.
+    Liquidation,
+}
+
+/// A structured record of a successful protocol operation.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolEvent {
+    pub kind: ProtocolEventKind,
+    pub ssi: String,
+    /// The amount of the operation (satoshis for mints and redemptions, SUSD for repayments
+    /// and liquidations).
+    pub amount: u64,
+    /// The index of the ledger transaction, i.e. the collateral credited to the box on the
+    /// SBTC ledger for mints.
+    pub block_index: u64,
+    /// The time of the operation in nanoseconds since the epoch.
+    pub timestamp: u64,
+    /// The bitcoin output backing a mint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outpoint: Option<OutPoint>,
+    /// The bitcoin address a redemption was sent to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub btc_address: Option<String>,
+}
+
+/// A protocol event together with its index in the protocol event log.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct MinterBlock {
+    pub id: u64,
+    pub block: ProtocolEvent,
+}
+
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct GetBlocksResult {
+    /// The total number of entries in the protocol event log.
+    pub log_length: u64,
+    /// See [BLOCK_SCHEMA_VERSION].
+    pub schema_version: u32,
+    pub blocks: Vec<MinterBlock>,
 }
 
 pub struct EventIterator {
@@ -57,6 +133,11 @@ impl Iterator for EventIterator {
     }
 }
 
+/// Returns the virtual memory with the given id of the shared memory manager.
+pub(crate) fn memory(id: MemoryId) -> VMem {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
 /// Encodes an event into a byte array.
 fn encode_event(event: &Event) -> Vec<u8> {
     let mut buf = Vec::new();
@@ -94,3 +175,99 @@ pub fn record_event(event: &Event) {
             .expect("failed to append an entry to the event log")
     });
 }
+
+/// Records a new protocol event.
+pub fn record_protocol_event(event: &ProtocolEvent) {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(event, &mut bytes).expect("failed to encode a protocol event");
+    PROTOCOL_EVENTS.with(|events| {
+        events
+            .borrow()
+            .append(&bytes)
+            .expect("failed to append an entry to the protocol event log")
+    });
+}
+
+/// Returns at most `length` protocol events starting at `start`, along with the cursor
+/// to pass as `start` to read the next page.
+pub fn protocol_events(start: u64, length: u64) -> (Vec<ProtocolEvent>, u64) {
+    PROTOCOL_EVENTS.with(|events| {
+        let events = events.borrow();
+        let end = start.saturating_add(length).min(events.len());
+        let mut buf = vec![];
+        let page: Vec<ProtocolEvent> = (start..end)
+            .map(|pos| {
+                events
+                    .read_entry(pos, &mut buf)
+                    .expect("BUG: protocol event index out of range");
+                ciborium::de::from_reader(buf.as_slice()).expect("failed to decode a protocol event")
+            })
+            .collect();
+        (page, start.max(end))
+    })
+}
+
+/// Returns at most `length` entries of the protocol event log starting at `start`.
+/// Entries are never removed from the log, so their indices are stable.
+pub fn blocks(start: u64, length: u64) -> GetBlocksResult {
+    let (events, _) = protocol_events(start, length);
+    GetBlocksResult {
+        log_length: PROTOCOL_EVENTS.with(|events| events.borrow().len()),
+        schema_version: BLOCK_SCHEMA_VERSION,
+        blocks: events
+            .into_iter()
+            .zip(start..)
+            .map(|(block, id)| MinterBlock { id, block })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol_event(amount: u64) -> ProtocolEvent {
+        ProtocolEvent {
+            kind: ProtocolEventKind::Mint,
+            ssi: "ssi".to_string(),
+            amount,
+            block_index: amount,
+            timestamp: 0,
+            outpoint: None,
+            btc_address: None,
+        }
+    }
+
+    #[test]
+    fn blocks_should_keep_the_log_indices() {
+        for amount in [10, 20, 30] {
+            record_protocol_event(&protocol_event(amount));
+        }
+
+        let page = blocks(1, 10);
+        assert_eq!(page.log_length, 3);
+        assert_eq!(page.schema_version, BLOCK_SCHEMA_VERSION);
+        assert_eq!(
+            page.blocks,
+            vec![
+                MinterBlock { id: 1, block: protocol_event(20) },
+                MinterBlock { id: 2, block: protocol_event(30) },
+            ]
+        );
+        assert!(blocks(3, 10).blocks.is_empty());
+    }
+
+    #[test]
+    fn provider_maps_should_not_overlap_the_event_logs() {
+        use crate::https::provider::{get_allowed_hostnames, set_allowed_hostnames};
+
+        set_allowed_hostnames(vec!["main.tyron.io/".to_string()]);
+        for amount in 0..100 {
+            record_protocol_event(&protocol_event(amount));
+        }
+
+        assert_eq!(get_allowed_hostnames(), vec!["main.tyron.io/".to_string()]);
+        assert_eq!(blocks(0, 200).log_length, 100);
+        assert_eq!(blocks(99, 1).blocks[0].block, protocol_event(99));
+    }
+}
@@ -13,6 +13,7 @@ pub enum TaskType {
     ProcessLogic,
     RefreshFeePercentiles,
     DistributeKytFee,
+    RevertStaleRunesDeposits,
     // @review (xrc) fetch bitcoin price
 }
 
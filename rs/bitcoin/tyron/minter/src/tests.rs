@@ -373,6 +373,19 @@ fn test_no_dust_outputs() {
     assert_eq!(available_utxos.len(), 1);
 }
 
+#[test]
+fn fee_at_percentile_requires_all_the_percentiles() {
+    use crate::fee_at_percentile;
+
+    let fees: Vec<u64> = (0..100).map(|i| 1_000 + i).collect();
+    assert_eq!(fee_at_percentile(&fees, 50), Some(1_050));
+    assert_eq!(fee_at_percentile(&fees, 0), Some(1_000));
+    assert_eq!(fee_at_percentile(&fees, 99), Some(1_099));
+    assert_eq!(fee_at_percentile(&fees, 100), None);
+    assert_eq!(fee_at_percentile(&fees[..99], 50), None);
+    assert_eq!(fee_at_percentile(&[], 50), None);
+}
+
 #[test]
 fn blocklist_is_sorted() {
     use crate::blocklist::BTC_ADDRESS_BLOCKLIST;
@@ -1,6 +1,7 @@
 pub mod get_btc_address;
 pub mod get_withdrawal_account;
 pub mod retrieve_btc;
+pub mod types;
 pub mod update_balance;
 
 pub use get_btc_address::get_btc_address;
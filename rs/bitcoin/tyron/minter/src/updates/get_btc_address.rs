@@ -1,5 +1,5 @@
 use crate::{
-    address::BitcoinAddress, logs::P1, management::{get_siwb_principal, CallError}, state::{mutate_state, read_state, MinterState}, ECDSAPublicKey
+    address::BitcoinAddress, logs::P1, management::{get_siwb_principal, CallError}, state::{mutate_state, read_state, MinterState, Network}, ECDSAPublicKey
 };
 use candid::{CandidType, Deserialize, Principal};
 use ic_base_types::PrincipalId;
@@ -7,8 +7,11 @@ use ic_canister_log::log;
 use ic_management_canister_types::DerivationPath;
 use icrc_ledger_types::icrc1::account::{Account, Subaccount};
 use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
 
-use super::get_withdrawal_account::compute_subaccount;
+use super::get_withdrawal_account::{minter_account, SubaccountKind};
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct GetBtcAddressArgs {
@@ -16,7 +19,7 @@ pub struct GetBtcAddressArgs {
     pub subaccount: Option<Subaccount>,
 }
 
-#[derive(CandidType, Clone, Copy, Deserialize, Debug, Eq, PartialEq, Serialize, Hash)]
+#[derive(CandidType, Clone, Copy, Deserialize, Debug, Eq, PartialEq, Serialize, Hash, PartialOrd, Ord)]
 pub enum SyronOperation {
     #[serde(rename = "getsyron")]
     GetSyron,
@@ -27,34 +30,170 @@ pub enum SyronOperation {
     #[serde(rename = "payment")]
     Payment,
     #[serde(rename = "depositsyron")]
-    DepositSyron
+    DepositSyron,
+    /// Credits the bitcoin collateral of new deposits without minting SUSD,
+    /// to improve the collateral ratio of the loan.
+    #[serde(rename = "addcollateral")]
+    AddCollateral
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct GetBoxAddressArgs {
     pub ssi: String,
-    pub op: SyronOperation
+    pub op: SyronOperation,
+    /// The amount of the operation, if it supports partial amounts (e.g. the BTC to redeem
+    /// or the pending runes deposit to finalize).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+    /// The number of confirmations required for the deposits, if stricter than the minter's
+    /// minimum. The stricter of the two always applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_confirmations: Option<u32>,
+    /// The owner of the account credited with the borrowed SUSD, if not the SSI balance
+    /// subaccount. Defaults to the caller if only the subaccount is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Principal>,
+    /// The subaccount credited with the borrowed SUSD, see [owner].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subaccount: Option<Subaccount>,
+    /// The Bitcoin address that receives the redeemed BTC. If set, the minter sends the
+    /// redeemed collateral on the Bitcoin network instead of only burning the SBTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub btc_address: Option<String>,
+}
+
+/// A minter account of an SSI and its bitcoin address.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SsiAccount {
+    pub account: Account,
+    pub address: String,
+}
+
+/// All the minter accounts of an SSI, see [SubaccountKind].
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SsiAccounts {
+    pub swap: SsiAccount,
+    /// The box holding the bitcoin collateral and the SUSD loan. Its address is the
+    /// deposit address returned by [get_box_address].
+    pub ssi_box: SsiAccount,
+    pub balance: SsiAccount,
+    pub withdrawal: SsiAccount,
+    pub runes: SsiAccount,
+    pub pending_runes: SsiAccount,
+}
+
+/// The deposit addresses of an SSI on the minter's network.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DepositAddresses {
+    /// The address that [get_box_address] returns, where deposits get SUSD.
+    pub ssi_box: String,
+    /// The P2WPKH address of the box account itself, without the SSI in the derivation.
+    pub vault: String,
+    /// The runes minter address, if the SSI is the treasury address.
+    pub runes_minter: Option<String>,
+}
+
+/// The maximum number of derived addresses kept in the [AddressCache].
+const MAX_CACHED_ADDRESSES: usize = 10_000;
+
+type AddressKey = (Account, Option<String>);
+
+/// The derived P2WPKH addresses, keyed by account and SSI (if part of the derivation).
+///
+/// The addresses are only valid for the network and the ECDSA public key they were
+/// derived with, and the cache is cleared whenever either changes. At most
+/// [MAX_CACHED_ADDRESSES] addresses are kept, evicting the least recently used.
+#[derive(Default)]
+struct AddressCache {
+    derived_with: Option<(Network, ECDSAPublicKey)>,
+    /// The addresses and the tick of their last use.
+    addresses: BTreeMap<AddressKey, (String, u64)>,
+    /// The keys of the addresses by the tick of their last use.
+    recency: BTreeMap<u64, AddressKey>,
+    tick: u64,
+}
+
+impl AddressCache {
+    fn clear(&mut self) {
+        self.addresses.clear();
+        self.recency.clear();
+    }
+
+    fn get_or_insert_with(
+        &mut self,
+        key: AddressKey,
+        capacity: usize,
+        derive: impl FnOnce() -> String,
+    ) -> String {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((address, last_used)) = self.addresses.get_mut(&key) {
+            self.recency.remove(last_used);
+            *last_used = tick;
+            let address = address.clone();
+            self.recency.insert(tick, key);
+            return address;
+        }
+        while self.addresses.len() >= capacity.max(1) {
+            match self.recency.pop_first() {
+                Some((_, evicted)) => self.addresses.remove(&evicted),
+                None => break,
+            };
+        }
+        let address = derive();
+        self.addresses.insert(key.clone(), (address.clone(), tick));
+        self.recency.insert(tick, key);
+        address
+    }
+}
+
+thread_local! {
+    static ADDRESS_CACHE: RefCell<AddressCache> = RefCell::default();
+}
+
+/// Returns the cached address of the account, or derives and caches it.
+fn cached_address(
+    network: Network,
+    ecdsa_public_key: &ECDSAPublicKey,
+    account: &Account,
+    ssi: Option<&str>,
+    derive: impl FnOnce() -> String,
+) -> String {
+    ADDRESS_CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        let derived_with = (network, ecdsa_public_key.clone());
+        if cache.derived_with.as_ref() != Some(&derived_with) {
+            cache.clear();
+            cache.derived_with = Some(derived_with);
+        }
+        cache.get_or_insert_with(
+            (*account, ssi.map(str::to_string)),
+            MAX_CACHED_ADDRESSES,
+            derive,
+        )
+    })
 }
 
 /// PRECONDITION: s.ecdsa_public_key.is_some()
 pub fn account_to_p2wpkh_address_from_state(s: &MinterState, account: &Account) -> String {
-    crate::address::account_to_p2wpkh_address(
-        s.btc_network,
-        s.ecdsa_public_key
-            .as_ref()
-            .expect("bug: the ECDSA public key must be initialized"),
-        account,
-    )
+    let ecdsa_public_key = s
+        .ecdsa_public_key
+        .as_ref()
+        .expect("bug: the ECDSA public key must be initialized");
+    cached_address(s.btc_network, ecdsa_public_key, account, None, || {
+        crate::address::account_to_p2wpkh_address(s.btc_network, ecdsa_public_key, account)
+    })
 }
 
+/// PRECONDITION: s.ecdsa_public_key.is_some()
 pub fn ssi_account_to_p2wpkh_address_from_state(s: &MinterState, account: &Account, ssi: &str) -> String {
-    crate::address::ssi_account_to_p2wpkh_address(
-        s.ecdsa_public_key
-            .as_ref()
-            .expect("bug: the ECDSA public key must be initialized"),
-        account,
-        ssi
-    )
+    let ecdsa_public_key = s
+        .ecdsa_public_key
+        .as_ref()
+        .expect("bug: the ECDSA public key must be initialized");
+    cached_address(s.btc_network, ecdsa_public_key, account, Some(ssi), || {
+        crate::address::ssi_account_to_p2wpkh_address(ecdsa_public_key, account, ssi)
+    })
 }
 
 pub async fn get_btc_address(args: GetBtcAddressArgs) -> String {
@@ -84,27 +223,11 @@ pub async fn get_box_address(args: GetBoxAddressArgs) -> String {
         }
     }
 
-    let minter = ic_cdk::id();
+    let ssi = parse_ssi(&args.ssi, "get_box_address");
 
-    // @dev Validate that the SSI is a valid Bitcoin address
-    let network = read_state(|s| (s.btc_network));
-    let ssi = match BitcoinAddress::parse(&args.ssi, network) {
-        Ok(ssi) => {
-            let addr = ssi.display(network);
-            ic_cdk::println!("@get_box_address: Given SSI = {}; Parsed Bitcoin Address = {}", &args.ssi, addr);
-            addr
-        },
-        Err(e) => {
-            ic_cdk::trap(&format!("@get_box_address: Invalid SSI, error = {e}"))
-        }       
-    };
-    
-    let ssi_box_subaccount: Subaccount = compute_subaccount(1, &ssi);
+    init_ecdsa_public_key().await;
 
-    let ssi_box_account =  &Account {
-        owner: minter,
-        subaccount: Some(ssi_box_subaccount)
-    };
+    let ssi_box_account = &minter_account(SubaccountKind::Box, &ssi);
 
     ic_cdk::println!("Getting Box address for Account ({}) with nonce ({})...", ssi_box_account, "1");
 
@@ -117,28 +240,197 @@ pub async fn get_box_address(args: GetBoxAddressArgs) -> String {
     })
 }
 
+/// Validates that the SSI is a valid Bitcoin address and returns its canonical form.
+/// Traps otherwise.
+fn parse_ssi(ssi: &str, method: &str) -> String {
+    let network = read_state(|s| s.btc_network);
+    match BitcoinAddress::parse(ssi, network) {
+        Ok(parsed) => {
+            let addr = parsed.display(network);
+            ic_cdk::println!("@{}: Given SSI = {}; Parsed Bitcoin Address = {}", method, ssi, addr);
+            addr
+        }
+        Err(e) => ic_cdk::trap(&format!("@{method}: Invalid SSI, error = {e}")),
+    }
+}
+
+/// Derives all the minter accounts of the given SSI and their bitcoin addresses.
+pub async fn get_ssi_accounts(ssi: String) -> SsiAccounts {
+    let ssi = parse_ssi(&ssi, "get_ssi_accounts");
+
+    init_ecdsa_public_key().await;
+
+    let account_of = |kind: SubaccountKind| minter_account(kind, &ssi);
+
+    read_state(|s| {
+        let entry = |kind: SubaccountKind| {
+            let account = account_of(kind);
+            SsiAccount {
+                address: account_to_p2wpkh_address_from_state(s, &account),
+                account,
+            }
+        };
+        let ssi_box = account_of(SubaccountKind::Box);
+        SsiAccounts {
+            swap: entry(SubaccountKind::Swap),
+            ssi_box: SsiAccount {
+                address: ssi_account_to_p2wpkh_address_from_state(s, &ssi_box, &ssi),
+                account: ssi_box,
+            },
+            balance: entry(SubaccountKind::Balance),
+            withdrawal: entry(SubaccountKind::Withdrawal),
+            runes: entry(SubaccountKind::Runes),
+            pending_runes: entry(SubaccountKind::PendingRunes),
+        }
+    })
+}
+
+/// Returns all the deposit addresses of the given SSI.
+///
+/// Does not initialize the ECDSA public key, so that it can run in queries. Traps if the
+/// key is not cached yet; [get_box_address] and [get_ssi_accounts] initialize it.
+pub fn get_deposit_addresses(ssi: String) -> DepositAddresses {
+    let ssi = parse_ssi(&ssi, "get_deposit_addresses");
+    let ssi_box = minter_account(SubaccountKind::Box, &ssi);
+
+    read_state(|s| {
+        if s.ecdsa_public_key.is_none() {
+            ic_cdk::trap("@get_deposit_addresses: the ECDSA public key is not initialized yet, call get_ssi_accounts first");
+        }
+        let runes_minter = match (s.dao_addr.get(1), s.dao_addr.get(2)) {
+            (Some(treasury), Some(runes_minter)) if treasury.display(s.btc_network) == ssi => {
+                Some(runes_minter.display(s.btc_network))
+            }
+            _ => None,
+        };
+        DepositAddresses {
+            ssi_box: ssi_account_to_p2wpkh_address_from_state(s, &ssi_box, &ssi),
+            vault: account_to_p2wpkh_address_from_state(s, &ssi_box),
+            runes_minter,
+        }
+    })
+}
+
 /// Initializes the Minter ECDSA public key. This function must be called
 /// before any endpoint runs its logic.
+///
+/// The key never changes, so only the first call after an install or an upgrade calls
+/// the management canister; later calls return the key cached in the state.
 pub async fn init_ecdsa_public_key() -> ECDSAPublicKey {
+    init_ecdsa_public_key_with(|key_name| {
+        crate::management::ecdsa_public_key(key_name, DerivationPath::new(vec![]))
+    })
+    .await
+}
+
+async fn init_ecdsa_public_key_with<F, Fut>(fetch: F) -> ECDSAPublicKey
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<ECDSAPublicKey, CallError>>,
+{
     if let Some(key) = read_state(|s| s.ecdsa_public_key.clone()) {
         return key;
     };
     let key_name = read_state(|s| s.ecdsa_key_name.clone());
     log!(P1, "Fetching the ECDSA public key {}", &key_name);
-    let ecdsa_public_key =
-        crate::management::ecdsa_public_key(key_name, DerivationPath::new(vec![]))
-            .await
-            .unwrap_or_else(|e| ic_cdk::trap(&format!("failed to retrieve ECDSA public key: {e}")));
+    let ecdsa_public_key = fetch(key_name)
+        .await
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("failed to retrieve ECDSA public key: {e}")));
     log!(
         P1,
         "ECDSA public key set to {}, chain code to {}",
         hex::encode(&ecdsa_public_key.public_key),
         hex::encode(&ecdsa_public_key.chain_code)
     );
+    // A concurrent call may have initialized the key while this one was waiting.
     mutate_state(|s| {
-        s.ecdsa_public_key = Some(ecdsa_public_key.clone());
-    });
-    ecdsa_public_key
+        s.ecdsa_public_key
+            .get_or_insert_with(|| ecdsa_public_key.clone())
+            .clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn key(byte: u8) -> ECDSAPublicKey {
+        ECDSAPublicKey {
+            public_key: vec![byte; 33],
+            chain_code: vec![0; 32],
+        }
+    }
+
+    fn init_state() {
+        crate::state::replace_state(MinterState::from(crate::lifecycle::init::test_init_args()));
+    }
+
+    #[test]
+    fn ecdsa_public_key_should_be_fetched_once() {
+        init_state();
+        let fetches = Cell::new(0);
+        let fetch = |key_name: String| {
+            assert_eq!(key_name, "some_key");
+            fetches.set(fetches.get() + 1);
+            async { Ok(key(2)) }
+        };
+
+        let first = futures::executor::block_on(init_ecdsa_public_key_with(fetch));
+        let second = futures::executor::block_on(init_ecdsa_public_key_with(fetch));
+
+        assert_eq!(first, key(2));
+        assert_eq!(second, key(2));
+        assert_eq!(fetches.get(), 1);
+        assert_eq!(read_state(|s| s.ecdsa_public_key.clone()), Some(key(2)));
+    }
+
+    #[test]
+    fn cached_address_should_be_invalidated_by_network_and_key_changes() {
+        let account = Account {
+            owner: Principal::anonymous(),
+            subaccount: Some([1; 32]),
+        };
+        let address = |network, key: &ECDSAPublicKey, derived: &str| {
+            cached_address(network, key, &account, Some("ssi"), || derived.to_string())
+        };
+
+        assert_eq!(address(Network::Testnet, &key(2), "tb1-a"), "tb1-a");
+        // Served from the cache.
+        assert_eq!(address(Network::Testnet, &key(2), "tb1-b"), "tb1-a");
+        // Another network or key derives the address again.
+        assert_eq!(address(Network::Mainnet, &key(2), "bc1-a"), "bc1-a");
+        assert_eq!(address(Network::Mainnet, &key(3), "bc1-b"), "bc1-b");
+        // The SSI is part of the key.
+        assert_eq!(
+            cached_address(Network::Mainnet, &key(3), &account, None, || "bc1-c".to_string()),
+            "bc1-c"
+        );
+    }
+
+    #[test]
+    fn address_cache_should_evict_the_least_recently_used() {
+        let key = |n: u8| {
+            (
+                Account {
+                    owner: Principal::anonymous(),
+                    subaccount: Some([n; 32]),
+                },
+                None,
+            )
+        };
+        let mut cache = AddressCache::default();
+        assert_eq!(cache.get_or_insert_with(key(1), 2, || "a".to_string()), "a");
+        assert_eq!(cache.get_or_insert_with(key(2), 2, || "b".to_string()), "b");
+        // Using the first address makes the second one the least recently used.
+        assert_eq!(cache.get_or_insert_with(key(1), 2, || "x".to_string()), "a");
+        assert_eq!(cache.get_or_insert_with(key(3), 2, || "c".to_string()), "c");
+
+        assert_eq!(cache.addresses.len(), 2);
+        assert_eq!(cache.recency.len(), 2);
+        assert_eq!(cache.get_or_insert_with(key(1), 2, || "x".to_string()), "a");
+        assert_eq!(cache.get_or_insert_with(key(2), 2, || "d".to_string()), "d");
+    }
 }
 
 // @review (test)
@@ -8,7 +8,7 @@ use super::get_btc_address::init_ecdsa_public_key;
 pub async fn get_withdrawal_account() -> Account {
     init_ecdsa_public_key().await;
     let minter = ic_cdk::id();
-    let subaccount: Subaccount = compute_subaccount(0, ""); // @review (burn)
+    let subaccount: Subaccount = compute_ssi_subaccount(SubaccountKind::Swap, ""); // @review (burn)
     // Check that the computed subaccount doesn't collide with minting account.
     if &subaccount == DEFAULT_SUBACCOUNT {
         panic!(
@@ -22,13 +22,95 @@ pub async fn get_withdrawal_account() -> Account {
     }
 }
 
+/// The kinds of minter subaccounts derived for an SSI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubaccountKind {
+    /// Swaps and burns (nonce 0).
+    Swap,
+    /// The box holding the bitcoin collateral and the SUSD loan (nonce 1).
+    Box,
+    /// The available SUSD balance (nonce 2).
+    Balance,
+    /// SUSD withdrawn to get bitcoin (nonce 3).
+    Withdrawal,
+    /// SUSD withdrawn to get runes (nonce 4).
+    Runes,
+    /// Runes deposits pending finalization (nonce 5).
+    PendingRunes,
+}
+
+impl SubaccountKind {
+    /// Returns the nonce used to derive subaccounts of this kind.
+    pub const fn nonce(&self) -> u64 {
+        match self {
+            SubaccountKind::Swap => 0,
+            SubaccountKind::Box => 1,
+            SubaccountKind::Balance => 2,
+            SubaccountKind::Withdrawal => 3,
+            SubaccountKind::Runes => 4,
+            SubaccountKind::PendingRunes => 5,
+        }
+    }
+}
+
+/// Compute the subaccount of the minter of the given kind for the SSI
+pub fn compute_ssi_subaccount(kind: SubaccountKind, ssi: &str) -> Subaccount {
+    compute_subaccount(kind.nonce(), ssi)
+}
+
+/// Returns the minter ledger account of the given kind for the SSI.
+pub fn minter_account(kind: SubaccountKind, ssi: &str) -> Account {
+    minter_account_with_nonce(kind.nonce(), ssi)
+}
+
+/// Returns the minter ledger account derived with the given nonce for the SSI.
+pub fn minter_account_with_nonce(nonce: u64, ssi: &str) -> Account {
+    minter_account_of(PrincipalId(ic_cdk::id()), nonce, ssi)
+}
+
+fn minter_account_of(minter: PrincipalId, nonce: u64, ssi: &str) -> Account {
+    Account {
+        owner: minter.0,
+        subaccount: Some(compute_versioned_subaccount(SUBACCOUNT_SCHEME_VERSION, minter, nonce, ssi)),
+    }
+}
+
+/// The version of the subaccount derivation scheme used by the minter.
+///
+/// CONSENSUS-CRITICAL: every ledger account of every SSI is derived with this scheme, so
+/// changing it (or the preimage of any existing version) moves all funds out of reach.
+/// A new scheme must get a new version, and the accounts derived with older versions must
+/// be kept reachable.
+pub const SUBACCOUNT_SCHEME_VERSION: u8 = 0;
+
 /// Compute the subaccount of the minter based on a given nonce and SSI
 pub fn compute_subaccount(nonce: u64, ssi: &str) -> Subaccount {
-    let minter = PrincipalId(ic_cdk::id());
+    compute_versioned_subaccount(
+        SUBACCOUNT_SCHEME_VERSION,
+        PrincipalId(ic_cdk::id()),
+        nonce,
+        ssi,
+    )
+}
+
+/// Compute the subaccount of the given minter with the given version of the derivation scheme.
+///
+/// Version 0 hashes `[0x05] || "syron" || minter || nonce (big-endian) || ssi`. Later
+/// versions prefix this preimage with the version byte, so that they can never collide
+/// with version 0.
+fn compute_versioned_subaccount(
+    version: u8,
+    minter: PrincipalId,
+    nonce: u64,
+    ssi: &str,
+) -> Subaccount {
     const DOMAIN: &[u8] = b"syron";
     const DOMAIN_LENGTH: [u8; 1] = [0x05];
 
     let mut hasher = Sha256::new();
+    if version != 0 {
+        hasher.write(&[version]);
+    }
     hasher.write(&DOMAIN_LENGTH);
     hasher.write(DOMAIN);
     hasher.write(minter.as_slice());
@@ -39,8 +121,12 @@ pub fn compute_subaccount(nonce: u64, ssi: &str) -> Subaccount {
 
 #[cfg(test)]
 mod tests {
-    use crate::updates::get_withdrawal_account::compute_subaccount;
+    use crate::updates::get_withdrawal_account::{
+        compute_subaccount, compute_versioned_subaccount, minter_account_of, SubaccountKind,
+        SUBACCOUNT_SCHEME_VERSION,
+    };
     use ic_base_types::PrincipalId;
+    use icrc_ledger_types::icrc1::account::Account;
     use std::str::FromStr;
 
     #[test]
@@ -52,4 +138,98 @@ mod tests {
         ];
         assert_eq!(expected, compute_subaccount(0, "")); //@review (burn)
     }
+
+    #[test]
+    fn test_compute_subaccount_with_ssi() {
+        let pid: PrincipalId = PrincipalId::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let ssi = "bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh";
+        let vectors: [(u64, &str, [u8; 32]); 5] = [
+            (
+                0,
+                "",
+                [
+                    216, 108, 90, 58, 28, 254, 103, 165, 94, 179, 175, 142, 205, 202, 118, 232,
+                    178, 170, 103, 185, 78, 192, 246, 99, 123, 211, 116, 16, 75, 140, 20, 147,
+                ],
+            ),
+            (
+                1,
+                ssi,
+                [
+                    67, 191, 91, 63, 21, 133, 235, 141, 119, 123, 231, 144, 38, 112, 236, 88, 236,
+                    253, 158, 176, 13, 40, 132, 121, 131, 140, 192, 136, 113, 222, 83, 11,
+                ],
+            ),
+            (
+                2,
+                ssi,
+                [
+                    23, 220, 30, 223, 145, 191, 222, 128, 220, 176, 165, 93, 13, 87, 137, 211, 122,
+                    138, 200, 205, 182, 135, 155, 151, 35, 42, 84, 108, 42, 212, 115, 95,
+                ],
+            ),
+            (
+                4,
+                ssi,
+                [
+                    97, 16, 250, 143, 229, 94, 42, 189, 52, 116, 121, 237, 251, 10, 2, 215, 147,
+                    227, 172, 121, 187, 199, 118, 236, 25, 204, 97, 196, 113, 168, 184, 28,
+                ],
+            ),
+            (
+                5,
+                ssi,
+                [
+                    105, 190, 71, 230, 255, 77, 139, 110, 43, 26, 200, 14, 31, 247, 132, 68, 161,
+                    150, 130, 57, 125, 6, 184, 104, 124, 185, 216, 189, 167, 153, 102, 102,
+                ],
+            ),
+        ];
+        for (nonce, ssi, expected) in vectors {
+            assert_eq!(
+                expected,
+                compute_versioned_subaccount(0, pid, nonce, ssi),
+                "nonce {nonce}, ssi {ssi:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn subaccount_scheme_versions_should_not_collide() {
+        let pid: PrincipalId = PrincipalId::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        let ssi = "bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh";
+        assert_eq!(SUBACCOUNT_SCHEME_VERSION, 0);
+        assert_ne!(
+            compute_versioned_subaccount(0, pid, 1, ssi),
+            compute_versioned_subaccount(1, pid, 1, ssi)
+        );
+        assert_ne!(
+            compute_versioned_subaccount(1, pid, 1, ssi),
+            compute_versioned_subaccount(2, pid, 1, ssi)
+        );
+    }
+
+    #[test]
+    fn subaccount_kinds_should_map_to_the_documented_nonces() {
+        assert_eq!(SubaccountKind::Swap.nonce(), 0);
+        assert_eq!(SubaccountKind::Box.nonce(), 1);
+        assert_eq!(SubaccountKind::Balance.nonce(), 2);
+        assert_eq!(SubaccountKind::Withdrawal.nonce(), 3);
+        assert_eq!(SubaccountKind::Runes.nonce(), 4);
+        assert_eq!(SubaccountKind::PendingRunes.nonce(), 5);
+    }
+
+    #[test]
+    fn minter_account_should_match_the_manual_construction() {
+        let pid: PrincipalId = PrincipalId::from_str("2chl6-4hpzw-vqaaa-aaaaa-c").unwrap();
+        for ssi in ["", "bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh", "tb1qexample"] {
+            for nonce in 0..=5 {
+                let expected = Account {
+                    owner: pid.0,
+                    subaccount: Some(compute_versioned_subaccount(0, pid, nonce, ssi)),
+                };
+                assert_eq!(minter_account_of(pid, nonce, ssi), expected, "nonce {nonce}, ssi {ssi:?}");
+            }
+        }
+    }
 }
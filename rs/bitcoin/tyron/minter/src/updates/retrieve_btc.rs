@@ -1,6 +1,6 @@
 use super::{
     // get_btc_address::init_ecdsa_public_key, 
-    get_withdrawal_account::compute_subaccount};
+    get_withdrawal_account::{compute_ssi_subaccount, compute_subaccount, SubaccountKind}};
 use crate::logs::P0;
 use crate::logs::P1;
 use crate::management::fetch_withdrawal_alerts;
@@ -29,7 +29,7 @@ use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromErro
 use num_traits::cast::ToPrimitive;
 use std::cmp::max;
 
-const MAX_CONCURRENT_PENDING_REQUESTS: usize = 1000;
+pub(crate) const MAX_CONCURRENT_PENDING_REQUESTS: usize = 1000;
 
 /// The arguments of the [retrieve_btc] endpoint.
 #[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -64,7 +64,14 @@ pub enum ErrorCode {
     // The retrieval address didn't pass the KYT check.
     TaintedAddress = 1,
     KytCallFailed = 2,
-    ReservedMinterNonce = 3
+    ReservedMinterNonce = 3,
+    // A value returned by the ledger does not fit into u64.
+    LedgerValueOverflow = 4,
+}
+
+/// Converts a value returned by the ledger to u64, or returns an error message naming it.
+fn ledger_value_to_u64(value: Nat, name: &str) -> Result<u64, String> {
+    value.0.to_u64().ok_or_else(|| format!("the ledger {} does not fit into u64", name))
 }
 
 pub enum SyronLedger {
@@ -183,8 +190,8 @@ pub async fn retrieve_btc(args: RetrieveBtcArgs, minter_nonce: u64, ssi_nonce: u
     let ssi = &args.address;
     let parsed_address = BitcoinAddress::parse(ssi, btc_network)?;
     
-    let ssi_subaccount = compute_subaccount(0, ssi);
-    let balance_subaccount = compute_subaccount(2, ssi);
+    let ssi_subaccount = compute_ssi_subaccount(SubaccountKind::Swap, ssi);
+    let balance_subaccount = compute_ssi_subaccount(SubaccountKind::Balance, ssi);
     
     let ssi_account = Account {
         owner: ic_cdk::id(),
@@ -220,7 +227,7 @@ pub async fn retrieve_btc(args: RetrieveBtcArgs, minter_nonce: u64, ssi_nonce: u
     // @dev get guard for the account
     let _guard = retrieve_btc_guard(ssi_account)?;
 
-    let balance = match balance_of(SyronLedger::SYRON, ssi, 2).await {
+    let balance = match balance_of(SyronLedger::SYRON, ssi, SubaccountKind::Balance.nonce()).await {
         Ok(bal) => bal,
         Err(e) =>
             return Err(RetrieveBtcError::TemporarilyUnavailable(
@@ -281,7 +288,15 @@ pub async fn retrieve_btc(args: RetrieveBtcArgs, minter_nonce: u64, ssi_nonce: u
     
     let block_index =
     // burn_ckbtcs(args.amount, crate::memo::encode(&burn_memo).into(), ssi).await?;
-    update_balance::syron_update(&ssi, 2, Some(ssi_nonce), args.amount)
+    update_balance::syron_update(
+        &ssi,
+        SubaccountKind::Balance.nonce(),
+        Some(ssi_nonce),
+        args.amount,
+        update_balance::pinned_fee(read_state(|s| s.susd_id.get().into()))
+            .await
+            .map_err(|e| RetrieveBtcError::TemporarilyUnavailable(format!("Could not read the SUSD ledger fee {:?}", e)))?,
+    )
         .await
         .map_err(|e| RetrieveBtcError::TemporarilyUnavailable(format!("syron_update failed: {:?}", e)))?;
     
@@ -499,7 +514,11 @@ pub async fn balance_of(ledger: SyronLedger, ssi: &str, nonce: u64) -> Result<u6
                 msg, code
             ))
         })?;
-    Ok(result.0.to_u64().expect("nat does not fit into u64"))
+    result.0.to_u64()
+        .ok_or_else(|| UpdateBalanceError::SystemError{
+            method: "balance_of".to_string(),
+            reason: "Balance too large for u64".to_string()
+        })
 }
 
 async fn burn_ckbtcs(amount: u64, memo: Memo, ssi: &str) -> Result<u64, RetrieveBtcError> {
@@ -510,7 +529,7 @@ async fn burn_ckbtcs(amount: u64, memo: Memo, ssi: &str) -> Result<u64, Retrieve
         ledger_canister_id: read_state(|s| s.ledger_id.get().into()),
     };
     let minter = ic_cdk::id();
-    let from_subaccount = compute_subaccount(1, ssi);
+    let from_subaccount = compute_ssi_subaccount(SubaccountKind::Box, ssi);
     let result = client
         .transfer(TransferArg {
             from_subaccount: Some(from_subaccount),
@@ -531,10 +550,16 @@ async fn burn_ckbtcs(amount: u64, memo: Memo, ssi: &str) -> Result<u64, Retrieve
             ))
         })?;
 
+    let to_u64 = |value: Nat, name: &str| {
+        ledger_value_to_u64(value, name).map_err(|error_message| RetrieveBtcError::GenericError {
+            error_message,
+            error_code: ErrorCode::LedgerValueOverflow as u64,
+        })
+    };
     match result {
-        Ok(block_index) => Ok(block_index.0.to_u64().expect("nat does not fit into u64")),
+        Ok(block_index) => to_u64(block_index, "block index"),
         Err(TransferError::InsufficientFunds { balance }) => Err(RetrieveBtcError::InsufficientFunds {
-            balance: balance.0.to_u64().expect("unreachable: ledger balance does not fit into u64")
+            balance: to_u64(balance, "balance")?
         }),
         Err(TransferError::TemporarilyUnavailable) => {
             Err(RetrieveBtcError::TemporarilyUnavailable(
@@ -601,13 +626,19 @@ async fn burn_ckbtcs_icrc2(
             ))
         })?;
 
+    let to_u64 = |value: Nat, name: &str| {
+        ledger_value_to_u64(value, name).map_err(|error_message| RetrieveBtcWithApprovalError::GenericError {
+            error_message,
+            error_code: ErrorCode::LedgerValueOverflow as u64,
+        })
+    };
     match result {
-        Ok(block_index) => Ok(block_index.0.to_u64().expect("nat does not fit into u64")),
+        Ok(block_index) => to_u64(block_index, "block index"),
         Err(TransferFromError::InsufficientFunds { balance }) => Err(RetrieveBtcWithApprovalError::InsufficientFunds {
-            balance: balance.0.to_u64().expect("unreachable: ledger balance does not fit into u64")
+            balance: to_u64(balance, "balance")?
         }),
         Err(TransferFromError::InsufficientAllowance { allowance }) => Err(RetrieveBtcWithApprovalError::InsufficientAllowance {
-            allowance: allowance.0.to_u64().expect("unreachable: ledger balance does not fit into u64")
+            allowance: to_u64(allowance, "allowance")?
         }),
         Err(TransferFromError::TemporarilyUnavailable) => {
             Err(RetrieveBtcWithApprovalError::TemporarilyUnavailable(
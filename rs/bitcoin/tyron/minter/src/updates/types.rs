@@ -0,0 +1,186 @@
+//! Types shared by the balance update endpoints.
+//!
+//! This module only depends on external crates so that it can be shared with other minters
+//! that expose the same [update_balance] Candid interface. Conversions from errors that are
+//! specific to this minter live next to the code that produces them.
+
+use candid::{CandidType, Deserialize};
+use ic_btc_interface::{GetUtxosError, OutPoint, Utxo};
+use ic_xrc_types::ExchangeRateError;
+use icrc_ledger_types::icrc1::transfer::TransferError;
+use serde::Serialize;
+
+/// The outcome of UTXO processing.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum UtxoStatus {
+    /// The UTXO might have a transfer inscription
+    TransferInscription(Utxo),
+    /// The UTXO value does not cover the KYT check cost.
+    /// Kept for backward compatibility, the minter reports such UTXOs as [UtxoStatus::Ignored].
+    ValueTooSmall(Utxo),
+    /// The minter intentionally ignored the UTXO. This state is final.
+    Ignored {
+        /// The ignored UTXO.
+        utxo: Utxo,
+        /// Why the minter ignored the UTXO.
+        reason: IgnoredReason,
+    },
+    /// The KYT check found issues with the deposited UTXO.
+    Tainted(Utxo),
+    /// The deposited UTXO passed the KYT check, but the minter failed to mint ckBTC on the ledger.
+    /// The caller should retry the [update_balance] call.
+    Checked(Utxo),
+    /// The minter accepted the UTXO and minted ckBTC tokens on the ledger.
+    Minted {
+        /// The MINT transaction index on the ledger.
+        block_index: u64,
+        /// The indices of all the transactions of the mint: the BTC collateral, followed by the
+        /// SUSD loan and the SUSD balance if any SUSD was minted.
+        block_indices: Vec<u64>,
+        /// The minted amount (UTXO value minus fees).
+        minted_amount: u64,
+        /// The UTXO that caused the balance update.
+        utxo: Utxo,
+        /// The BTC/USD exchange rate applied to the deposit.
+        exchange_rate: u64,
+        /// The collateral ratio (in basis points) of the account before the deposit.
+        collateral_ratio: u64,
+    },
+    Read(Utxo),
+    /// The UTXO was not processed because the call reached the maximum number of UTXOs per update.
+    /// The caller should retry the [update_balance] call.
+    Deferred(Utxo),
+    /// The minter finalized (part of) a pending runes deposit.
+    SyronDeposited {
+        /// The transaction index of the deposit on the SUSD ledger.
+        block_index: u64,
+        /// The finalized amount.
+        deposited_amount: u64,
+        /// The amount that is still pending.
+        remaining_amount: u64,
+    },
+    /// The caller liquidated (part of) the loan of the SSI.
+    Liquidated {
+        /// The transaction index of the seized collateral on the SBTC ledger.
+        block_index: u64,
+        /// The SUSD repaid by the liquidator.
+        repaid_susd: u64,
+        /// The SBTC collateral transferred to the liquidator, including the bonus.
+        seized_collateral: u64,
+        /// The part of the seized collateral that is the liquidation bonus.
+        bonus: u64,
+    },
+    /// The minter redeemed (part of) the bitcoin collateral of the SSI.
+    Redeemed {
+        /// The transaction index of the redemption on the SBTC ledger.
+        block_index: u64,
+        /// If the BTC is sent to a Bitcoin address, the id of the request in [retrieve_btc_status].
+        request_id: Option<u64>,
+        /// The SBTC collateral released from the loan.
+        gross_amount: u64,
+        /// The redemption fee transferred to the treasury.
+        fee: u64,
+        /// The redeemed SBTC, net of the fee.
+        net_amount: u64,
+    },
+}
+
+/// The reason why the minter ignored a UTXO.
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum IgnoredReason {
+    /// The UTXO value is at most the dust limit, e.g. an output carrying an inscription or runes.
+    Dust,
+    /// The UTXO value is lower than the minimum deposit amount.
+    BelowMinDeposit,
+    /// The UTXO value does not cover the KYT fee.
+    KytFeeNotCovered,
+    /// The deposit would push the collateral of the SSI above the per-account cap.
+    /// Unlike the other reasons, the UTXO is processed again once the cap allows it.
+    CollateralCapExceeded {
+        /// The collateral (in Satoshi) that the SSI can still deposit.
+        headroom: u64,
+    },
+}
+
+pub enum ErrorCode {
+    ConfigurationError = 1,
+    UnsupportedOperation = 2,
+    InsufficientAmount = 3,
+    DebtCeilingExceeded = 4,
+    BadFee = 5,
+    RedemptionLimitExceeded = 6,
+    TaintedAddress = 7
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct PendingUtxo {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub confirmations: u32,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum UpdateBalanceError {
+    /// The minter experiences temporary issues, try the call again later.
+    TemporarilyUnavailable(String),
+    /// There is a concurrent [update_balance] invocation from the same caller.
+    AlreadyProcessing,
+    /// The minter experiences temporary issues and knows how long they last.
+    /// Try the call again after [retry_after_nanos], if set.
+    RetryLater {
+        reason: String,
+        retry_after_nanos: Option<u64>,
+    },
+    /// The caller is not the principal linked to the SSI by the SIWB canister.
+    Unauthorized(String),
+    /// The minter didn't discover new UTXOs with enough confirmations.
+    NoNewUtxos {
+        /// If there are new UTXOs that do not have enough
+        /// confirmations yet, this field will contain the number of
+        /// confirmations as observed by the minter.
+        current_confirmations: Option<u32>,
+        /// The minimum number of UTXO confirmation required for the minter to accept a UTXO.
+        required_confirmations: u32,
+        /// List of utxos that don't have enough confirmations yet to be processed.
+        pending_utxos: Option<Vec<PendingUtxo>>,
+    },
+    GenericError {
+        error_code: u64,
+        error_message: String,
+    },
+    CallError {
+        method: String,
+        reason: String
+    },
+    SystemError {
+        method: String,
+        reason: String
+    },
+}
+
+/// The result of the minter operations.
+pub type MinterResult<T> = Result<T, UpdateBalanceError>;
+
+impl From<GetUtxosError> for UpdateBalanceError {
+    fn from(e: GetUtxosError) -> Self {
+        Self::GenericError {
+            error_code: ErrorCode::ConfigurationError as u64,
+            error_message: format!("failed to get UTXOs from the Bitcoin canister: {}", e),
+        }
+    }
+}
+
+impl From<TransferError> for UpdateBalanceError {
+    fn from(e: TransferError) -> Self {
+        Self::GenericError {
+            error_code: ErrorCode::ConfigurationError as u64,
+            error_message: format!("failed to mint tokens on the ledger: {:?}", e),
+        }
+    }
+}
+
+impl From<ExchangeRateError> for UpdateBalanceError {
+    fn from(e: ExchangeRateError) -> Self {
+        Self::TemporarilyUnavailable(format!("failed to fetch the current exchange rate: {:?}", e))
+    }
+}
@@ -2,26 +2,29 @@ use crate::address::BitcoinAddress;
 use crate::logs::{P0, P1};
 use crate::management::{fetch_btc_exchange_rate, get_siwb_principal};
 use crate::memo::MintMemo;
-use crate::state::{mutate_state, read_state, UtxoCheckStatus};
+use crate::state::{mutate_state, read_state, MintAttempt, StabilityFee, UtxoCheckStatus, MINT_DEDUP_WINDOW_NANOS};
 use crate::tasks::{schedule_now, TaskType};
 use candid::{CandidType, Deserialize, Nat, Principal};
-use ic_btc_interface::{GetUtxosError, GetUtxosResponse, OutPoint, Utxo};
+use ic_btc_interface::{GetUtxosResponse, OutPoint, Utxo};
 use ic_canister_log::log;
-use ic_ckbtc_kyt::Error as KytError;
-use ic_xrc_types::ExchangeRateError;
+use ic_ckbtc_kyt::{Error as KytError, FetchAlertsResponse};
 use icrc_ledger_client_cdk::{CdkRuntime, ICRC1Client};
 use icrc_ledger_types::icrc1::{
-    account::{Account, Subaccount},
+    account::{Account, Subaccount, DEFAULT_SUBACCOUNT},
     transfer::{Memo, TransferArg, TransferError}
 };
+use icrc_ledger_types::icrc2::transfer_from::TransferFromArgs;
 use num_traits::ToPrimitive;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use super::get_btc_address::{GetBoxAddressArgs, SyronOperation};
-use super::get_withdrawal_account::compute_subaccount;
-use super::retrieve_btc::{balance_of, SyronLedger};
+use super::get_withdrawal_account::{compute_ssi_subaccount, compute_subaccount, minter_account, minter_account_with_nonce, SubaccountKind};
+use super::retrieve_btc::{balance_of, SyronLedger, MAX_CONCURRENT_PENDING_REQUESTS};
+use crate::storage::{ProtocolEvent, ProtocolEventKind};
+pub use super::types::{ErrorCode, IgnoredReason, MinterResult, PendingUtxo, UpdateBalanceError, UtxoStatus};
 use crate::{
-    guard::{balance_update_guard, GuardError},
-    management::{fetch_utxo_alerts, get_utxos, CallError, CallSource},
+    guard::{balance_update_guard, loan_guard, operation_guard, DebtReservation, GuardError},
+    management::{fetch_utxo_alerts, fetch_withdrawal_alerts, get_utxos, CallError, CallSource, Reason},
     state,
     tx::{DisplayAmount, DisplayOutpoint},
     updates::get_btc_address,
@@ -37,123 +40,109 @@ pub struct UpdateBalanceArgs {
     pub subaccount: Option<Subaccount>,
 }
 
-/// The outcome of UTXO processing.
-#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
-pub enum UtxoStatus {
-    /// The UTXO might have a transfer inscription
-    TransferInscription(Utxo),
-    /// The UTXO value does not cover the KYT check cost.
-    ValueTooSmall(Utxo),
-    /// The KYT check found issues with the deposited UTXO.
-    Tainted(Utxo),
-    /// The deposited UTXO passed the KYT check, but the minter failed to mint ckBTC on the ledger.
-    /// The caller should retry the [update_balance] call.
-    Checked(Utxo),
-    /// The minter accepted the UTXO and minted ckBTC tokens on the ledger.
-    Minted {
-        /// The MINT transaction index on the ledger.
-        block_index: u64,
-        /// The minted amount (UTXO value minus fees).
-        minted_amount: u64,
-        /// The UTXO that caused the balance update.
-        utxo: Utxo,
-    },
-    Read(Utxo)
-}
-
-pub enum ErrorCode {
-    ConfigurationError = 1,
-    UnsupportedOperation = 2,
-    InsufficientAmount = 3
-}
-
-#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
-pub struct PendingUtxo {
-    pub outpoint: OutPoint,
-    pub value: u64,
-    pub confirmations: u32,
-}
-
-#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
-pub enum UpdateBalanceError {
-    /// The minter experiences temporary issues, try the call again later.
-    TemporarilyUnavailable(String),
-    /// There is a concurrent [update_balance] invocation from the same caller.
-    AlreadyProcessing,
-    /// The minter didn't discover new UTXOs with enough confirmations.
-    NoNewUtxos {
-        /// If there are new UTXOs that do not have enough
-        /// confirmations yet, this field will contain the number of
-        /// confirmations as observed by the minter.
-        current_confirmations: Option<u32>,
-        /// The minimum number of UTXO confirmation required for the minter to accept a UTXO.
-        required_confirmations: u32,
-        /// List of utxos that don't have enough confirmations yet to be processed.
-        pending_utxos: Option<Vec<PendingUtxo>>,
-    },
-    GenericError {
-        error_code: u64,
-        error_message: String,
-    },
-    CallError {
-        method: String,
-        reason: String
-    },
-    SystemError {
-        method: String,
-        reason: String
-    },
-}
+/// The time (in nanoseconds) after which a call rejected for too many concurrent requests
+/// should be retried.
+pub const CONCURRENCY_RETRY_AFTER_NANOS: u64 = 5 * 1_000_000_000;
 
 impl From<GuardError> for UpdateBalanceError {
     fn from(e: GuardError) -> Self {
         match e {
             GuardError::AlreadyProcessing => Self::AlreadyProcessing,
-            GuardError::TooManyConcurrentRequests => {
-                Self::TemporarilyUnavailable("too many concurrent requests".to_string())
-            }
+            GuardError::TooManyConcurrentRequests => Self::RetryLater {
+                reason: "too many concurrent requests".to_string(),
+                retry_after_nanos: Some(CONCURRENCY_RETRY_AFTER_NANOS),
+            },
         }
     }
 }
 
-impl From<GetUtxosError> for UpdateBalanceError {
-    fn from(e: GetUtxosError) -> Self {
-        Self::GenericError {
-            error_code: ErrorCode::ConfigurationError as u64,
-            error_message: format!("failed to get UTXOs from the Bitcoin canister: {}", e),
+impl From<CallError> for UpdateBalanceError {
+    fn from(e: CallError) -> Self {
+        match e.reason() {
+            Reason::Unauthorized => Self::Unauthorized(e.to_string()),
+            _ => Self::TemporarilyUnavailable(e.to_string()),
         }
     }
 }
 
-impl From<TransferError> for UpdateBalanceError {
-    fn from(e: TransferError) -> Self {
-        Self::GenericError {
-            error_code: ErrorCode::ConfigurationError as u64,
-            error_message: format!("failed to mint tokens on the ledger: {:?}", e),
-        }
-    }
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CollateralizedAccount {
+    exchange_rate: u64,
+    pub collateral_ratio: u64,
+    pub btc_1: u64,
+    pub susd_1: u64,
+    pub susd_2: u64,
+    pub susd_3: u64,
+    /// The stability fee accrued on the loan (susd_1) that was not paid yet.
+    pub stability_fee: u64
 }
 
-impl From<CallError> for UpdateBalanceError {
-    fn from(e: CallError) -> Self {
-        Self::TemporarilyUnavailable(e.to_string())
+impl CollateralizedAccount {
+    /// Returns the outstanding debt, including the accrued stability fee.
+    pub fn debt(&self) -> u64 {
+        self.susd_1.saturating_add(self.stability_fee)
     }
 }
 
-impl From<ExchangeRateError> for UpdateBalanceError {
-    fn from(e: ExchangeRateError) -> Self {
-        Self::TemporarilyUnavailable(format!("failed to fetch the current exchange rate: {:?}", e))
+/// The ledger transactions of a [mint].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockIndices {
+    /// The bitcoin collateral credited to the box on the SBTC ledger.
+    pub collateral: u64,
+    /// The SUSD loan recorded in the box, if any SUSD was minted.
+    pub loan: Option<u64>,
+    /// The SUSD credited to the balance account, if any SUSD was minted.
+    pub balance: Option<u64>,
+}
+
+impl BlockIndices {
+    /// Returns the indices in the order of the transactions: the collateral, the loan and
+    /// the balance.
+    pub fn to_vec(&self) -> Vec<u64> {
+        std::iter::once(self.collateral)
+            .chain(self.loan)
+            .chain(self.balance)
+            .collect()
     }
 }
 
+/// The result of the [repay_loan] endpoint.
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
-pub struct CollateralizedAccount {
-    exchange_rate: u64,
+pub struct RepayLoanResult {
+    /// The index of the SUSD burn transaction.
+    pub block_index: u64,
+    /// The amount of SUSD repaid, capped at the outstanding debt.
+    pub repaid_amount: u64,
+    /// The part of the repaid amount that paid the accrued stability fee.
+    pub stability_fee_paid: u64,
+    /// The collateral ratio (in basis points) after the repayment.
     pub collateral_ratio: u64,
-    pub btc_1: u64,
-    pub susd_1: u64,
-    pub susd_2: u64,
-    pub susd_3: u64
+}
+
+/// The expected outcome of a liquidation, see [simulate_liquidation].
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LiquidationPreview {
+    /// Whether the loan is below the liquidation threshold and its grace period elapsed.
+    pub liquidatable: bool,
+    /// The time (in nanoseconds) the loan was first observed below the liquidation threshold.
+    pub underwater_since: Option<u64>,
+    /// The current collateral ratio (in basis points) of the loan.
+    pub collateral_ratio: u64,
+    /// The SUSD that the liquidator would repay, capped at the outstanding loan.
+    pub repaid_susd: u64,
+    /// The SBTC collateral that the liquidator would receive, including the bonus.
+    pub seized_collateral: u64,
+    /// The part of the seized collateral that is the liquidation bonus.
+    pub bonus: u64,
+}
+
+/// The result of the [close_loan] endpoint.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CloseLoanResult {
+    /// The amount of SBTC collateral redeemed.
+    pub redeemed_btc: u64,
+    /// The amount of SUSD burned to repay the loan.
+    pub burned_susd: u64,
 }
 
 /// Notifies the ckBTC minter to update the balance of the user subaccount.
@@ -338,40 +327,610 @@ pub struct CollateralizedAccount {
 //     // Ok(utxo_statuses)
 // }
 
+/// Returns true if the UTXO has at least `min_confirmations` confirmations at `tip_height`.
+fn is_confirmed(utxo: &Utxo, tip_height: u32, min_confirmations: u32) -> bool {
+    // @dev in u64 so that a large requested number of confirmations cannot overflow
+    utxo.height as u64 + min_confirmations as u64 <= tip_height as u64 + 1
+}
+
+/// The largest UTXO value (in Satoshi) considered dust, e.g. an output carrying an inscription or runes.
+const DUST_LIMIT: u64 = 546;
+
+/// Returns the confirmations required for a deposit, rejecting requested values
+/// below the minter's minimum so that callers cannot weaken its safety.
+fn effective_min_confirmations(requested: Option<u32>, min_confirmations: u32) -> MinterResult<u32> {
+    match requested {
+        None => Ok(min_confirmations),
+        Some(requested) if requested >= min_confirmations => Ok(requested),
+        Some(requested) => Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: format!(
+                "@update_ssi_balance: The requested confirmations ({}) are below the minimum ({})",
+                requested, min_confirmations
+            ),
+        }),
+    }
+}
+
+/// Returns the amount to mint for a deposit, or the reason to ignore the UTXO if its
+/// value is dust, lower than the minimum deposit or does not cover the KYT fee.
+fn deposit_amount(utxo_value: u64, min_deposit: u64, kyt_fee: u64) -> Result<u64, IgnoredReason> {
+    if utxo_value <= DUST_LIMIT {
+        return Err(IgnoredReason::Dust);
+    }
+    if utxo_value < min_deposit {
+        return Err(IgnoredReason::BelowMinDeposit);
+    }
+    utxo_value.checked_sub(kyt_fee).ok_or(IgnoredReason::KytFeeNotCovered)
+}
+
+/// Returns the UTXOs that do not have enough confirmations yet,
+/// with their confirmations relative to `tip_height`.
+fn to_pending_utxos(mut utxos: Vec<Utxo>, tip_height: u32, min_confirmations: u32) -> Vec<PendingUtxo> {
+    utxos.retain(|u| !is_confirmed(u, tip_height, min_confirmations));
+    utxos
+        .into_iter()
+        .map(|u| PendingUtxo {
+            outpoint: u.outpoint,
+            value: u.value,
+            // @dev a reorg can report UTXOs above the tip height
+            confirmations: tip_height.saturating_sub(u.height).saturating_add(1),
+        })
+        .collect()
+}
+
+/// Returns the deposits to the SSI box address that do not have enough confirmations yet.
+/// Unlike [update_ssi_balance], it never mints.
+pub async fn get_pending_deposits(ssi: String) -> MinterResult<Vec<PendingUtxo>> {
+    let ssi_box_account = minter_account(SubaccountKind::Box, &ssi);
+
+    let box_address = state::read_state(|s| {
+        get_btc_address::ssi_account_to_p2wpkh_address_from_state(s, &ssi_box_account, &ssi)
+    });
+
+    let (btc_network, min_confirmations) =
+        state::read_state(|s| (s.btc_network, s.min_confirmations));
+
+    let GetUtxosResponse {
+        tip_height,
+        utxos,
+        ..
+    } = get_utxos(
+        btc_network,
+        &box_address,
+        /*min_confirmations=*/ 0,
+        CallSource::Client,
+    )
+    .await?;
+
+    Ok(to_pending_utxos(utxos, tip_height, min_confirmations))
+}
+
+/// Returns the NoNewUtxos error for the pending UTXOs, reporting at most `max_pending_utxos`
+/// of them (the ones with the most confirmations) and the maximum confirmations across all of them.
+fn no_new_utxos_error(
+    mut pending_utxos: Vec<PendingUtxo>,
+    required_confirmations: u32,
+    max_pending_utxos: usize,
+) -> UpdateBalanceError {
+    let current_confirmations = pending_utxos.iter().map(|u| u.confirmations).max();
+    pending_utxos.sort_by(|a, b| b.confirmations.cmp(&a.confirmations));
+    pending_utxos.truncate(max_pending_utxos);
+    UpdateBalanceError::NoNewUtxos {
+        current_confirmations,
+        required_confirmations,
+        pending_utxos: Some(pending_utxos),
+    }
+}
+
+/// The result of the [reconcile_account] endpoint.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ReconcileResult {
+    /// UTXOs at the box address that the minter has not recorded.
+    pub unrecorded: Vec<OutPoint>,
+    /// UTXOs recorded for the box account that are no longer at the box address.
+    pub missing: Vec<OutPoint>,
+}
+
+/// Returns the outpoints of the recorded UTXOs that are not in the given UTXO list.
+fn missing_outpoints<'a>(recorded: impl IntoIterator<Item = &'a Utxo>, utxos: &[Utxo]) -> Vec<OutPoint> {
+    recorded
+        .into_iter()
+        .filter(|utxo| !utxos.contains(utxo))
+        .map(|utxo| utxo.outpoint.clone())
+        .collect()
+}
+
+/// The maximum number of ledger calls in flight while seeding the totals of the loans.
+const MAX_CONCURRENT_SEED_CALLS: usize = 10;
+
+/// Seeds the total collateral and debt from the box balances of the given SSIs, which must
+/// include every SSI with a loan, and returns them.
+///
+/// The minter must be in read-only mode, so that no loan changes while the balances are read.
+pub async fn seed_totals(ssis: Vec<String>) -> MinterResult<(u64, u64)> {
+    if read_state(|s| s.mode != state::Mode::ReadOnly || !s.update_balance_operations.is_empty()) {
+        return Err(UpdateBalanceError::TemporarilyUnavailable(
+            "the totals can only be seeded in read-only mode without operations in flight".to_string(),
+        ));
+    }
+
+    let (mut total_collateral, mut total_debt) = (0u64, 0u64);
+    for batch in ssis.chunks(MAX_CONCURRENT_SEED_CALLS) {
+        let balances = futures::future::join_all(batch.iter().map(|ssi| async move {
+            let btc_1 = balance_of(SyronLedger::BTC, ssi, SubaccountKind::Box.nonce()).await?;
+            let susd_1 = balance_of(SyronLedger::SYRON, ssi, SubaccountKind::Box.nonce()).await?;
+            Ok::<_, UpdateBalanceError>((btc_1, susd_1))
+        }))
+        .await;
+        for balance in balances {
+            let (btc_1, susd_1) = balance?;
+            total_collateral = total_collateral.saturating_add(btc_1);
+            total_debt = total_debt.saturating_add(susd_1);
+        }
+    }
+
+    mutate_state(|s| state::audit::seed_totals(s, total_collateral, total_debt));
+    log!(
+        P0,
+        "Seeded the totals of the loans of {} SSIs: {} (BTC) of collateral and {} (SUSD) of debt",
+        ssis.len(),
+        DisplayAmount(total_collateral),
+        DisplayAmount(total_debt),
+    );
+    Ok((total_collateral, total_debt))
+}
+
+/// Compares the UTXOs at the box address of the given SSI with the UTXOs recorded in the state.
+///
+/// This is a diagnostic for operators recovering from partial failures; it never mints.
+/// Recorded UTXOs spent by an in-flight transaction also show up as missing.
+pub async fn reconcile_account(ssi: String) -> MinterResult<ReconcileResult> {
+    let ssi_box_account = minter_account(SubaccountKind::Box, &ssi);
+
+    let box_address = state::read_state(|s| {
+        get_btc_address::ssi_account_to_p2wpkh_address_from_state(s, &ssi_box_account, &ssi)
+    });
+
+    let (btc_network, min_confirmations) =
+        state::read_state(|s| (s.btc_network, s.min_confirmations));
+
+    let utxos = get_utxos(btc_network, &box_address, min_confirmations, CallSource::Client)
+        .await?
+        .utxos;
+
+    Ok(state::read_state(|s| ReconcileResult {
+        unrecorded: s
+            .new_utxos_for_account(utxos.clone(), &ssi_box_account)
+            .into_iter()
+            .filter(|utxo| !s.ignored_utxos.contains(utxo))
+            .map(|utxo| utxo.outpoint)
+            .collect(),
+        missing: s
+            .utxos_state_addresses
+            .get(&ssi_box_account)
+            .map(|recorded| missing_outpoints(recorded, &utxos))
+            .unwrap_or_default(),
+    }))
+}
+
+/// Returns the protocol event of a successful operation, without bitcoin context.
+fn protocol_event(kind: ProtocolEventKind, ssi: &str, amount: u64, block_index: u64) -> ProtocolEvent {
+    ProtocolEvent {
+        kind,
+        ssi: ssi.to_string(),
+        amount,
+        block_index,
+        timestamp: ic_cdk::api::time(),
+        outpoint: None,
+        btc_address: None,
+    }
+}
+
+/// Appends a successful operation to the protocol event log.
+fn record_protocol_event(kind: ProtocolEventKind, ssi: &str, amount: u64, block_index: u64) {
+    crate::storage::record_protocol_event(&protocol_event(kind, ssi, amount, block_index));
+}
+
+/// Returns an error with the remaining headroom if depositing `amount` would push the
+/// collateral of an SSI above `max_collateral`.
+fn check_collateral_cap(collateral: u64, amount: u64, max_collateral: u64) -> Result<(), u64> {
+    let headroom = max_collateral.saturating_sub(collateral);
+    if amount > headroom {
+        return Err(headroom);
+    }
+    Ok(())
+}
+
+/// Returns the amount of SUSD to repay, capped at the outstanding loan.
+fn repayment_amount(amount: u64, debt: u64) -> MinterResult<u64> {
+    if amount == 0 {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: "@repay_loan: The amount to repay must be greater than zero".to_string()
+        });
+    }
+    if debt == 0 {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: "@repay_loan: There is no outstanding loan to repay".to_string()
+        });
+    }
+    Ok(amount.min(debt))
+}
+
+/// Splits a repayment into the part that pays the accrued stability fee, which is settled
+/// first, and the part that repays the loan.
+fn split_repayment(amount: u64, stability_fee: u64) -> (u64, u64) {
+    let fee_paid = amount.min(stability_fee);
+    (fee_paid, amount - fee_paid)
+}
+
+/// Repays part of the SUSD loan of the given SSI.
+///
+/// The accrued stability fee is paid first, by burning SUSD from the balance subaccount
+/// (nonce 2). The rest is burned by transferring it from the loan subaccount (nonce 1) to
+/// the minter's default account, which frees the corresponding share of the bitcoin collateral.
+pub async fn repay_loan(ssi: String, amount: u64) -> MinterResult<RepayLoanResult> {
+    let ssi_box_subaccount = compute_ssi_subaccount(SubaccountKind::Box, &ssi);
+    let ssi_box_account = minter_account(SubaccountKind::Box, &ssi);
+
+    state::read_state(|s| s.mode.is_withdrawal_available_for(&ssi_box_account))
+        .map_err(UpdateBalanceError::TemporarilyUnavailable)?;
+
+    let _guard = loan_guard(ssi_box_account)?;
+
+    let susd_1 = balance_of(SyronLedger::SYRON, &ssi, SubaccountKind::Box.nonce()).await?;
+    let stability_fee = accrue_stability_fee(&ssi, susd_1, ic_cdk::api::time());
+    let repaid_amount = repayment_amount(amount, susd_1.saturating_add(stability_fee))?;
+    let (stability_fee_paid, repaid_loan) = split_repayment(repaid_amount, stability_fee);
+
+    let mut block_index = None;
+    if stability_fee_paid != 0 {
+        block_index = Some(pay_stability_fee(&ssi, stability_fee_paid, "@repay_loan: Cannot pay the stability fee").await?);
+    }
+
+    if repaid_loan != 0 {
+        let loan_block_index = burn_from_box(
+            SyronLedger::SYRON,
+            ssi_box_subaccount,
+            repaid_loan,
+            "@repay_loan: Cannot burn the loan repayment",
+        ).await?;
+
+        mutate_state(|s| state::audit::repaid(s, ssi.clone(), 0, repaid_loan));
+        record_protocol_event(ProtocolEventKind::Repay, &ssi, repaid_loan, loan_block_index);
+        block_index = Some(loan_block_index);
+    }
+
+    // @dev the remaining loan keeps accruing from now on
+    accrue_stability_fee(&ssi, susd_1 - repaid_loan, ic_cdk::api::time());
+
+    log!(
+        P1,
+        "Repaid {} (SUSD) of the loan of SSI {ssi}, including a stability fee of {} (SUSD)",
+        DisplayAmount(repaid_amount),
+        DisplayAmount(stability_fee_paid),
+    );
+
+    // @dev a repayment is never zero, so it paid the fee, the loan or both
+    let block_index = block_index.ok_or_else(|| UpdateBalanceError::SystemError{
+        method: "repay_loan".to_string(),
+        reason: "No SUSD was burned".to_string()
+    })?;
+    let collateralized_account = get_collateralized_account(&ssi).await?;
+
+    Ok(RepayLoanResult {
+        block_index,
+        repaid_amount,
+        stability_fee_paid,
+        collateral_ratio: collateralized_account.collateral_ratio,
+    })
+}
+
+/// One year in nanoseconds, the period of the stability fee rate.
+const NANOS_PER_YEAR: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Returns the stability fee that a loan of `debt` SUSD accrues in `elapsed` nanoseconds.
+fn stability_fee(debt: u64, fee_bps_per_year: u64, elapsed: u64) -> u64 {
+    let fee = (debt as u128 * fee_bps_per_year as u128).saturating_mul(elapsed as u128)
+        / (10_000 * NANOS_PER_YEAR as u128);
+    fee.min(u64::MAX as u128) as u64
+}
+
+/// Accrues the stability fee on the loan (`debt`) of the given SSI up to `now` and returns
+/// the fee that was not paid yet.
+///
+/// The accrual time only moves forward when a non-zero fee accrues, so that frequent
+/// interactions with a small loan do not round its fee down to zero.
+fn accrue_stability_fee(ssi: &str, debt: u64, now: u64) -> u64 {
+    mutate_state(|s| {
+        let fee_bps_per_year = s.stability_fee_bps_per_year;
+        match s.stability_fees.get(ssi).cloned() {
+            None => {
+                // @dev start the accrual of a new loan
+                if debt != 0 {
+                    state::audit::accrue_stability_fee(s, ssi.to_string(), 0, now);
+                }
+                0
+            }
+            Some(StabilityFee { accrued, accrued_at }) if debt == 0 => {
+                if accrued == 0 {
+                    // @dev the loan was repaid, stop tracking it
+                    state::audit::settle_stability_fee(s, ssi.to_string(), 0);
+                } else if accrued_at < now {
+                    // @dev the unpaid fee does not accrue, nor the time without a loan
+                    state::audit::accrue_stability_fee(s, ssi.to_string(), 0, now);
+                }
+                accrued
+            }
+            Some(StabilityFee { accrued, accrued_at }) => {
+                let fee = stability_fee(debt, fee_bps_per_year, now.saturating_sub(accrued_at));
+                if fee != 0 {
+                    state::audit::accrue_stability_fee(s, ssi.to_string(), fee, now);
+                }
+                accrued.saturating_add(fee)
+            }
+        }
+    })
+}
+
+/// Returns the stability fee that `accrue_stability_fee` would return for the loan (`debt`)
+/// of the given SSI at `now`, without recording the accrual.
+fn accrued_stability_fee(s: &state::MinterState, ssi: &str, debt: u64, now: u64) -> u64 {
+    match s.stability_fees.get(ssi) {
+        None => 0,
+        Some(StabilityFee { accrued, .. }) if debt == 0 => *accrued,
+        Some(StabilityFee { accrued, accrued_at }) => accrued.saturating_add(stability_fee(
+            debt,
+            s.stability_fee_bps_per_year,
+            now.saturating_sub(*accrued_at),
+        )),
+    }
+}
+
+/// Burns `fee` SUSD from the balance subaccount (nonce 2) of the given SSI to pay its
+/// accrued stability fee, and returns the index of the ledger transaction.
+async fn pay_stability_fee(ssi: &str, fee: u64, context: &str) -> MinterResult<u64> {
+    let block_index = burn_from_box(
+        SyronLedger::SYRON,
+        compute_ssi_subaccount(SubaccountKind::Balance, ssi),
+        fee,
+        context,
+    ).await?;
+    mutate_state(|s| state::audit::settle_stability_fee(s, ssi.to_string(), fee));
+    Ok(block_index)
+}
+
+/// Pays the whole stability fee accrued on the loan (`susd_1`) of the given SSI before the
+/// loan is closed, and returns the paid fee.
+async fn settle_accrued_stability_fee(ssi: &str, susd_1: u64, context: &str) -> MinterResult<u64> {
+    let stability_fee = accrue_stability_fee(ssi, susd_1, ic_cdk::api::time());
+    if stability_fee != 0 {
+        pay_stability_fee(ssi, stability_fee, context).await?;
+    }
+    Ok(stability_fee)
+}
+
+/// Transfers the given amount from the SSI box subaccount to the minter's default account,
+/// which burns it, and returns the index of the ledger transaction.
+async fn burn_from_box(
+    ledger: SyronLedger,
+    ssi_box_subaccount: Subaccount,
+    amount: u64,
+    context: &str,
+) -> MinterResult<u64> {
+    let ledger_canister_id = state::read_state(|s| match ledger {
+        SyronLedger::BTC => s.ledger_id.get().into(),
+        SyronLedger::SYRON => s.susd_id.get().into(),
+    });
+    let client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id,
+    };
+    let block_index = client
+        .transfer(TransferArg {
+            from_subaccount: Some(ssi_box_subaccount),
+            to: Account {
+                owner: ic_cdk::id(),
+                subaccount: None
+            },
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: Nat::from(amount),
+        })
+        .await
+        .map_err(|(code, msg)| {
+            UpdateBalanceError::TemporarilyUnavailable(format!(
+                "{context}: {} (reject_code = {})",
+                msg, code
+            ))
+        })??;
+    block_index.0.to_u64()
+        .ok_or_else(|| UpdateBalanceError::SystemError{
+            method: "burn_from_box".to_string(),
+            reason: "Block index too large for u64".to_string()
+        })
+}
+
+/// Closes the position of the given SSI by burning its entire SUSD loan and then
+/// redeeming all of its bitcoin collateral.
+///
+/// If the collateral cannot be redeemed after the loan was burned, the minter mints the
+/// SUSD back to the SSI box subaccount so that the position is left unchanged.
+pub async fn close_loan(ssi: String) -> MinterResult<CloseLoanResult> {
+    let ssi_box_subaccount = compute_ssi_subaccount(SubaccountKind::Box, &ssi);
+    let ssi_box_account = minter_account(SubaccountKind::Box, &ssi);
+
+    state::read_state(|s| s.mode.is_withdrawal_available_for(&ssi_box_account))
+        .map_err(UpdateBalanceError::TemporarilyUnavailable)?;
+
+    let _guard = loan_guard(ssi_box_account)?;
+
+    let CollateralizedAccount { btc_1, susd_1, .. } = get_collateralized_account(&ssi).await?;
+    if btc_1 == 0 {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: "@close_loan: There is no bitcoin collateral to redeem".to_string()
+        });
+    }
+
+    settle_accrued_stability_fee(&ssi, susd_1, "@close_loan: Cannot pay the stability fee").await?;
+
+    let burn_block_index = if susd_1 != 0 {
+        Some(burn_from_box(
+            SyronLedger::SYRON,
+            ssi_box_subaccount,
+            susd_1,
+            "@close_loan: Cannot burn the loan",
+        ).await?)
+    } else {
+        None
+    };
+
+    let redeem_block_index = match burn_from_box(
+        SyronLedger::BTC,
+        ssi_box_subaccount,
+        btc_1,
+        "@close_loan: Cannot redeem bitcoin",
+    ).await {
+        Ok(block_index) => block_index,
+        Err(err) => {
+            if susd_1 != 0 {
+                let susd_client = ICRC1Client {
+                    runtime: CdkRuntime,
+                    ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
+                };
+                let refund = susd_client
+                    .transfer(TransferArg {
+                        from_subaccount: None,
+                        to: ssi_box_account,
+                        fee: None,
+                        created_at_time: None,
+                        memo: None,
+                        amount: Nat::from(susd_1),
+                    })
+                    .await;
+                if !matches!(refund, Ok(Ok(_))) {
+                    log!(
+                        P0,
+                        "[close_loan]: failed to restore the {} (SUSD) loan of SSI {ssi}: {:?}",
+                        DisplayAmount(susd_1),
+                        refund
+                    );
+                    mutate_state(|s| state::audit::repaid(s, ssi.clone(), 0, susd_1));
+                }
+            }
+            return Err(err);
+        }
+    };
+
+    mutate_state(|s| state::audit::repaid(s, ssi.clone(), btc_1, susd_1));
+    if let Some(block_index) = burn_block_index {
+        record_protocol_event(ProtocolEventKind::Repay, &ssi, susd_1, block_index);
+    }
+    record_protocol_event(ProtocolEventKind::Redeem, &ssi, btc_1, redeem_block_index);
+
+    log!(
+        P1,
+        "Closed the loan of SSI {ssi}: burned {} (SUSD) and redeemed {} (BTC)",
+        DisplayAmount(susd_1),
+        DisplayAmount(btc_1),
+    );
+
+    Ok(CloseLoanResult {
+        redeemed_btc: btc_1,
+        burned_susd: susd_1,
+    })
+}
+
+/// Returns the time (in nanoseconds) the caller must wait before its next balance update of
+/// the SSI, or records the update and returns None if the last accepted update is old enough.
+fn balance_update_wait_time<K: Ord>(
+    last_updates: &mut BTreeMap<K, u64>,
+    key: K,
+    now: u64,
+    interval: u64,
+) -> Option<u64> {
+    if let Some(&last_update) = last_updates.get(&key) {
+        let elapsed = now.saturating_sub(last_update);
+        if elapsed < interval {
+            return Some(interval - elapsed);
+        }
+    }
+    // @dev drop the entries that can no longer limit a caller to keep the map bounded
+    last_updates.retain(|_, &mut last_update| now.saturating_sub(last_update) < interval);
+    last_updates.insert(key, now);
+    None
+}
+
+/// Rejects the balance update of an SSI that the caller updated less than the configured
+/// interval ago. The limit is kept per caller, so that no caller can exhaust it for others.
+///
+/// Only the [update_ssi_balance] endpoint is rate limited; internal calls are exempt.
+pub fn check_balance_update_rate_limit(caller: Principal, ssi: &str) -> MinterResult<()> {
+    let now = ic_cdk::api::time();
+    mutate_state(|s| {
+        let interval = s.update_balance_interval;
+        match balance_update_wait_time(&mut s.last_balance_updates, (caller, ssi.to_string()), now, interval) {
+            None => Ok(()),
+            Some(wait_time) => Err(UpdateBalanceError::RetryLater {
+                reason: format!(
+                    "too many balance updates of this SSI by the caller, retry after {} seconds",
+                    wait_time.div_ceil(1_000_000_000)
+                ),
+                retry_after_nanos: Some(wait_time),
+            }),
+        }
+    })
+}
+
+/// Returns Ok if the mode of the minter allows the operation for the given account.
+/// Redemptions remain available when the minter stops issuing SUSD.
+fn check_operation_mode(mode: &state::Mode, op: SyronOperation, account: &Account) -> Result<(), String> {
+    match op {
+        SyronOperation::RedeemBitcoin => mode.is_withdrawal_available_for(account),
+        _ => mode.is_deposit_available_for(account),
+    }
+}
+
 /// Notifies the minter to update the balance of the user subaccount.
 pub async fn update_ssi_balance(
     args: GetBoxAddressArgs,
-) -> Result<Vec<UtxoStatus>, UpdateBalanceError> {
+) -> MinterResult<Vec<UtxoStatus>> {
     let minter = ic_cdk::id();
     // @dev get user ssi account
-    let ssi_subaccount = compute_subaccount(0, &args.ssi);
-    let ssi_account = Account {
-        owner: minter,
-        subaccount: Some(ssi_subaccount)
-    };
+    let ssi_account = minter_account(SubaccountKind::Swap, &args.ssi);
 
-    state::read_state(|s| s.mode.is_deposit_available_for(&ssi_account))
+    state::read_state(|s| check_operation_mode(&s.mode, args.op, &ssi_account))
         .map_err(UpdateBalanceError::TemporarilyUnavailable)?;
 
-    // @review (guard) the guard was moved to the upstream function
-    // let _guard = balance_update_guard(ssi_account.clone())?;
+    let ssi_box_subaccount = compute_ssi_subaccount(SubaccountKind::Box, &args.ssi);
 
-    let ssi_box_subaccount = compute_subaccount(1, &args.ssi);
+    let _guard = operation_guard(minter_account(SubaccountKind::Box, &args.ssi), args.op)?;
     
     let mut utxo_statuses: Vec<UtxoStatus> = vec![];
 
     match args.op {
-        SyronOperation::GetSyron => {
-            let ssi_box_account = Account {
-                owner: minter,
-                subaccount: Some(ssi_box_subaccount)
-            };
-            
-            let ssi_balance_subaccount = compute_subaccount(2, &args.ssi);
-            let ssi_balance_account = Account {
-                owner: minter,
-                subaccount: Some(ssi_balance_subaccount)
-            };
+        SyronOperation::GetSyron | SyronOperation::AddCollateral => {
+            // @dev top-ups credit the collateral but never mint SUSD
+            let borrow = args.op == SyronOperation::GetSyron;
+
+            let ssi_box_account = minter_account(SubaccountKind::Box, &args.ssi);
+            let ssi_balance_account = minter_account(SubaccountKind::Balance, &args.ssi);
+
+            // @dev only the principal of the SSI can redirect its loan to another account
+            if args.owner.is_some() || args.subaccount.is_some() {
+                get_siwb_principal(&args.ssi).await?;
+            }
+            let ssi_balance_account = recipient_account(
+                minter,
+                ic_cdk::caller(),
+                args.owner,
+                args.subaccount,
+                ssi_balance_account,
+            )?;
         
             let box_address = state::read_state(|s| {
                 get_btc_address::ssi_account_to_p2wpkh_address_from_state(s, &ssi_box_account, &args.ssi)
@@ -379,12 +938,27 @@ pub async fn update_ssi_balance(
         
             let (btc_network, min_confirmations) =
                 state::read_state(|s| (s.btc_network, s.min_confirmations));
+            let min_confirmations = effective_min_confirmations(args.min_confirmations, min_confirmations)?;
+
+            // @dev fetch all UTXOs once with a zero confirmation limit and split them locally,
+            // so that we can indicate the approximate wait time to the caller without a second call
+            let GetUtxosResponse {
+                tip_height,
+                utxos,
+                ..
+            } = get_utxos(
+                btc_network,
+                &box_address,
+                /*min_confirmations=*/ 0,
+                CallSource::Client,
+            )
+            .await?;
+
+            let (utxos, unconfirmed_utxos): (Vec<Utxo>, Vec<Utxo>) = utxos
+                .into_iter()
+                .partition(|u| is_confirmed(u, tip_height, min_confirmations));
         
-            let utxos = get_utxos(btc_network, &box_address, min_confirmations, CallSource::Client)
-                .await?
-                .utxos;
-        
-            let new_utxos = state::read_state(|s| s.new_utxos_for_account(utxos, &ssi_box_account));
+            let mut new_utxos = state::read_state(|s| s.new_utxos_for_account(utxos, &ssi_box_account));
         
             // Remove pending finalized transactions
             state::mutate_state(|s| s.finalized_utxos.remove(&ssi_box_account));
@@ -395,46 +969,10 @@ pub async fn update_ssi_balance(
                 // We bail out early if there are no UTXOs to avoid creating a new entry
                 // in the UTXOs map. If we allowed empty entries, malicious callers
                 // could exhaust the canister memory.
-        
-                // We get the entire list of UTXOs again with a zero
-                // confirmation limit so that we can indicate the approximate
-                // wait time to the caller.
-                let GetUtxosResponse {
-                    tip_height,
-                    mut utxos,
-                    ..
-                } = get_utxos(
-                    btc_network,
-                    &box_address,
-                    /*min_confirmations=*/ 0,
-                    CallSource::Client,
-                )
-                .await?;
-        
-                utxos.retain(|u| {
-                    tip_height
-                        < u.height
-                            .checked_add(min_confirmations)
-                            .expect("bug: this shouldn't overflow")
-                            .checked_sub(1)
-                            .expect("bug: this shouldn't underflow")
-                });
-                let pending_utxos: Vec<PendingUtxo> = utxos
-                    .iter()
-                    .map(|u| PendingUtxo {
-                        outpoint: u.outpoint.clone(),
-                        value: u.value,
-                        confirmations: tip_height - u.height + 1,
-                    })
-                    .collect();
-        
-                let current_confirmations = pending_utxos.iter().map(|u| u.confirmations).max();
-        
-                return Err(UpdateBalanceError::NoNewUtxos {
-                    current_confirmations,
-                    required_confirmations: min_confirmations,
-                    pending_utxos: Some(pending_utxos),
-                });
+                let pending_utxos = to_pending_utxos(unconfirmed_utxos, tip_height, min_confirmations);
+                let max_pending_utxos = read_state(|s| s.max_pending_utxos) as usize;
+
+                return Err(no_new_utxos_error(pending_utxos, min_confirmations, max_pending_utxos));
             }
         
             let token_name = match btc_network {
@@ -445,20 +983,57 @@ pub async fn update_ssi_balance(
             let kyt_fee = read_state(|s| s.kyt_fee);
             let min_deposit = read_state(|s| s.min_btc_deposit);
 
+            // @dev the collateral of the SSI, tracked across the loop to enforce the per-account cap
+            let max_collateral = read_state(|s| s.max_collateral_per_account);
+            let mut collateral = if max_collateral == u64::MAX {
+                0
+            } else {
+                balance_of(SyronLedger::BTC, &args.ssi, SubaccountKind::Box.nonce()).await?
+            };
+
+            // @dev process a bounded number of UTXOs per call so that large accounts cannot exhaust the instruction limit
+            let max_utxos = read_state(|s| s.max_utxos_per_update) as usize;
+            let deferred_utxos = if new_utxos.len() > max_utxos {
+                new_utxos.split_off(max_utxos)
+            } else {
+                vec![]
+            };
+
             for utxo in new_utxos {
-                if utxo.value < min_deposit {
-                    mutate_state(|s| crate::state::audit::ignore_utxo(s, utxo.clone()));
+                let amount = match deposit_amount(utxo.value, min_deposit, kyt_fee) {
+                    Ok(amount) => amount,
+                    Err(reason) => {
+                        mutate_state(|s| crate::state::audit::ignore_utxo(s, utxo.clone()));
+                        log!(
+                            P1,
+                            "Ignored UTXO {} for account {ssi_box_account} ({:?}): UTXO value {}, minimum deposit amount {}, KYT fee {}",
+                            DisplayOutpoint(&utxo.outpoint),
+                            reason,
+                            DisplayAmount(utxo.value),
+                            DisplayAmount(min_deposit),
+                            DisplayAmount(kyt_fee),
+                        );
+                        utxo_statuses.push(UtxoStatus::Ignored { utxo, reason });
+                        continue;
+                    }
+                };
+                
+                if let Err(headroom) = check_collateral_cap(collateral, amount, max_collateral) {
+                    // @dev the UTXO is not marked as ignored, so it is processed again once the cap allows it
                     log!(
                         P1,
-                        "Ignored UTXO {} for account {ssi_box_account} because UTXO value {} is lower than the minimum deposit amount {}",
+                        "Deferred UTXO {} for account {ssi_box_account}: the deposit of {} exceeds the collateral headroom of {}",
                         DisplayOutpoint(&utxo.outpoint),
-                        DisplayAmount(utxo.value),
-                        DisplayAmount(min_deposit),
+                        DisplayAmount(amount),
+                        DisplayAmount(headroom),
                     );
-                    utxo_statuses.push(UtxoStatus::ValueTooSmall(utxo));
+                    utxo_statuses.push(UtxoStatus::Ignored {
+                        utxo,
+                        reason: IgnoredReason::CollateralCapExceeded { headroom },
+                    });
                     continue;
                 }
-                
+
                 // @review (kyt)
                 // let (uuid, status, kyt_provider) = kyt_check_utxo(caller_account.owner, &utxo).await?;
                 // mutate_state(|s| {
@@ -468,15 +1043,16 @@ pub async fn update_ssi_balance(
                 //     utxo_statuses.push(UtxoStatus::Tainted(utxo.clone()));
                 //     continue;
                 // }
-                let amount = utxo.value - kyt_fee;
-                let memo = MintMemo::Convert {
-                    txid: Some(utxo.outpoint.txid.as_ref()),
-                    vout: Some(utxo.outpoint.vout),
-                    kyt_fee: Some(kyt_fee),
-                };
+                let memo = MintMemo::convert(utxo.outpoint.txid.as_ref(), utxo.outpoint.vout);
         
-                match mint(&args.ssi, amount, ssi_box_account, crate::memo::encode(&memo).into(), ssi_balance_account).await {
-                    Ok(block_index) => {
+                match mint(&args.ssi, amount, ssi_box_account, crate::memo::encode(&memo).into(), ssi_balance_account, &utxo.outpoint, borrow).await {
+                    Ok((block_indices, collateralized_account)) => {
+                        collateral = collateral.saturating_add(amount);
+                        crate::metrics::increment(&crate::metrics::MINTED_TOTAL);
+                        crate::storage::record_protocol_event(&ProtocolEvent {
+                            outpoint: Some(utxo.outpoint.clone()),
+                            ..protocol_event(ProtocolEventKind::Mint, &args.ssi, amount, block_indices.collateral)
+                        });
                         log!(
                             P1,
                             "Minted {amount} {token_name} for account {ssi_box_account} corresponding to utxo {} with value {}",
@@ -489,16 +1065,19 @@ pub async fn update_ssi_balance(
                             state::audit::add_utxos(
                                 false,
                                 s,
-                                Some(block_index[0]),
+                                Some(block_indices.collateral),
                                 ssi_box_account,
                                 vec![utxo.clone()],
                                 Some(args.ssi.clone()),
                             )
                         });
                         utxo_statuses.push(UtxoStatus::Minted {
-                            block_index: block_index[0],
+                            block_index: block_indices.collateral,
+                            block_indices: block_indices.to_vec(),
                             utxo,
                             minted_amount: amount,
+                            exchange_rate: collateralized_account.exchange_rate,
+                            collateral_ratio: collateralized_account.collateral_ratio,
                         });
                     }
                     Err(err) => {
@@ -508,12 +1087,15 @@ pub async fn update_ssi_balance(
                             DisplayOutpoint(&utxo.outpoint),
                             err
                         );
+                        crate::metrics::increment(&crate::metrics::MINT_FAILURES_TOTAL);
                         utxo_statuses.push(UtxoStatus::Checked(utxo));
                         return Err(err);
                     }
                 }
             }
         
+            utxo_statuses.extend(deferred_utxos.into_iter().map(UtxoStatus::Deferred));
+
             // let res = match mint(satoshis_to_mint, caller_account).await {
             //     Ok(res) => Ok(utxo_statuses),
             //     Err(res) => Err(res)
@@ -521,75 +1103,185 @@ pub async fn update_ssi_balance(
             // return res
         },
         SyronOperation::RedeemBitcoin => {
+            // @dev only the principal of the SSI can redeem its collateral
+            get_siwb_principal(&args.ssi).await?;
+
             let minter_account = Account{
                 owner: minter,
                 subaccount: None
             };
 
-            let btc_1 = balance_of(SyronLedger::BTC, &args.ssi, 1).await.unwrap_or(0);
-            let susd_1 = balance_of(SyronLedger::SYRON, &args.ssi, 1).await.unwrap_or(0);
-    
-            // @dev Throw an error if the bitcoin collateral balance is zero
-            if btc_1 == 0 {
-                return Err(UpdateBalanceError::GenericError {
-                    error_code: ErrorCode::UnsupportedOperation as u64,
-                    error_message: "@update_ssi_balance: Invalid balance to redeem BTC".to_string()
-                });
-            }
+            // @dev the redeemed BTC is sent to the given address, or to the SSI by default for the KYT check
+            let destination = args.btc_address.as_deref().map(redemption_destination).transpose()?;
+            let kyt_address = args.btc_address.clone().unwrap_or_else(|| args.ssi.clone());
 
-            // Syron bitcoin ledger
-            let sbtc_client = ICRC1Client {
-                runtime: CdkRuntime,
-                ledger_canister_id: state::read_state(|s| s.ledger_id.get().into()),
+            // @dev the SBTC collateral to redeem and the SUSD loan to repay
+            let (btc_1, susd_1) = match args.amount {
+                None => {
+                    let btc_1 = balance_of(SyronLedger::BTC, &args.ssi, SubaccountKind::Box.nonce()).await?;
+                    let susd_1 = balance_of(SyronLedger::SYRON, &args.ssi, SubaccountKind::Box.nonce()).await?;
+            
+                    // @dev Throw an error if the bitcoin collateral balance is zero
+                    if btc_1 == 0 {
+                        return Err(UpdateBalanceError::GenericError {
+                            error_code: ErrorCode::UnsupportedOperation as u64,
+                            error_message: "@update_ssi_balance: Invalid balance to redeem BTC".to_string()
+                        });
+                    }
+                    check_redemption_amount(btc_1)?;
+                    kyt_check_withdrawal(ic_cdk::caller(), &kyt_address, btc_1).await?;
+                    settle_accrued_stability_fee(&args.ssi, susd_1, "@update_ssi_balance: Cannot pay the stability fee").await?;
+                    (btc_1, susd_1)
+                },
+                Some(amount) => {
+                    check_redemption_amount(amount)?;
+                    kyt_check_withdrawal(ic_cdk::caller(), &kyt_address, amount).await?;
+                    let collateralized_account = get_collateralized_account(&args.ssi).await?;
+                    let redemption = partial_redemption(&collateralized_account, amount, read_state(|s| s.min_collateral_ratio_bps))?;
+                    // @dev the accrued stability fee is settled first, so the repayment only reduces the principal
+                    if collateralized_account.stability_fee != 0 {
+                        pay_stability_fee(&args.ssi, collateralized_account.stability_fee, "@update_ssi_balance: Cannot pay the stability fee").await?;
+                    }
+                    redemption
+                }
             };
-            sbtc_client
+
+            // Syron stablecoin ledger
+            // @dev the loan is repaid before any collateral leaves the box
+            if susd_1 != 0 {
+                let susd_client = ICRC1Client {
+                    runtime: CdkRuntime,
+                    ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
+                };
+
+                susd_client
                 .transfer(TransferArg {
                     from_subaccount: Some(ssi_box_subaccount),
                     to: minter_account,
                     fee: None,
                     created_at_time: None,
                     memo: None,
-                    amount: Nat::from(btc_1),
+                    amount: Nat::from(susd_1),
                 })
                 .await
                 .map_err(|(code, msg)| {
                     UpdateBalanceError::TemporarilyUnavailable(format!(
-                        "update_ssi_balance: Cannot redeem bitcoin: {} (reject_code = {})",
+                        "update_ssi_balance: Cannot grant loan repayment: {} (reject_code = {})",
                         msg, code
                     ))
-                })??;
-        
-            // Syron stablecoin ledger
-            if susd_1 != 0 {
-                let susd_client = ICRC1Client {
-                    runtime: CdkRuntime,
-                    ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
-                };
+                })?
+                .map_err(|err| redemption_transfer_error(err, "@update_ssi_balance: Cannot grant loan repayment"))?;
+                mutate_state(|s| state::audit::repaid(s, args.ssi.clone(), 0, susd_1));
+            }
 
-                susd_client
+            // Syron bitcoin ledger
+            // @dev if the transfer fails, the collateral stays in the box of the repaid loan and
+            // can be redeemed again
+            let sbtc_client = ICRC1Client {
+                runtime: CdkRuntime,
+                ledger_canister_id: state::read_state(|s| s.ledger_id.get().into()),
+            };
+            // @dev the redemption fee is deducted from the redeemed SBTC @governance
+            let (net_amount, fee) = redemption_fee(btc_1, read_state(|s| s.redemption_fee_bps));
+            let redeem_block_index = sbtc_client
                 .transfer(TransferArg {
                     from_subaccount: Some(ssi_box_subaccount),
                     to: minter_account,
                     fee: None,
                     created_at_time: None,
                     memo: None,
-                    amount: Nat::from(susd_1),
+                    amount: Nat::from(net_amount),
                 })
                 .await
                 .map_err(|(code, msg)| {
                     UpdateBalanceError::TemporarilyUnavailable(format!(
-                        "update_ssi_balance: Cannot grant loan repayment: {} (reject_code = {})",
+                        "update_ssi_balance: Cannot redeem bitcoin: {} (reject_code = {})",
                         msg, code
                     ))
-                })??;
+                })?
+                .map_err(|err| redemption_transfer_error(err, "@update_ssi_balance: Cannot redeem bitcoin"))?;
+            let redeem_block_index = redeem_block_index.0.to_u64()
+                .ok_or_else(|| UpdateBalanceError::SystemError{
+                    method: "update_ssi_balance".to_string(),
+                    reason: "Block index too large for u64".to_string()
+                })?;
+            let mut released = net_amount;
+            if fee != 0 {
+                let fee_transfer = sbtc_client
+                    .transfer(TransferArg {
+                        from_subaccount: Some(ssi_box_subaccount),
+                        to: treasury_account(),
+                        fee: None,
+                        created_at_time: None,
+                        memo: None,
+                        amount: Nat::from(fee),
+                    })
+                    .await;
+                if matches!(fee_transfer, Ok(Ok(_))) {
+                    released = btc_1;
+                } else {
+                    // @dev the fee stays in the box as collateral, so the redemption is not reverted
+                    log!(
+                        P0,
+                        "[update_ssi_balance]: failed to transfer the redemption fee of {} (SBTC) of SSI {} to the treasury: {:?}",
+                        DisplayAmount(fee),
+                        args.ssi,
+                        fee_transfer
+                    );
+                }
+            }
+            mutate_state(|s| state::audit::repaid(s, args.ssi.clone(), released, 0));
+            crate::storage::record_protocol_event(&ProtocolEvent {
+                btc_address: args.btc_address.clone(),
+                ..protocol_event(ProtocolEventKind::Redeem, &args.ssi, net_amount, redeem_block_index)
+            });
+            let mut request_id = None;
+            if let Some(address) = destination {
+                // @dev the retrieval queue builds, signs and sends the transaction, spending the
+                // minter's UTXOs and returning the change to the vault
+                // @dev redemptions take their request ids from their own range, so they never
+                // collide with the ledger block indices of the retrieve_btc requests
+                let id = read_state(|s| s.next_redemption_request_id());
+                request_id = Some(id);
+                let request = state::RetrieveBtcRequest {
+                    amount: net_amount,
+                    address,
+                    block_index: id,
+                    received_at: ic_cdk::api::time(),
+                    kyt_provider: None,
+                    reimbursement_account: Some(Account {
+                        owner: minter,
+                        subaccount: Some(ssi_box_subaccount)
+                    }),
+                };
+                log!(
+                    P1,
+                    "accepted a redemption of {} (BTC) of SSI {} to address {}",
+                    DisplayAmount(net_amount),
+                    args.ssi,
+                    args.btc_address.as_deref().unwrap_or_default()
+                );
+                mutate_state(|s| state::audit::accept_retrieve_btc_request(s, request));
+                schedule_now(TaskType::ProcessLogic);
             }
+            utxo_statuses.push(UtxoStatus::Redeemed {
+                block_index: redeem_block_index,
+                request_id,
+                gross_amount: btc_1,
+                fee,
+                net_amount,
+            });
         },
         SyronOperation::Liquidation => {
-            // invalid operation, throw error
-            return Err(UpdateBalanceError::GenericError {  
+            let repay_amount = args.amount.ok_or_else(|| UpdateBalanceError::GenericError {
                 error_code: ErrorCode::UnsupportedOperation as u64,
-                error_message: "@update_ssi_balance: Invalid liquidation operation".to_string()
-            });
+                error_message: "@update_ssi_balance: The liquidation requires the amount of SUSD to repay".to_string()
+            })?;
+            let liquidator = Account {
+                owner: ic_cdk::caller(),
+                subaccount: args.subaccount,
+            };
+            utxo_statuses.push(liquidate(&args.ssi, repay_amount, liquidator).await?);
         },
         SyronOperation::Payment => {
             // invalid operation, throw error
@@ -599,7 +1291,7 @@ pub async fn update_ssi_balance(
             });
         },
         SyronOperation::DepositSyron => {
-            let current_runes_deposit = balance_of(SyronLedger::SYRON, &args.ssi, 5).await.unwrap_or(0);
+            let current_runes_deposit = balance_of(SyronLedger::SYRON, &args.ssi, SubaccountKind::PendingRunes.nonce()).await.unwrap_or(0);
             // @dev throw error if zero
             if current_runes_deposit == 0 {
                 return Err(UpdateBalanceError::GenericError {
@@ -607,7 +1299,34 @@ pub async fn update_ssi_balance(
                     error_message: "@update_ssi_balance: No runes deposit balance available".to_string()
                 });
             }
-            let _index = syron_update(&args.ssi, 5, Some(2), current_runes_deposit).await?;
+
+            // @dev reconfirm the pending credit against the runes held on-chain by the runes minter
+            let (_, runes_utxos) = crate::runes::check_runes_minter_utxos().await?;
+            if !read_state(|s| is_pending_runes_deposit_backed(s, &args.ssi, current_runes_deposit, &runes_utxos)) {
+                log!(
+                    P0,
+                    "Reverting the runes deposit of {} for SSI {} because the indexer does not confirm it",
+                    DisplayAmount(current_runes_deposit),
+                    args.ssi,
+                );
+                syron_runes_deposit(&args.ssi, current_runes_deposit, true, vec![]).await?;
+                return Err(UpdateBalanceError::GenericError {
+                    error_code: ErrorCode::InsufficientAmount as u64,
+                    error_message: "@update_ssi_balance: The runes deposit does not match the indexer balance".to_string()
+                });
+            }
+
+            let deposited_amount = runes_deposit_amount(args.amount, current_runes_deposit)?;
+            let fee = pinned_fee(read_state(|s| s.susd_id.get().into())).await?;
+            let block_index = syron_update(&args.ssi, SubaccountKind::PendingRunes.nonce(), Some(SubaccountKind::Balance.nonce()), deposited_amount, fee).await?;
+            mutate_state(|s| {
+                state::audit::settle_pending_runes_deposit(s, args.ssi.clone(), deposited_amount)
+            });
+            utxo_statuses.push(UtxoStatus::SyronDeposited {
+                block_index,
+                deposited_amount,
+                remaining_amount: current_runes_deposit - deposited_amount,
+            });
         }
     }
     schedule_now(TaskType::ProcessLogic);
@@ -615,8 +1334,49 @@ pub async fn update_ssi_balance(
     Ok(utxo_statuses)
 }
 
+/// The maximum number of SSIs that [update_ssi_balance_batch] processes in one call.
+pub const MAX_BATCH_SIZE: usize = 10;
+
+/// Updates the balances of several SSIs sequentially, returning the results in input order.
+///
+/// The failure of an SSI does not abort the others. Traps if the batch has more than
+/// [MAX_BATCH_SIZE] SSIs.
+pub async fn update_ssi_balance_batch(
+    args: Vec<GetBoxAddressArgs>,
+) -> Vec<MinterResult<Vec<UtxoStatus>>> {
+    if args.len() > MAX_BATCH_SIZE {
+        ic_cdk::trap(&format!(
+            "@update_ssi_balance_batch: at most {MAX_BATCH_SIZE} SSIs per batch, got {}",
+            args.len()
+        ));
+    }
+    let mut results = Vec::with_capacity(args.len());
+    for arg in args {
+        results.push(update_ssi_balance(arg).await);
+    }
+    results
+}
+
+/// Removes duplicate outpoints from the sats and runes UTXOs of the runes minter, so that
+/// each UTXO is credited once.
+///
+/// An outpoint present in both lists is kept as a runes UTXO (credited to the runes
+/// subaccount), so that it is never spent as a plain sats UTXO.
+fn dedup_runes_minter_utxos(sats_utxos: Vec<Utxo>, runes_utxos: Vec<Utxo>) -> (Vec<Utxo>, Vec<Utxo>) {
+    let mut seen = std::collections::BTreeSet::new();
+    let runes_utxos: Vec<Utxo> = runes_utxos
+        .into_iter()
+        .filter(|u| seen.insert(u.outpoint.clone()))
+        .collect();
+    let sats_utxos = sats_utxos
+        .into_iter()
+        .filter(|u| seen.insert(u.outpoint.clone()))
+        .collect();
+    (sats_utxos, runes_utxos)
+}
+
 /// Notifies the minter to update its own balance of syron runes.
-pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<UtxoStatus>, UpdateBalanceError> {
+pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> MinterResult<Vec<UtxoStatus>> {
     // @dev get minter runes address
     let dao_addr = state::read_state(|s| s.dao_addr.clone());
     
@@ -627,11 +1387,7 @@ pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<U
         state::read_state(|s| (s.btc_network, s.min_confirmations));
         
     let treasury_addr = treasury_address.display(btc_network); 
-    let runes_minter_subaccount = compute_subaccount(1, &treasury_addr);
-    let runes_minter_account = Account {
-        owner: ic_cdk::id(),
-        subaccount: Some(runes_minter_subaccount)
-    };
+    let runes_minter_account = minter_account(SubaccountKind::Box, &treasury_addr);
     state::read_state(|s| s.mode.is_deposit_available_for(&runes_minter_account))
         .map_err(UpdateBalanceError::TemporarilyUnavailable)?;
 
@@ -639,8 +1395,9 @@ pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<U
 
     let mut utxo_statuses: Vec<UtxoStatus> = vec![];
 
-    let new_sats_utxos = state::read_state(|s| s.new_utxos_for_account(utxos.0, &runes_minter_account));
-    let new_runes_utxos = state::read_state(|s| s.new_utxos_for_account(utxos.1, &runes_minter_account));
+    let (sats_utxos, runes_utxos) = dedup_runes_minter_utxos(utxos.0, utxos.1);
+    let new_sats_utxos = state::read_state(|s| s.new_utxos_for_account(sats_utxos, &runes_minter_account));
+    let new_runes_utxos = state::read_state(|s| s.new_utxos_for_account(runes_utxos, &runes_minter_account));
     let mut total_utxos = new_sats_utxos.clone();
     total_utxos.extend(new_runes_utxos.clone());
 
@@ -663,7 +1420,7 @@ pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<U
         // wait time to the caller.
         let GetUtxosResponse {
             tip_height,
-            mut utxos,
+            utxos,
             ..
         } = get_utxos(
             btc_network,
@@ -673,45 +1430,17 @@ pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<U
         )
         .await?;
 
-        utxos.retain(|u| {
-            tip_height
-                < u.height
-                    .checked_add(min_confirmations)
-                    .expect("bug: this shouldn't overflow")
-                    .checked_sub(1)
-                    .expect("bug: this shouldn't underflow")
-        });
-        let pending_utxos: Vec<PendingUtxo> = utxos
-            .iter()
-            .map(|u| PendingUtxo {
-                outpoint: u.outpoint.clone(),
-                value: u.value,
-                confirmations: tip_height - u.height + 1,
-            })
-            .collect();
-
-        let current_confirmations = pending_utxos.iter().map(|u| u.confirmations).max();
+        let pending_utxos = to_pending_utxos(utxos, tip_height, min_confirmations);
+        let max_pending_utxos = read_state(|s| s.max_pending_utxos) as usize;
 
-        return Err(UpdateBalanceError::NoNewUtxos {
-            current_confirmations,
-            required_confirmations: min_confirmations,
-            pending_utxos: Some(pending_utxos),
-        });
+        return Err(no_new_utxos_error(pending_utxos, min_confirmations, max_pending_utxos));
     }
 
     // @dev use box subaccount for gas and runes subaccount for stablecoin balances of the runes minter
-    let runes_subaccount = compute_subaccount(4, &treasury_addr);
-    let runes_ledger_account = Account {
-        owner: ic_cdk::id(),
-        subaccount: Some(runes_subaccount)
-    };
+    let runes_ledger_account = minter_account(SubaccountKind::Runes, &treasury_addr);
 
     for utxo in new_sats_utxos {
-        let memo = MintMemo::Convert {
-            txid: Some(utxo.outpoint.txid.as_ref()),
-            vout: Some(utxo.outpoint.vout),
-            kyt_fee: None,
-        };
+        let memo = MintMemo::convert(utxo.outpoint.txid.as_ref(), utxo.outpoint.vout);
 
         match count_runes_minter(utxo.value, runes_minter_account, crate::memo::encode(&memo).into()).await {
             Ok(block_index) => {
@@ -741,11 +1470,7 @@ pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<U
     }
 
     for utxo in new_runes_utxos {
-        let memo = MintMemo::Convert {
-            txid: Some(utxo.outpoint.txid.as_ref()),
-            vout: Some(utxo.outpoint.vout),
-            kyt_fee: None,
-        };
+        let memo = MintMemo::convert(utxo.outpoint.txid.as_ref(), utxo.outpoint.vout);
 
         match count_runes_minter(utxo.value, runes_ledger_account, crate::memo::encode(&memo).into()).await {
             Ok(block_index) => {
@@ -779,11 +1504,14 @@ pub async fn update_runes_balance(utxos: (Vec<Utxo>, Vec<Utxo>)) -> Result<Vec<U
     Ok(utxo_statuses)
 }
 
-async fn count_runes_minter(runes: u64, to: Account, memo: Memo) -> Result<u64, UpdateBalanceError> {
+async fn count_runes_minter(runes: u64, to: Account, memo: Memo) -> MinterResult<u64> {
     let btc_client = ICRC1Client {
         runtime: CdkRuntime,
         ledger_canister_id: state::read_state(|s| s.ledger_id.get().into()),
     };
+    // @dev a mint from the minting account pays no fee
+    check_amount_covers_fee(runes, 0, "count_runes_minter")?;
+    debug_assert!(memo.0.len() <= crate::memo::MAX_MEMO_SIZE);
 
     let block_index = btc_client
         .transfer(TransferArg {
@@ -791,7 +1519,7 @@ async fn count_runes_minter(runes: u64, to: Account, memo: Memo) -> Result<u64,
             to,
             fee: None,
             created_at_time: None,
-            memo: None, //Some(memo), // @review (alpha) 'the memo field size of 39 bytes is above the allowed limit of 32 bytes'.
+            memo: Some(memo),
             amount: Nat::from(runes),
         })
         .await
@@ -810,10 +1538,68 @@ async fn count_runes_minter(runes: u64, to: Account, memo: Memo) -> Result<u64,
     Ok(res)
 }
 
+/// Screens the redemption of `amount` satoshis to the given Bitcoin address with the KYT
+/// canister and refuses it if the address has alerts. Redemptions are not screened if no
+/// KYT canister is configured.
+async fn kyt_check_withdrawal(
+    caller: Principal,
+    address: &str,
+    amount: u64,
+) -> MinterResult<()> {
+    let kyt_principal = match read_state(|s| s.kyt_principal) {
+        Some(kyt_principal) => kyt_principal.get().into(),
+        None => return Ok(()),
+    };
+
+    let response = fetch_withdrawal_alerts(kyt_principal, caller, address.to_string(), amount)
+        .await
+        .map_err(|call_err| {
+            UpdateBalanceError::TemporarilyUnavailable(format!(
+                "Failed to call KYT canister: {}",
+                call_err
+            ))
+        })?;
+    kyt_withdrawal_outcome(address, response)
+}
+
+/// Maps the response of the KYT canister to the outcome of the withdrawal check: a refusal if
+/// the destination address raised alerts.
+fn kyt_withdrawal_outcome(address: &str, response: Result<FetchAlertsResponse, KytError>) -> MinterResult<()> {
+    match response {
+        Ok(response) => {
+            if !response.alerts.is_empty() {
+                log!(
+                    P0,
+                    "Discovered a tainted withdrawal address {} (external id {})",
+                    address,
+                    response.external_id
+                );
+                Err(UpdateBalanceError::GenericError {
+                    error_code: ErrorCode::TaintedAddress as u64,
+                    error_message: format!("@update_ssi_balance: The redemption to {} was refused by the KYT check", address),
+                })
+            } else {
+                Ok(())
+            }
+        }
+        Err(KytError::TemporarilyUnavailable(reason)) => {
+            log!(
+                P1,
+                "The KYT provider is temporarily unavailable: {}",
+                reason
+            );
+            Err(UpdateBalanceError::TemporarilyUnavailable(format!(
+                "The KYT provider is temporarily unavailable: {}",
+                reason
+            )))
+        }
+    }
+}
+
 async fn _kyt_check_utxo(
     caller: Principal,
     utxo: &Utxo,
-) -> Result<(String, UtxoCheckStatus, Principal), UpdateBalanceError> {
+) -> MinterResult<(String, UtxoCheckStatus, Principal)> {
     let kyt_principal = read_state(|s| {
         s.kyt_principal
             .expect("BUG: upgrade procedure must ensure that the KYT principal is set")
@@ -869,45 +1655,264 @@ async fn _kyt_check_utxo(
     }
 }
 
-/// Registers the amount of bitcoin collateral, the syron loan, and the available balance.
-pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, account: Account) -> Result<Vec<u64 /*UtxoStatus*/>, UpdateBalanceError> {
-    let collateralized_account = get_collateralized_account(ssi).await?;
-    let exchange_rate = collateralized_account.exchange_rate;
+/// Returns the first attempt to mint for the given outpoint if it is still within
+/// [MINT_DEDUP_WINDOW_NANOS], so that a retry repeats it and the ledger collapses the
+/// retry into the original transaction instead of minting twice.
+fn pending_mint_attempt(attempts: &BTreeMap<OutPoint, MintAttempt>, outpoint: &OutPoint, now: u64) -> Option<MintAttempt> {
+    attempts
+        .get(outpoint)
+        .filter(|attempt| now.saturating_sub(attempt.created_at_time) < MINT_DEDUP_WINDOW_NANOS)
+        .cloned()
+}
 
-    // @notice We assume that the current collateral ratio is >= 15,000 basis points.
-    let mut susd: u64 = satoshis * exchange_rate / 15 * 10; //@review (mainnet) over-collateralization ratio (1.5)
+/// Treats a transfer that the ledger deduplicated as successful and returns the block index
+/// of the original transaction, and whether the transfer is new.
+fn dedup_transfer_result(result: Result<Nat, TransferError>) -> Result<(Nat, bool), TransferError> {
+    match result {
+        Err(TransferError::Duplicate { duplicate_of }) => Ok((duplicate_of, false)),
+        result => result.map(|block_index| (block_index, true)),
+    }
+}
+
+/// Returns the largest SUSD loan that `satoshis` of collateral can back at the given minimum
+/// collateral ratio (in basis points).
+fn susd_capacity(satoshis: u64, exchange_rate: u64, min_collateral_ratio: u64) -> u64 {
+    let susd = satoshis as u128 * exchange_rate as u128 * 10_000 / min_collateral_ratio.max(1) as u128;
+    susd.min(u64::MAX as u128) as u64
+}
 
-    // if the collateral ratio is less than 15000 basis points, then the user cannot withdraw SUSD amount, can withdraw an amount of SUSD so that the collateral ratio is at least 15000 basis points
-    if collateralized_account.collateral_ratio < 15000 {
-        // calculate the amount of satoshis required so that the collateral ratio is at least 15000 basis points
-        let sats = ((1.5 * collateralized_account.susd_1 as f64 / exchange_rate as f64) as u64 - collateralized_account.btc_1).max(0);
+/// Returns the additional SUSD that the given account can borrow against its current
+/// collateral while keeping the minimum collateral ratio.
+pub fn borrowable_susd(account: &CollateralizedAccount, min_collateral_ratio: u64) -> u64 {
+    susd_capacity(account.btc_1, account.exchange_rate, min_collateral_ratio).saturating_sub(account.debt())
+}
 
-        let accepted_deposit = (satoshis - sats).max(0);
+/// Returns the additional SUSD that the given SSI can borrow against its current collateral.
+pub async fn max_borrowable_susd(ssi: String) -> MinterResult<u64> {
+    let collateralized_account = get_collateralized_account(&ssi).await?;
+    Ok(borrowable_susd(&collateralized_account, read_state(|s| s.min_collateral_ratio_bps)))
+}
 
-        // calculate the maximum amount of susd that can be withdrawn
-        if accepted_deposit > 0 {
-            // @runes
-            // susd = accepted_deposit * exchange_rate / 15 * 10;
-        } else {
-            // all satoshis are deposited but no new SUSD can be minted
-            susd = 0;
+/// Returns the given account after a deposit of `satoshis` that mints no SUSD.
+fn with_added_collateral(account: CollateralizedAccount, satoshis: u64) -> CollateralizedAccount {
+    let btc_1 = account.btc_1.saturating_add(satoshis);
+    let collateral_ratio = if account.susd_1 == 0 {
+        account.collateral_ratio
+    } else {
+        collateral_ratio(btc_1, account.debt(), account.exchange_rate)
+    };
+    CollateralizedAccount {
+        btc_1,
+        collateral_ratio,
+        ..account
+    }
+}
+
+/// Returns the amount of SUSD that a deposit of `satoshis` would mint for the given account,
+/// i.e. what it can borrow against its collateral after the deposit.
+pub fn compute_mintable_susd(satoshis: u64, account: &CollateralizedAccount, min_collateral_ratio: u64) -> u64 {
+    borrowable_susd(&with_added_collateral(account.clone(), satoshis), min_collateral_ratio)
+}
+
+/// Returns an error if the account is the minter's default account, which collects the
+/// burns and fees, so that a mis-derived subaccount never mints into the protocol's pool.
+fn check_not_default_account(account: &Account, method: &str) -> MinterResult<()> {
+    if account.subaccount.is_none() || account.subaccount.as_ref() == Some(DEFAULT_SUBACCOUNT) {
+        return Err(UpdateBalanceError::SystemError {
+            method: method.to_string(),
+            reason: format!("Cannot mint to the default account of {}", account.owner),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the account credited with the borrowed SUSD: the SSI balance subaccount by
+/// default, or the given owner (the caller if unset) and subaccount.
+///
+/// Minter accounts cannot be given explicitly, since they belong to other SSIs.
+fn recipient_account(
+    minter: Principal,
+    caller: Principal,
+    owner: Option<Principal>,
+    subaccount: Option<Subaccount>,
+    ssi_balance_account: Account,
+) -> MinterResult<Account> {
+    if owner.is_none() && subaccount.is_none() {
+        return Ok(ssi_balance_account);
+    }
+    let owner = owner.unwrap_or(caller);
+    if owner == minter {
+        return Err(UpdateBalanceError::Unauthorized(
+            "cannot credit an account of the minter".to_string(),
+        ));
+    }
+    Ok(Account { owner, subaccount })
+}
+
+/// Returns the fee of the given ledger if the minter pins the fees of its transfers.
+pub(crate) async fn pinned_fee(ledger_id: Principal) -> MinterResult<Option<u64>> {
+    if !read_state(|s| s.pin_ledger_fees) {
+        return Ok(None);
+    }
+    ledger_fee(ledger_id).await.map(Some)
+}
+
+/// Returns the fee that a transfer of the minter states: the pinned fee for transfers between
+/// accounts, and none for mints from and burns to the minting account, which pay no fee.
+fn transfer_fee(pinned_fee: Option<u64>, from: &Account, to: &Account, minter: Principal) -> Option<u64> {
+    if is_mint_or_burn(from, to, minter) {
+        None
+    } else {
+        pinned_fee
+    }
+}
+
+/// Returns true if a transfer is a mint from or a burn to the minting account of the minter.
+fn is_mint_or_burn(from: &Account, to: &Account, minter: Principal) -> bool {
+    let minting_account = Account {
+        owner: minter,
+        subaccount: None,
+    };
+    *from == minting_account || *to == minting_account
+}
+
+/// Returns the transfer fee of the given ledger, querying it once per canister session.
+async fn ledger_fee(ledger_id: Principal) -> MinterResult<u64> {
+    if let Some(fee) = read_state(|s| s.ledger_fees.get(&ledger_id).copied()) {
+        return Ok(fee);
+    }
+    let client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: ledger_id,
+    };
+    let fee = client.fee().await.map_err(|(code, msg)| {
+        UpdateBalanceError::TemporarilyUnavailable(format!(
+            "Cannot query the ledger fee: {} (reject_code = {})",
+            msg, code
+        ))
+    })?;
+    let fee = fee.0.to_u64().ok_or_else(|| UpdateBalanceError::SystemError {
+        method: "ledger_fee".to_string(),
+        reason: "Ledger fee too large for u64".to_string()
+    })?;
+    mutate_state(|s| s.ledger_fees.insert(ledger_id, fee));
+    Ok(fee)
+}
+
+/// Returns an error if the transfer amount does not exceed the ledger fee.
+fn check_amount_covers_fee(amount: u64, fee: u64, method: &str) -> MinterResult<()> {
+    if amount <= fee {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!(
+                "@{}: The transfer amount ({}) must exceed the ledger fee ({})",
+                method, amount, fee
+            )
+        });
+    }
+    Ok(())
+}
+
+/// Returns the memos of the collateral and loan transfers of a mint. A retry of an attempt made
+/// before the convert memos repeats its compact memo on the collateral transfer and no memo on
+/// the loan transfers, so that the ledgers deduplicate the transfers that already succeeded.
+fn mint_memos(attempt: Option<&MintAttempt>, memo: Memo, outpoint: &OutPoint) -> (Memo, Option<Memo>) {
+    match attempt {
+        Some(attempt) if !attempt.convert_memo => {
+            let compact = MintMemo::compact(outpoint.txid.as_ref(), outpoint.vout);
+            (crate::memo::encode(&compact).into(), None)
         }
+        _ => (memo.clone(), Some(memo)),
+    }
+}
+
+/// Reserves `susd` against the debt ceiling until the returned reservation is dropped, or
+/// returns an error if lending it would push the total debt above the debt ceiling.
+fn reserve_debt(susd: u64) -> MinterResult<DebtReservation> {
+    DebtReservation::new(susd).map_err(|total_debt| UpdateBalanceError::GenericError {
+        error_code: ErrorCode::DebtCeilingExceeded as u64,
+        error_message: format!(
+            "@mint: Lending {} SUSD would exceed the debt ceiling of {} SUSD (current debt: {})",
+            susd,
+            read_state(|s| s.debt_ceiling_susd),
+            total_debt
+        )
+    })
+}
+
+/// Returns the amount of SUSD that a deposit of `satoshis` would mint for the given SSI,
+/// without transferring any tokens.
+pub async fn simulate_mint(ssi: String, satoshis: u64) -> MinterResult<u64> {
+    let collateralized_account = read_collateralized_account(&ssi).await?;
+    Ok(compute_mintable_susd(satoshis, &collateralized_account, read_state(|s| s.min_collateral_ratio_bps)))
+}
+
+/// Registers the amount of bitcoin collateral, the syron loan, and the available balance.
+/// Returns the block indices and the collateralized account used to compute the loan.
+///
+/// If `borrow` is false, only the collateral is registered and the returned account
+/// reflects the collateral ratio after the deposit.
+///
+/// A retry for the same `outpoint` repeats the first attempt, with the same amounts and
+/// `created_at_time`, so that the ledgers deduplicate the transfers that already succeeded.
+pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, account: Account, outpoint: &OutPoint, borrow: bool) -> MinterResult<(BlockIndices, CollateralizedAccount)> {
+    check_not_default_account(&to, "mint")?;
+    if account.owner == to.owner {
+        check_not_default_account(&account, "mint")?;
     }
 
+    let now = ic_cdk::api::time();
+    let mut collateralized_account = get_collateralized_account(ssi).await?;
+    let exchange_rate = collateralized_account.exchange_rate;
+    accept_exchange_rate(exchange_rate, now)?;
+    let attempt = read_state(|s| pending_mint_attempt(&s.mint_attempts, outpoint, now));
+    let susd = if borrow {
+        match &attempt {
+            Some(attempt) => attempt.susd,
+            None => compute_mintable_susd(satoshis, &collateralized_account, read_state(|s| s.min_collateral_ratio_bps)),
+        }
+    } else {
+        collateralized_account = with_added_collateral(collateralized_account, satoshis);
+        0
+    };
+    // @dev the reservation is taken before the first ledger call and held until the loan is recorded
+    let _reservation = if attempt.is_none() {
+        Some(reserve_debt(susd)?)
+    } else {
+        None
+    };
+
     let client = ICRC1Client {
         runtime: CdkRuntime,
         ledger_canister_id: state::read_state(|s| s.ledger_id.get().into()),
     };
+    // @dev the legs are mints, which pay no fee, so the amounts only need to be positive
+    check_amount_covers_fee(satoshis, 0, "mint")?;
+
+    let (memo, loan_memo) = mint_memos(attempt.as_ref(), memo, outpoint);
+    let created_at_time = match attempt {
+        Some(attempt) => attempt.created_at_time,
+        None => {
+            mutate_state(|s| {
+                state::audit::attempt_mint(
+                    s,
+                    outpoint.clone(),
+                    MintAttempt { created_at_time: now, susd, convert_memo: true },
+                )
+            });
+            now
+        }
+    };
 
-    // debug_assert!(memo.0.len() <= crate::LEDGER_MEMO_SIZE as usize); @review (mainnet)
-    // Canister called `ic0.trap` with message: the memo field size of 39 bytes is above the allowed limit of 32 bytes (reject_code = 5)"
+    debug_assert!(memo.0.len() <= crate::memo::MAX_MEMO_SIZE);
+    // @dev the transfers of a mint are mints from the minting account, which pay no fee
     let block_index_btc1 = client
         .transfer(TransferArg {
             from_subaccount: None,
             to,
             fee: None,
-            created_at_time: None,
-            memo: None,//Some(memo.clone()), @review (alpha) 'the memo field size of 39 bytes is above the allowed limit of 32 bytes'.
+            created_at_time: Some(created_at_time),
+            memo: Some(memo.clone()),
             amount: Nat::from(satoshis),
         })
         .await
@@ -916,10 +1921,26 @@ pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, acco
                 "@mint: Cannot register bitcoin collateral due to error ({} - reject_code = {})",
                 msg, code
             ))
-        })??;
+        })?;
+    let (block_index_btc1, new_collateral) = dedup_transfer_result(block_index_btc1)?;
 
-    let mut res: Vec<u64> = Vec::new();
-    res.push(block_index_btc1.0.to_u64().expect("@mint: Nat does not fit into u64"));
+    // @dev a deduplicated transfer was recorded by the attempt that made it
+    if new_collateral {
+        mutate_state(|s| state::audit::borrowed(s, ssi.to_string(), satoshis, 0));
+    }
+
+    let to_block_index = |block_index: Nat| {
+        block_index.0.to_u64()
+            .ok_or_else(|| UpdateBalanceError::SystemError{
+                method: "mint".to_string(),
+                reason: "Block index too large for u64".to_string()
+            })
+    };
+    let mut block_indices = BlockIndices {
+        collateral: to_block_index(block_index_btc1)?,
+        loan: None,
+        balance: None,
+    };
 
     if susd != 0 {
         // @dev SUSD
@@ -934,8 +1955,8 @@ pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, acco
                 from_subaccount: None,
                 to,
                 fee: None,
-                created_at_time: None,
-                memo: None, //Some(memo.clone()), @review (alpha) 'the memo field size of 39 bytes is above the allowed limit of 32 bytes'.
+                created_at_time: Some(created_at_time),
+                memo: loan_memo.clone(),
                 amount: Nat::from(susd),
             })
             .await
@@ -944,15 +1965,19 @@ pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, acco
                     "@mint: Cannot grant syron loan due to error ({} - reject_code = {})",
                     msg, code
                 ))
-            })??;
+            })?;
+        let (block_index_susd1, new_loan) = dedup_transfer_result(block_index_susd1)?;
+        if new_loan {
+            mutate_state(|s| state::audit::borrowed(s, ssi.to_string(), 0, susd));
+        }
 
         let block_index_susd2 = susd_client
             .transfer(TransferArg {
                 from_subaccount: None,
                 to: account,
                 fee: None,
-                created_at_time: None,
-                memo: None, //Some(memo.clone()), @review (alpha) 'the memo field size of 39 bytes is above the allowed limit of 32 bytes'.
+                created_at_time: Some(created_at_time),
+                memo: loan_memo.clone(),
                 amount: Nat::from(susd),
             })
             .await
@@ -961,7 +1986,8 @@ pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, acco
                     "@mint: Cannot update syron balance due to error ({} - reject_code = {})", // @review (alpha) if it fails, make sure that the fn fails entirely (revert previous updates in collateral and loan)
                     msg, code
                 ))
-            })??;
+            })?;
+        let (block_index_susd2, _) = dedup_transfer_result(block_index_susd2)?;
 
         // return Err(
         //     UpdateBalanceError::TemporarilyUnavailable(format!(
@@ -976,24 +2002,20 @@ pub(crate) async fn mint(ssi: &str, satoshis: u64, to: Account, memo: Memo, acco
             DisplayAmount(exchange_rate),
         );
 
-        res.push(block_index_susd1.0.to_u64().expect("@mint: Nat does not fit into u64"));
-        res.push(block_index_susd2.0.to_u64().expect("@mint: Nat does not fit into u64"));
+        block_indices.loan = Some(to_block_index(block_index_susd1)?);
+        block_indices.balance = Some(to_block_index(block_index_susd2)?);
     }
 
-    Ok(res)
+    Ok((block_indices, collateralized_account))
 }
 
-pub async fn syron_update(ssi: &str, from: u64, to: Option<u64>, amt: u64) -> Result<u64, UpdateBalanceError> {
+/// Transfers `amt` SUSD between the subaccounts of the SSI with the given nonces, or burns
+/// it if `to` is None. Transfers between the subaccounts state the given fee, if any.
+pub async fn syron_update(ssi: &str, from: u64, to: Option<u64>, amt: u64, fee: Option<u64>) -> MinterResult<u64> {
     let from_subaccount = Some(compute_subaccount(from, ssi));
     
     let to_account: Account = match to {
-        Some(to) => {
-            let to_subaccount = compute_subaccount(to, ssi);
-            Account {
-                owner: ic_cdk::id(),
-                subaccount: Some(to_subaccount)
-            }
-        },
+        Some(to) => minter_account_with_nonce(to, ssi),
         None => Account { // means burning the stablecoin
             owner: ic_cdk::id(),
             subaccount: None
@@ -1004,11 +2026,23 @@ pub async fn syron_update(ssi: &str, from: u64, to: Option<u64>, amt: u64) -> Re
         runtime: CdkRuntime,
         ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
     };
+    let from_account = Account {
+        owner: ic_cdk::id(),
+        subaccount: from_subaccount,
+    };
+    let fee = transfer_fee(fee, &from_account, &to_account, ic_cdk::id());
+    // @dev burns pay no fee, so only transfers between the subaccounts must exceed it
+    let min_fee = if is_mint_or_burn(&from_account, &to_account, ic_cdk::id()) {
+        0
+    } else {
+        ledger_fee(susd_client.ledger_canister_id).await?
+    };
+    check_amount_covers_fee(amt, min_fee, "syron_update")?;
     let block_index_susd = susd_client
     .transfer(TransferArg {
         from_subaccount,
         to: to_account,
-        fee: None,
+        fee: fee.map(Nat::from),
         created_at_time: None,
         memo: None,
         amount: Nat::from(amt),
@@ -1032,23 +2066,63 @@ pub async fn syron_update(ssi: &str, from: u64, to: Option<u64>, amt: u64) -> Re
     Ok(res)
 }
 
-// @dev add pending runes balance
+/// Returns the amount of the pending runes deposit to finalize, which defaults to the full deposit.
+fn runes_deposit_amount(amount: Option<u64>, pending_deposit: u64) -> MinterResult<u64> {
+    match amount {
+        None => Ok(pending_deposit),
+        Some(amount) if amount == 0 || amount > pending_deposit => Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!(
+                "@update_ssi_balance: The deposit amount must be between 1 and the pending runes deposit ({})",
+                pending_deposit
+            )
+        }),
+        Some(amount) => Ok(amount),
+    }
+}
+
+/// Returns whether the pending runes deposit of the SSI is covered by the UTXOs recorded
+/// when it was credited.
+fn is_pending_runes_deposit_backed(s: &state::MinterState, ssi: &str, pending_deposit: u64, runes_utxos: &[Utxo]) -> bool {
+    let deposit_outpoints = s
+        .pending_runes_deposits
+        .get(ssi)
+        .map(|deposit| deposit.outpoints.as_slice())
+        .unwrap_or_default();
+    is_runes_deposit_backed(pending_deposit, deposit_outpoints, runes_utxos)
+}
+
+/// Returns whether the pending runes deposit is covered by its own UTXOs among the runes UTXOs,
+/// whose values are the runes amounts reported by the indexer.
+fn is_runes_deposit_backed(pending_deposit: u64, deposit_outpoints: &[OutPoint], runes_utxos: &[Utxo]) -> bool {
+    let runes_balance = runes_utxos
+        .iter()
+        .filter(|utxo| deposit_outpoints.contains(&utxo.outpoint))
+        .fold(0u64, |total, utxo| total.saturating_add(utxo.value));
+    pending_deposit <= runes_balance
+}
+
+// @dev add pending runes balance backed by the given runes UTXOs
 // explicitly revert if requested
-pub async fn syron_runes_deposit(ssi: &str, amt: u64, revert: bool) -> Result<u64, UpdateBalanceError> {
+pub async fn syron_runes_deposit(ssi: &str, amt: u64, revert: bool, outpoints: Vec<OutPoint>) -> MinterResult<u64> {
     if amt == 0 {
         return Err(UpdateBalanceError::CallError{
             method: "syron_runes_deposit".to_string(),
             reason: "Amount cannot be zero".to_string()
         });
     }
+    // @dev each runes UTXO backs at most one pending deposit
+    if !revert && (outpoints.is_empty() || read_state(|s| outpoints.iter().any(|outpoint| s.is_backing_runes_deposit(outpoint)))) {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: "@syron_runes_deposit: The deposit must be backed by runes UTXOs that back no other deposit".to_string()
+        });
+    }
     
     // @dev use nonce 5 for runes pending deposits
     let minter = ic_cdk::id(); 
-    let pending_subaccount = compute_subaccount(5, ssi);
-    let pending_account: Account = Account {
-        owner: minter,
-        subaccount: Some(pending_subaccount)
-    };
+    let pending_subaccount = compute_ssi_subaccount(SubaccountKind::PendingRunes, ssi);
+    let pending_account: Account = minter_account(SubaccountKind::PendingRunes, ssi);
 
     let susd_client = ICRC1Client {
         runtime: CdkRuntime,
@@ -1060,7 +2134,7 @@ pub async fn syron_runes_deposit(ssi: &str, amt: u64, revert: bool) -> Result<u6
                 owner: minter,
                 subaccount: None
             };
-            susd_client
+            let block_index = susd_client
             .transfer(TransferArg {
                 from_subaccount: Some(pending_subaccount),
                 to: minter_account,
@@ -1077,10 +2151,12 @@ pub async fn syron_runes_deposit(ssi: &str, amt: u64, revert: bool) -> Result<u6
                     "@syron_runes_deposit: Could not revert syron runes deposit balance: {}",
                     msg)
                 }
-            })?? 
+            })??;
+            mutate_state(|s| state::audit::settle_pending_runes_deposit(s, ssi.to_string(), amt));
+            block_index
         }
         false => {
-            susd_client
+            let block_index = susd_client
             .transfer(TransferArg {
                 from_subaccount: None,
                 to: pending_account,
@@ -1097,7 +2173,11 @@ pub async fn syron_runes_deposit(ssi: &str, amt: u64, revert: bool) -> Result<u6
                     "@syron_runes_deposit: Could not update syron runes deposit balance: {}",
                     msg)
                 }
-            })??
+            })??;
+            mutate_state(|s| {
+                state::audit::add_pending_runes_deposit(s, ssi.to_string(), amt, ic_cdk::api::time(), outpoints)
+            });
+            block_index
         }
     };
     
@@ -1109,17 +2189,59 @@ pub async fn syron_runes_deposit(ssi: &str, amt: u64, revert: bool) -> Result<u6
     Ok(res)
 }
 
-pub async fn btc_bal_update(ssi: &str, from: u64, to: Option<u64>, amt: u64) -> Result<Vec<u64>, UpdateBalanceError> {
+/// Reverts the pending runes deposits that were not finalized within the configured TTL.
+pub async fn revert_stale_runes_deposits() {
+    let stale_deposits = read_state(|s| s.stale_runes_deposits(ic_cdk::api::time()));
+    for (ssi, amount) in stale_deposits {
+        match syron_runes_deposit(&ssi, amount, true, vec![]).await {
+            Ok(block_index) => log!(
+                P1,
+                "Reverted the stale runes deposit of {} for SSI {ssi} at block index {block_index}",
+                DisplayAmount(amount),
+            ),
+            Err(err) => log!(
+                P0,
+                "Failed to revert the stale runes deposit of {} for SSI {ssi}: {:?}",
+                DisplayAmount(amount),
+                err
+            ),
+        }
+    }
+}
+
+/// Transfers `amt` SBTC between the subaccounts of the SSI with the given nonces, or burns
+/// it if `to` is None. Transfers between the subaccounts state the given fee, if any.
+/// Reverts the BTC swap credit of a payment with `revert` if its SUSD leg failed, so that the
+/// sender is not left with a dangling swap balance, and returns the result of the SUSD leg.
+async fn revert_swap_credit_on_failure<T, F, Fut>(
+    result: MinterResult<T>,
+    swap_credit: Option<u64>,
+    ssi: &str,
+    revert: F,
+) -> MinterResult<T>
+where
+    F: FnOnce(u64) -> Fut,
+    Fut: std::future::Future<Output = MinterResult<Vec<u64>>>,
+{
+    if let (Err(_), Some(bitcoin_amount)) = (&result, swap_credit) {
+        if let Err(revert_err) = revert(bitcoin_amount).await {
+            log!(
+                P0,
+                "[syron_payment]: failed to revert the BTC swap credit of {} sats for {}: {:?}",
+                bitcoin_amount,
+                ssi,
+                revert_err
+            );
+        }
+    }
+    result
+}
+
+pub async fn btc_bal_update(ssi: &str, from: u64, to: Option<u64>, amt: u64, fee: Option<u64>) -> MinterResult<Vec<u64>> {
     let from_subaccount = Some(compute_subaccount(from, ssi));
     
     let to_account: Account = match to {
-        Some(to) => {
-            let to_subaccount = compute_subaccount(to, ssi);
-            Account {
-                owner: ic_cdk::id(),
-                subaccount: Some(to_subaccount)
-            }
-        },
+        Some(to) => minter_account_with_nonce(to, ssi),
         None => Account {
             owner: ic_cdk::id(),
             subaccount: None
@@ -1130,11 +2252,16 @@ pub async fn btc_bal_update(ssi: &str, from: u64, to: Option<u64>, amt: u64) ->
         runtime: CdkRuntime,
         ledger_canister_id: state::read_state(|s| s.ledger_id.get().into()),
     };
+    let from_account = Account {
+        owner: ic_cdk::id(),
+        subaccount: from_subaccount,
+    };
+    let fee = transfer_fee(fee, &from_account, &to_account, ic_cdk::id());
     let block_index_btc = sbtc_client
     .transfer(TransferArg {
         from_subaccount,
         to: to_account,
-        fee: None,
+        fee: fee.map(Nat::from),
         created_at_time: None,
         memo: None,
         amount: Nat::from(amt),
@@ -1159,45 +2286,643 @@ pub async fn btc_bal_update(ssi: &str, from: u64, to: Option<u64>, amt: u64) ->
     Ok(res.to_vec())
 }
 
-pub async fn get_collateralized_account(ssi: &str) -> Result<CollateralizedAccount, UpdateBalanceError> {
-    let xr = fetch_btc_exchange_rate("USD".to_string()).await??;
-    let btc_1 = balance_of(SyronLedger::BTC, ssi, 1).await.unwrap_or(0);
-    let susd_1 = balance_of(SyronLedger::SYRON, ssi, 1).await.unwrap_or(0);
-    let susd_2 = balance_of(SyronLedger::SYRON, ssi, 2).await.unwrap_or(0);
-    let susd_3 = balance_of(SyronLedger::SYRON, ssi, 3).await.unwrap_or(0);
-    
-    let exchange_rate: u64 = xr.rate / 1_000_000_000;
-    
-    // if dummy {
-    //     if btc_1 != 0 {
-    //         (1.15 * susd_1 as f64 / btc_1 as f64) as u64
-    //     } else {
-    //         xr.rate / 1_000_000_000 / 137 * 100
-    //     }
-    // } else {
-    //     xr.rate / 1_000_000_000
-    // };
-
-    let collateral_ratio = if btc_1 == 0 || susd_1 == 0 {
-        15000 // 150%
-    } else {
-        ((btc_1 as f64 * exchange_rate as f64 / susd_1 as f64) * 10000.0) as u64
-    };
-
-    Ok(CollateralizedAccount{
-        exchange_rate,
-        collateral_ratio,
-        btc_1,
-        susd_1,
-        susd_2,
-        susd_3
-    })
+/// The cycles attached to each BTC price outcall to a service provider.
+const PRICE_OUTCALL_CYCLES: u128 = 72_000_000;
+
+/// Fetches the BTC exchange rate from the exchange rate canister.
+async fn fetch_xrc_exchange_rate(quote_currency: String) -> MinterResult<u64> {
+    let (xr, source) = fetch_btc_exchange_rate(quote_currency).await?;
+    mutate_state(|s| s.last_xrc_source = Some(source));
+    let xr = xr?;
+    // @dev the rate is scaled by the number of decimals reported by the XRC
+    let scale = 10_u64.checked_pow(xr.metadata.decimals).ok_or_else(|| {
+        UpdateBalanceError::SystemError {
+            method: "fetch_xrc_exchange_rate".to_string(),
+            reason: format!("Unsupported number of rate decimals: {}", xr.metadata.decimals),
+        }
+    })?;
+    Ok(xr.rate / scale)
 }
 
-pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt: u64, btc: Option<u64>) -> Result<Vec<u64>, UpdateBalanceError> {
-    // @dev Syron amount cannot be lower than 20 cents @governance
-    if amt < 20_000_000 {
-        return Err(UpdateBalanceError::GenericError{
+/// Returns the median of the rates that lie within `max_deviation_bps` of the median of all rates,
+/// or None if fewer than `quorum` rates remain.
+fn aggregate_exchange_rates(mut rates: Vec<u64>, max_deviation_bps: u64, quorum: usize) -> Option<u64> {
+    // @dev the two middle rates are averaged for an even number of rates, so that neither biases the median
+    fn median(sorted: &[u64]) -> u64 {
+        let middle = sorted.len() / 2;
+        if sorted.len() % 2 == 0 {
+            ((sorted[middle - 1] as u128 + sorted[middle] as u128) / 2) as u64
+        } else {
+            sorted[middle]
+        }
+    }
+
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_unstable();
+    let reference = median(&rates);
+    rates.retain(|&rate| {
+        rate.abs_diff(reference) as u128 * 10_000 <= reference as u128 * max_deviation_bps as u128
+    });
+    if rates.len() < quorum {
+        return None;
+    }
+    Some(median(&rates))
+}
+
+/// Fetches the BTC exchange rate in the configured quote currency and caches it in the state.
+///
+/// The exchange rate canister and the configured rate providers are queried concurrently, and the
+/// median of the rates is returned once the outliers are discarded, provided a majority of the
+/// sources agree.
+pub(crate) async fn fetch_exchange_rate() -> MinterResult<u64> {
+    let (quote_currency, rate_providers, max_deviation_bps) = read_state(|s| {
+        (s.quote_currency.clone(), s.rate_providers.clone(), s.rate_max_deviation_bps)
+    });
+
+    let (xrc_rate, provider_rates) = futures::join!(
+        fetch_xrc_exchange_rate(quote_currency.clone()),
+        futures::future::join_all(rate_providers.iter().map(|&provider| {
+            // @dev each rate provider is an independent source, so a failed call is not rotated to another provider
+            crate::https::outcall::call_provider_btc_price(
+                &quote_currency,
+                PRICE_OUTCALL_CYCLES,
+                crate::https::types::ServiceProvider::Provider(provider),
+                crate::https::outcall::PROVIDER_RETRY_POLICY,
+            )
+        })),
+    );
+
+    let exchange_rate = if rate_providers.is_empty() {
+        xrc_rate?
+    } else {
+        let sources = rate_providers.len() + 1;
+        let rates: Vec<u64> = std::iter::once(xrc_rate)
+            .chain(provider_rates)
+            .filter_map(|rate| match rate {
+                Ok(rate) => Some(rate),
+                Err(e) => {
+                    log!(P1, "[fetch_exchange_rate]: failed to fetch a BTC price source: {:?}", e);
+                    None
+                }
+            })
+            .collect();
+        aggregate_exchange_rates(rates, max_deviation_bps, sources / 2 + 1).ok_or_else(|| {
+            UpdateBalanceError::TemporarilyUnavailable(format!(
+                "fewer than {} of the {} BTC price sources agree on the exchange rate",
+                sources / 2 + 1,
+                sources
+            ))
+        })?
+    };
+
+    mutate_state(|s| s.last_exchange_rate = Some((exchange_rate, ic_cdk::api::time())));
+    Ok(exchange_rate)
+}
+
+/// Returns whether the exchange rate is within `max_move_bps` of the last accepted rate, or of the
+/// last rejected rate (which confirms the move), while the accepted rate is within the window.
+fn is_rate_move_accepted(
+    accepted: Option<(u64, u64)>,
+    unconfirmed: Option<u64>,
+    rate: u64,
+    now: u64,
+    max_move_bps: u64,
+    window: u64,
+) -> bool {
+    let is_close_to = |reference: u64| {
+        rate.abs_diff(reference) as u128 * 10_000 <= reference as u128 * max_move_bps as u128
+    };
+    match accepted {
+        None => true,
+        Some((_, accepted_at)) if now.saturating_sub(accepted_at) > window => true,
+        Some((reference, _)) => is_close_to(reference) || unconfirmed.is_some_and(is_close_to),
+    }
+}
+
+/// Checks the exchange rate against the circuit breaker, halting minting on large rate moves
+/// until the rate is re-confirmed or the controllers override it.
+fn accept_exchange_rate(rate: u64, now: u64) -> MinterResult<()> {
+    mutate_state(|s| {
+        if is_rate_move_accepted(
+            s.accepted_exchange_rate,
+            s.unconfirmed_exchange_rate,
+            rate,
+            now,
+            s.circuit_breaker_bps,
+            s.circuit_breaker_window,
+        ) {
+            state::audit::accept_exchange_rate(s, rate, now);
+            Ok(())
+        } else {
+            log!(
+                P0,
+                "[accept_exchange_rate]: circuit breaker tripped by the exchange rate {} (last accepted: {:?}, XRC source: {:?})",
+                rate,
+                s.accepted_exchange_rate,
+                s.last_xrc_source
+            );
+            state::audit::reject_exchange_rate(s, rate);
+            Err(UpdateBalanceError::TemporarilyUnavailable(format!(
+                "the exchange rate moved beyond the circuit breaker limit to {}, minting is halted until the rate is confirmed",
+                rate
+            )))
+        }
+    })
+}
+
+/// Accepts the last exchange rate rejected by the circuit breaker, resuming minting,
+/// and returns the accepted rate.
+pub fn override_circuit_breaker() -> Option<u64> {
+    mutate_state(|s| {
+        let rate = s.unconfirmed_exchange_rate;
+        if let Some(rate) = rate {
+            state::audit::accept_exchange_rate(s, rate, ic_cdk::api::time());
+        }
+        rate
+    })
+}
+
+/// Returns the collateral ratio in basis points.
+fn collateral_ratio(btc: u64, susd: u64, exchange_rate: u64) -> u64 {
+    ((btc as f64 * exchange_rate as f64 / susd as f64) * 10000.0) as u64
+}
+
+/// Maps a ledger rejection of a redemption transfer to an error the user can act on.
+///
+/// Other rejections keep the generic conversion of [TransferError].
+fn redemption_transfer_error(err: TransferError, context: &str) -> UpdateBalanceError {
+    match err {
+        TransferError::InsufficientFunds { balance } => UpdateBalanceError::GenericError {
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!("{context}: The balance ({}) is insufficient", balance),
+        },
+        TransferError::BadFee { expected_fee } => UpdateBalanceError::GenericError {
+            error_code: ErrorCode::BadFee as u64,
+            error_message: format!("{context}: The ledger expects a fee of {}", expected_fee),
+        },
+        err => UpdateBalanceError::from(err),
+    }
+}
+
+/// Parses the Bitcoin address that receives a redemption, rejecting the addresses of other
+/// networks, the blocked ones and the minter's own vault.
+fn redemption_destination(address: &str) -> MinterResult<BitcoinAddress> {
+    let (btc_network, vault) = read_state(|s| (s.btc_network, s.dao_addr.get(2).cloned()));
+    let parsed_address = BitcoinAddress::parse(address, btc_network).map_err(|err| {
+        UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: format!("@update_ssi_balance: Invalid redemption address ({}): {}", address, err)
+        }
+    })?;
+    if crate::blocklist::BTC_ADDRESS_BLOCKLIST
+        .binary_search(&address.trim())
+        .is_ok()
+    {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: format!("@update_ssi_balance: Cannot redeem to a blocked address ({})", address)
+        });
+    }
+    if vault.as_ref() == Some(&parsed_address) {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: "@update_ssi_balance: Cannot redeem to the minter's vault".to_string()
+        });
+    }
+    if read_state(|s| s.count_incomplete_retrieve_btc_requests() >= MAX_CONCURRENT_PENDING_REQUESTS) {
+        return Err(UpdateBalanceError::TemporarilyUnavailable(
+            "too many pending retrieve requests".to_string(),
+        ));
+    }
+    Ok(parsed_address)
+}
+
+/// Rejects redemptions outside of the configured bounds.
+fn check_redemption_amount(amount: u64) -> MinterResult<()> {
+    let (min_redeem_btc, max_redeem_btc) = read_state(|s| (s.min_redeem_btc, s.max_redeem_btc));
+    redemption_bounds(amount, min_redeem_btc, max_redeem_btc)
+}
+
+/// Returns an error if `amount` is below `min_redeem_btc` or above `max_redeem_btc`.
+fn redemption_bounds(amount: u64, min_redeem_btc: u64, max_redeem_btc: u64) -> MinterResult<()> {
+    if amount < min_redeem_btc {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!(
+                "@update_ssi_balance: The amount to redeem ({}) is below the minimum ({})",
+                amount, min_redeem_btc
+            )
+        });
+    }
+    if amount > max_redeem_btc {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::RedemptionLimitExceeded as u64,
+            error_message: format!(
+                "@update_ssi_balance: The amount to redeem ({}) is above the maximum ({})",
+                amount, max_redeem_btc
+            )
+        });
+    }
+    Ok(())
+}
+
+/// Returns the redeemed SBTC net of the redemption fee, and the fee.
+fn redemption_fee(gross_amount: u64, redemption_fee_bps: u64) -> (u64, u64) {
+    let fee = (gross_amount as u128 * redemption_fee_bps.min(10_000) as u128 / 10_000) as u64;
+    (gross_amount - fee, fee)
+}
+
+/// Returns the ledger account that collects the protocol fees: the balance subaccount
+/// (nonce 2) of the treasury SSI, or the minter's default account if there is no treasury.
+fn treasury_account() -> Account {
+    match read_state(|s| s.dao_addr.get(1).map(|addr| addr.display(s.btc_network))) {
+        Some(treasury) => minter_account(SubaccountKind::Balance, &treasury),
+        None => Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        },
+    }
+}
+
+/// Returns the SBTC collateral to redeem and the proportional SUSD loan to repay,
+/// rejecting redemptions that would leave the loan below the minimum collateral ratio.
+fn partial_redemption(account: &CollateralizedAccount, amount: u64, min_collateral_ratio: u64) -> MinterResult<(u64, u64)> {
+    if amount == 0 || amount > account.btc_1 {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: format!(
+                "@partial_redemption: Invalid amount to redeem ({}), the collateral balance is {}",
+                amount, account.btc_1
+            )
+        });
+    }
+
+    // @dev round the repayment up so that the remaining loan is never less collateralized
+    let susd_repayment = ((account.susd_1 as u128 * amount as u128 + account.btc_1 as u128 - 1) / account.btc_1 as u128) as u64;
+
+    let remaining_btc = account.btc_1 - amount;
+    let remaining_susd = account.susd_1 - susd_repayment;
+    if remaining_susd != 0 {
+        let ratio = collateral_ratio(remaining_btc, remaining_susd + account.stability_fee, account.exchange_rate);
+        if ratio < min_collateral_ratio {
+            return Err(UpdateBalanceError::GenericError {
+                error_code: ErrorCode::InsufficientAmount as u64,
+                error_message: format!(
+                    "@partial_redemption: The collateral ratio after redemption ({}) would be below the minimum ({})",
+                    ratio, min_collateral_ratio
+                )
+            });
+        }
+    }
+    Ok((amount, susd_repayment))
+}
+
+/// Returns the SBTC collateral worth `repaid_susd` plus the bonus at the given exchange rate,
+/// capped at the available collateral, and the part of it that is the bonus.
+fn seized_collateral(repaid_susd: u64, exchange_rate: u64, available: u64, bonus_bps: u64) -> (u64, u64) {
+    if exchange_rate == 0 {
+        return (0, 0);
+    }
+    let base = repaid_susd as u128 / exchange_rate as u128;
+    let with_bonus = repaid_susd as u128 * (10_000 + bonus_bps as u128) / (10_000 * exchange_rate as u128);
+    let seized = with_bonus.min(available as u128) as u64;
+    let bonus = seized - base.min(seized as u128) as u64;
+    (seized, bonus)
+}
+
+/// Returns true if the loan of the given account is below the liquidation threshold.
+fn is_underwater(account: &CollateralizedAccount, liquidation_threshold: u64) -> bool {
+    account.susd_1 != 0 && account.collateral_ratio < liquidation_threshold
+}
+
+/// Records the first time the loan of the SSI was observed below the liquidation threshold,
+/// or clears it once the loan recovers, and returns that time.
+fn track_underwater(s: &mut state::MinterState, ssi: &str, underwater: bool, now: u64) -> Option<u64> {
+    match (underwater, s.underwater_since.get(ssi).copied()) {
+        (true, Some(since)) => Some(since),
+        (true, None) => {
+            state::audit::set_underwater_since(s, ssi.to_string(), Some(now));
+            Some(now)
+        }
+        (false, Some(_)) => {
+            state::audit::set_underwater_since(s, ssi.to_string(), None);
+            None
+        }
+        (false, None) => None,
+    }
+}
+
+/// Makes the previewed loan liquidatable only once it stayed below the liquidation threshold
+/// for the grace period.
+fn with_grace_period(
+    preview: LiquidationPreview,
+    underwater_since: Option<u64>,
+    now: u64,
+    grace_period: u64,
+) -> LiquidationPreview {
+    let grace_period_elapsed =
+        underwater_since.is_some_and(|since| now.saturating_sub(since) >= grace_period);
+    LiquidationPreview {
+        liquidatable: preview.liquidatable && grace_period_elapsed,
+        underwater_since,
+        ..preview
+    }
+}
+
+/// Returns the outcome of repaying `repay_amount` of the loan of the given account in a liquidation.
+fn liquidation_preview(
+    account: &CollateralizedAccount,
+    repay_amount: u64,
+    liquidation_threshold: u64,
+    bonus_bps: u64,
+) -> LiquidationPreview {
+    let repaid_susd = repay_amount.min(account.debt());
+    let (seized_collateral, bonus) =
+        seized_collateral(repaid_susd, account.exchange_rate, account.btc_1, bonus_bps);
+    LiquidationPreview {
+        liquidatable: is_underwater(account, liquidation_threshold),
+        underwater_since: None,
+        collateral_ratio: account.collateral_ratio,
+        repaid_susd,
+        seized_collateral,
+        bonus,
+    }
+}
+
+/// Returns the expected outcome of liquidating the loan of the given SSI by repaying
+/// `repay_amount` of SUSD. It never mutates the state nor transfers tokens.
+pub async fn simulate_liquidation(ssi: String, repay_amount: u64) -> MinterResult<LiquidationPreview> {
+    let collateralized_account = read_collateralized_account(&ssi).await?;
+    Ok(preview_liquidation(&ssi, &collateralized_account, repay_amount))
+}
+
+/// Returns the outcome of a liquidation with the configured parameters.
+fn preview_liquidation(ssi: &str, account: &CollateralizedAccount, repay_amount: u64) -> LiquidationPreview {
+    let now = ic_cdk::api::time();
+    read_state(|s| {
+        // @dev the grace period of a loan that is not tracked yet starts now
+        let underwater_since = is_underwater(account, s.liquidation_threshold_bps)
+            .then(|| s.underwater_since.get(ssi).copied().unwrap_or(now));
+        with_grace_period(
+            liquidation_preview(account, repay_amount, s.liquidation_threshold_bps, s.liquidation_bonus_bps),
+            underwater_since,
+            now,
+            s.liquidation_grace_period,
+        )
+    })
+}
+
+/// Liquidates (part of) the loan of the given SSI if it is below the liquidation threshold.
+///
+/// The liquidator repays the SUSD from an account that approved the minter (ICRC-2) and
+/// receives SBTC collateral worth the repaid SUSD plus the liquidation bonus.
+async fn liquidate(ssi: &str, repay_amount: u64, liquidator: Account) -> MinterResult<UtxoStatus> {
+    let minter = ic_cdk::id();
+    let ssi_box_subaccount = compute_ssi_subaccount(SubaccountKind::Box, ssi);
+
+    let collateralized_account = get_collateralized_account(ssi).await?;
+    let preview = preview_liquidation(ssi, &collateralized_account, repay_amount);
+    if !preview.liquidatable || preview.repaid_susd == 0 {
+        return Err(UpdateBalanceError::GenericError {
+            error_code: ErrorCode::UnsupportedOperation as u64,
+            error_message: format!(
+                "@liquidate: The loan of {} cannot be liquidated (collateral ratio = {}, below the threshold since {:?})",
+                ssi, preview.collateral_ratio, preview.underwater_since
+            )
+        });
+    }
+
+    let susd_client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
+    };
+
+    // @dev the transfer to the minter's default account burns the liquidator's SUSD
+    susd_client
+        .transfer_from(TransferFromArgs {
+            spender_subaccount: None,
+            from: liquidator,
+            to: Account {
+                owner: minter,
+                subaccount: None
+            },
+            amount: Nat::from(preview.repaid_susd),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        })
+        .await
+        .map_err(|(code, msg)| {
+            UpdateBalanceError::TemporarilyUnavailable(format!(
+                "@liquidate: Cannot burn the liquidator's SUSD: {} (reject_code = {})",
+                msg, code
+            ))
+        })?
+        .map_err(|err| UpdateBalanceError::GenericError {
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!("@liquidate: The SUSD ledger rejected the repayment: {:?}", err)
+        })?;
+
+    // @dev the repayment settles the accrued stability fee first
+    let (stability_fee_paid, repaid_loan) = split_repayment(preview.repaid_susd, collateralized_account.stability_fee);
+    let burned = if repaid_loan != 0 {
+        burn_from_box(SyronLedger::SYRON, ssi_box_subaccount, repaid_loan, "@liquidate: Cannot burn the loan").await.map(|_| ())
+    } else {
+        Ok(())
+    };
+    if let Err(err) = burned {
+        // @dev refund the liquidator, since the loan is unchanged
+        refund_liquidator(&susd_client, ssi, liquidator, preview.repaid_susd).await;
+        return Err(err);
+    }
+    mutate_state(|s| {
+        if stability_fee_paid != 0 {
+            state::audit::settle_stability_fee(s, ssi.to_string(), stability_fee_paid);
+        }
+        if repaid_loan != 0 {
+            state::audit::repaid(s, ssi.to_string(), 0, repaid_loan);
+        }
+    });
+    // @dev the remaining loan keeps accruing from now on
+    accrue_stability_fee(ssi, collateralized_account.susd_1 - repaid_loan, ic_cdk::api::time());
+
+    let sbtc_client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: state::read_state(|s| s.ledger_id.get().into()),
+    };
+    let block_index = sbtc_client
+        .transfer(TransferArg {
+            from_subaccount: Some(ssi_box_subaccount),
+            to: liquidator,
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: Nat::from(preview.seized_collateral),
+        })
+        .await
+        .map_err(|(code, msg)| {
+            UpdateBalanceError::TemporarilyUnavailable(format!(
+                "@liquidate: Cannot transfer the seized collateral: {} (reject_code = {})",
+                msg, code
+            ))
+        })
+        .and_then(|result| result.map_err(UpdateBalanceError::from))
+        .and_then(|block_index| {
+            block_index.0.to_u64().ok_or_else(|| UpdateBalanceError::SystemError{
+                method: "liquidate".to_string(),
+                reason: "Block index too large for u64".to_string()
+            })
+        });
+    let block_index = match block_index {
+        Ok(block_index) => block_index,
+        Err(err) => {
+            log!(
+                P0,
+                "[liquidate]: repaid {} (SUSD) of the loan of {ssi} but failed to transfer {} (SBTC) to {liquidator}: {:?}",
+                DisplayAmount(preview.repaid_susd),
+                DisplayAmount(preview.seized_collateral),
+                err
+            );
+            // @dev restore the loan and the stability fee, then refund the liquidator
+            if repaid_loan != 0 {
+                let restored = susd_client
+                    .transfer(TransferArg {
+                        from_subaccount: None,
+                        to: Account {
+                            owner: minter,
+                            subaccount: Some(ssi_box_subaccount),
+                        },
+                        fee: None,
+                        created_at_time: None,
+                        memo: None,
+                        amount: Nat::from(repaid_loan),
+                    })
+                    .await;
+                match restored {
+                    Ok(Ok(_)) => mutate_state(|s| state::audit::borrowed(s, ssi.to_string(), 0, repaid_loan)),
+                    _ => log!(
+                        P0,
+                        "[liquidate]: failed to restore {} (SUSD) of the loan of {ssi}: {:?}",
+                        DisplayAmount(repaid_loan),
+                        restored
+                    ),
+                }
+            }
+            if stability_fee_paid != 0 {
+                mutate_state(|s| {
+                    state::audit::accrue_stability_fee(s, ssi.to_string(), stability_fee_paid, ic_cdk::api::time())
+                });
+            }
+            refund_liquidator(&susd_client, ssi, liquidator, preview.repaid_susd).await;
+            return Err(err);
+        }
+    };
+    mutate_state(|s| state::audit::repaid(s, ssi.to_string(), preview.seized_collateral, 0));
+    record_protocol_event(ProtocolEventKind::Liquidation, ssi, preview.repaid_susd, block_index);
+
+    log!(
+        P1,
+        "Liquidated {} (SUSD) of the loan of {ssi}, seizing {} (SBTC) for {liquidator}",
+        DisplayAmount(preview.repaid_susd),
+        DisplayAmount(preview.seized_collateral),
+    );
+
+    Ok(UtxoStatus::Liquidated {
+        block_index,
+        repaid_susd: preview.repaid_susd,
+        seized_collateral: preview.seized_collateral,
+        bonus: preview.bonus,
+    })
+}
+
+/// Mints the SUSD that a failed liquidation burned back to the liquidator.
+async fn refund_liquidator(susd_client: &ICRC1Client<CdkRuntime>, ssi: &str, liquidator: Account, amount: u64) {
+    let refund = susd_client
+        .transfer(TransferArg {
+            from_subaccount: None,
+            to: liquidator,
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: Nat::from(amount),
+        })
+        .await;
+    if !matches!(refund, Ok(Ok(_))) {
+        log!(
+            P0,
+            "[liquidate]: failed to refund {} (SUSD) to {liquidator} after a failed liquidation of {ssi}: {:?}",
+            DisplayAmount(amount),
+            refund
+        );
+    }
+}
+
+/// Fetches the collateral and loan balances of the given SSI and computes its stability fee
+/// and collateral ratio, without recording the accrual nor the liquidation grace period.
+async fn read_collateralized_account(ssi: &str) -> MinterResult<CollateralizedAccount> {
+    // @dev the inter-canister calls are independent, so we issue them concurrently
+    let (exchange_rate, btc_1, susd_1, susd_2, susd_3) = futures::join!(
+        fetch_exchange_rate(),
+        balance_of(SyronLedger::BTC, ssi, SubaccountKind::Box.nonce()),
+        balance_of(SyronLedger::SYRON, ssi, SubaccountKind::Box.nonce()),
+        balance_of(SyronLedger::SYRON, ssi, SubaccountKind::Balance.nonce()),
+        balance_of(SyronLedger::SYRON, ssi, SubaccountKind::Withdrawal.nonce()),
+    );
+    let exchange_rate = exchange_rate?;
+    let btc_1 = btc_1.unwrap_or(0);
+    let susd_1 = susd_1.unwrap_or(0);
+    let susd_2 = susd_2.unwrap_or(0);
+    let susd_3 = susd_3.unwrap_or(0);
+    let stability_fee = read_state(|s| accrued_stability_fee(s, ssi, susd_1, ic_cdk::api::time()));
+    
+    // if dummy {
+    //     if btc_1 != 0 {
+    //         (1.15 * susd_1 as f64 / btc_1 as f64) as u64
+    //     } else {
+    //         xr.rate / 1_000_000_000 / 137 * 100
+    //     }
+    // } else {
+    //     xr.rate / 1_000_000_000
+    // };
+
+    let collateral_ratio = if btc_1 == 0 || susd_1 == 0 {
+        15000 // 150%
+    } else {
+        // @dev the accrued stability fee is part of the debt
+        collateral_ratio(btc_1, susd_1.saturating_add(stability_fee), exchange_rate)
+    };
+
+    Ok(CollateralizedAccount{
+        exchange_rate,
+        collateral_ratio,
+        btc_1,
+        susd_1,
+        susd_2,
+        susd_3,
+        stability_fee
+    })
+}
+
+pub async fn get_collateralized_account(ssi: &str) -> MinterResult<CollateralizedAccount> {
+    let collateralized_account = read_collateralized_account(ssi).await?;
+    let now = ic_cdk::api::time();
+    accrue_stability_fee(ssi, collateralized_account.susd_1, now);
+
+    // @dev start (or clear) the liquidation grace period of the loan
+    let underwater = is_underwater(&collateralized_account, read_state(|s| s.liquidation_threshold_bps));
+    mutate_state(|s| track_underwater(s, ssi, underwater, now));
+
+    Ok(collateralized_account)
+}
+
+
+pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt: u64, btc: Option<u64>) -> MinterResult<Vec<u64>> {
+    // @dev Syron amount cannot be lower than the minimum payment (20 cents by default) @governance
+    let min_payment_susd = read_state(|s| s.min_payment_susd);
+    if amt < min_payment_susd {
+        return Err(UpdateBalanceError::GenericError{
             error_code: ErrorCode::InsufficientAmount as u64,
             error_message: format!("@syron_payment: Syron amount ({}) is below the minimum", amt),
         });
@@ -1211,19 +2936,20 @@ pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt
     ic_cdk::println!("@syron_payment: SIWB Internet Identity = {:?}", principal);
     
     let mut res = vec![];
+    let mut swap_credit: Option<u64> = None;
 
     match btc {
         Some(btc) => {
-            // @dev BTC amount cannot be lower than 200 sats @governance
-            if btc < 200 {
+            // @dev BTC amount cannot be lower than the minimum payment (200 sats by default) @governance
+            let min_payment_btc = read_state(|s| s.min_payment_btc);
+            if btc < min_payment_btc {
                 return Err(UpdateBalanceError::GenericError{
                     error_code: ErrorCode::InsufficientAmount as u64,
                     error_message: format!("@syron_payment: BTC amount ({}) is below the minimum", btc),
                 });
             }
 
-            let xr = fetch_btc_exchange_rate("USD".to_string()).await??;
-            let exchange_rate: u64 = xr.rate / 1_000_000_000;
+            let exchange_rate = fetch_exchange_rate().await?;
             let bitcoin_amount = (amt as f64 / exchange_rate as f64) as u64;
             
             // "bitcoin_amount" must be at least the minimum BTC amount requested by the user ("btc")
@@ -1238,11 +2964,7 @@ pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt
             }
             
             // @dev Use subaccount 0 in SBTC ledger for swap credit
-            let swap_subaccount = compute_subaccount(0, ssi);
-            let swap_account = Account {
-                owner: ic_cdk::id(),
-                subaccount: Some(swap_subaccount)
-            };
+            let swap_account = minter_account(SubaccountKind::Swap, ssi);
 
             // Syron BTC Ledger
             let sbtc_client = ICRC1Client {
@@ -1275,24 +2997,20 @@ pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt
                     reason: "Block index too large for u64".to_string()
                 })?
             );
+            swap_credit = Some(bitcoin_amount);
             ic_cdk::println!("@syron_payment: The user has been credited {:?} satoshis", bitcoin_amount);
         },
         None => {}  
     } 
     
-    let from_subaccount = Some(compute_subaccount(2, ssi));
-    let to_subaccount = compute_subaccount(2, recipient);
-    
-    let to_account = Account {
-        owner: ic_cdk::id(),
-        subaccount: Some(to_subaccount)
-    };
+    let from_subaccount = Some(compute_ssi_subaccount(SubaccountKind::Balance, ssi));
+    let to_account = minter_account(SubaccountKind::Balance, recipient);
 
     let susd_client = ICRC1Client {
         runtime: CdkRuntime,
         ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
     };
-    let block_index_susd = susd_client
+    let susd_result = susd_client
     .transfer(TransferArg {
         from_subaccount,
         to: to_account,
@@ -1309,7 +3027,15 @@ pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt
             "@syron_payment: Could not update the Syron transfer balance: {}",
             msg)
         }
-    })??;
+    })
+    .and_then(|result| result.map_err(UpdateBalanceError::from));
+
+    let block_index_susd = revert_swap_credit_on_failure(susd_result, swap_credit, ssi, |bitcoin_amount| async move {
+        // @dev an unknown fee must not prevent the revert, so it falls back to the ledger default
+        let fee = pinned_fee(read_state(|s| s.ledger_id.get().into())).await.unwrap_or(None);
+        btc_bal_update(ssi, SubaccountKind::Swap.nonce(), None, bitcoin_amount, fee).await
+    })
+    .await?;
     
     res.push(
         block_index_susd.0.to_u64()
@@ -1323,9 +3049,70 @@ pub async fn syron_payment(sender: BitcoinAddress, receiver: BitcoinAddress, amt
     Ok(res)
 }
 
-pub async fn syron_payment_icp(sender: BitcoinAddress, receiver: Account, amt: u64) -> Result<Vec<u64>, UpdateBalanceError> {
-    // @dev Syron amount cannot be lower than 20 cents @governance
-    if amt < 20_000_000 {
+// @dev ICRC-2 payment: the minter pulls SUSD from an account that approved the spender's nonce-2 subaccount of the minter,
+// so that allowances are scoped per SSI
+pub async fn syron_payment_from(spender: BitcoinAddress, from: Account, to: Account, amt: u64) -> MinterResult<u64> {
+    // @dev Syron amount cannot be lower than the minimum payment (20 cents by default) @governance
+    let min_payment_susd = read_state(|s| s.min_payment_susd);
+    if amt < min_payment_susd {
+        return Err(UpdateBalanceError::GenericError{
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!("@syron_payment_from: Syron amount ({}) is below the minimum", amt),
+        });
+    }
+
+    let network = read_state(|s| (s.btc_network));
+    let ssi = &spender.display(network);
+
+    let principal = get_siwb_principal(ssi).await?;
+    ic_cdk::println!("@syron_payment_from: SIWB Internet Identity = {:?}", principal);
+
+    let syron_client = ICRC1Client {
+        runtime: CdkRuntime,
+        ledger_canister_id: state::read_state(|s| s.susd_id.get().into()),
+    };
+    let block_index_susd = syron_client
+    .transfer_from(TransferFromArgs {
+        spender_subaccount: Some(compute_ssi_subaccount(SubaccountKind::Balance, ssi)),
+        from,
+        to,
+        amount: Nat::from(amt),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    })
+    .await
+    .map_err(|(code, msg)| {
+        UpdateBalanceError::GenericError{
+            error_code: code as u64,
+            error_message: format!(
+            "@syron_payment_from: Could not update the Syron transfer balance: {}",
+            msg)
+        }
+    })?
+    .map_err(|err| {
+        UpdateBalanceError::GenericError{
+            error_code: ErrorCode::InsufficientAmount as u64,
+            error_message: format!(
+            "@syron_payment_from: The Syron ledger rejected the transfer: {:?}",
+            err)
+        }
+    })?;
+
+    let res = block_index_susd.0.to_u64()
+        .ok_or_else(|| UpdateBalanceError::SystemError{
+            method: "syron_payment_from".to_string(),
+            reason: "Block index too large for u64".to_string()
+        })?;
+    ic_cdk::println!("@syron_payment_from: {:?} syron-sats were sent from account {:?} to account {:?}", amt, from, to);
+
+    Ok(res)
+}
+
+pub async fn syron_payment_icp(sender: BitcoinAddress, receiver: Account, amt: u64) -> MinterResult<Vec<u64>> {
+    // @dev Syron amount cannot be lower than the minimum payment (20 cents by default) @governance
+    let min_payment_susd = read_state(|s| s.min_payment_susd);
+    if amt < min_payment_susd {
         return Err(UpdateBalanceError::GenericError{
             error_code: ErrorCode::InsufficientAmount as u64,
             error_message: format!("@syron_payment_icp: Syron amount ({}) is below the minimum", amt),
@@ -1338,7 +3125,7 @@ pub async fn syron_payment_icp(sender: BitcoinAddress, receiver: Account, amt: u
     let principal = get_siwb_principal(ssi).await?;
     ic_cdk::println!("@syron_payment_icp: SIWB Internet Identity = {:?}", principal);
     
-    let from_subaccount = Some(compute_subaccount(2, ssi));
+    let from_subaccount = Some(compute_ssi_subaccount(SubaccountKind::Balance, ssi));
 
     let syron_client = ICRC1Client {
         runtime: CdkRuntime,
@@ -1374,3 +3161,745 @@ pub async fn syron_payment_icp(sender: BitcoinAddress, receiver: Account, amt: u
     
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::init::DEFAULT_MIN_COLLATERAL_RATIO_BPS;
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint {
+            txid: [1; 32].into(),
+            vout,
+        }
+    }
+
+    #[test]
+    fn exchange_rate_should_be_the_median_without_outliers() {
+        assert_eq!(aggregate_exchange_rates(vec![60_000, 61_000, 200_000], 500, 2), Some(60_500));
+        assert_eq!(aggregate_exchange_rates(vec![60_000, 62_000, 59_000], 500, 2), Some(60_000));
+        assert_eq!(aggregate_exchange_rates(vec![60_000], 500, 1), Some(60_000));
+    }
+
+    #[test]
+    fn exchange_rate_should_average_the_two_middle_rates() {
+        assert_eq!(aggregate_exchange_rates(vec![61_000, 60_000], 500, 2), Some(60_500));
+        assert_eq!(aggregate_exchange_rates(vec![60_000, 60_001, 62_000, 59_000], 500, 2), Some(60_000));
+        assert_eq!(aggregate_exchange_rates(vec![u64::MAX, u64::MAX - 1], 500, 2), Some(u64::MAX - 1));
+    }
+
+    #[test]
+    fn exchange_rate_should_require_a_quorum() {
+        assert_eq!(aggregate_exchange_rates(vec![], 500, 1), None);
+        assert_eq!(aggregate_exchange_rates(vec![10_000, 60_000, 200_000], 500, 2), None);
+    }
+
+    #[test]
+    fn circuit_breaker_should_trip_on_large_moves_within_the_window() {
+        let accepted = Some((60_000, 1_000));
+        assert!(is_rate_move_accepted(None, None, 60_000, 1_000, 2_000, 100));
+        assert!(is_rate_move_accepted(accepted, None, 70_000, 1_050, 2_000, 100));
+        assert!(!is_rate_move_accepted(accepted, None, 80_000, 1_050, 2_000, 100));
+        assert!(is_rate_move_accepted(accepted, None, 80_000, 1_101, 2_000, 100));
+    }
+
+    #[test]
+    fn circuit_breaker_should_accept_a_confirmed_move() {
+        let accepted = Some((60_000, 1_000));
+        assert!(is_rate_move_accepted(accepted, Some(80_000), 81_000, 1_050, 2_000, 100));
+        assert!(!is_rate_move_accepted(accepted, Some(30_000), 81_000, 1_050, 2_000, 100));
+    }
+
+    #[test]
+    fn circuit_breaker_rates_should_survive_a_replay() {
+        use crate::state::eventlog::{replay, Event};
+
+        let replayed = |events: Vec<Event>| {
+            let state = replay(
+                std::iter::once(Event::Init(crate::lifecycle::init::test_init_args())).chain(events),
+            )
+            .unwrap();
+            (state.accepted_exchange_rate, state.unconfirmed_exchange_rate)
+        };
+        let accepted = Event::AcceptedExchangeRate { rate: 60_000, timestamp: 1_000 };
+        let rejected = Event::RejectedExchangeRate { rate: 80_000 };
+        assert_eq!(replayed(vec![accepted.clone(), rejected.clone()]), (Some((60_000, 1_000)), Some(80_000)));
+        // @dev a confirmed rate clears the pending one
+        assert_eq!(
+            replayed(vec![accepted, rejected, Event::AcceptedExchangeRate { rate: 81_000, timestamp: 1_050 }]),
+            (Some((81_000, 1_050)), None)
+        );
+    }
+
+    #[test]
+    fn balance_update_should_wait_for_the_interval() {
+        let (alice, bob) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let key = |caller, ssi: &str| (caller, ssi.to_string());
+        let mut last_updates = BTreeMap::new();
+        assert_eq!(balance_update_wait_time(&mut last_updates, key(alice, "ssi"), 1_000, 100), None);
+        assert_eq!(balance_update_wait_time(&mut last_updates, key(alice, "ssi"), 1_040, 100), Some(60));
+        assert_eq!(balance_update_wait_time(&mut last_updates, key(alice, "other"), 1_040, 100), None);
+        // @dev another caller cannot exhaust the limit of the SSI
+        assert_eq!(balance_update_wait_time(&mut last_updates, key(bob, "ssi"), 1_040, 100), None);
+        assert_eq!(balance_update_wait_time(&mut last_updates, key(alice, "ssi"), 1_100, 100), None);
+        assert_eq!(last_updates.len(), 3);
+    }
+
+    #[test]
+    fn mint_retry_should_repeat_the_first_attempt() {
+        let first = MintAttempt { created_at_time: 1_000, susd: 6_000_000, convert_memo: true };
+        let attempts = BTreeMap::from([(outpoint(0), first.clone())]);
+        assert_eq!(pending_mint_attempt(&attempts, &outpoint(0), 5_000), Some(first));
+        assert_eq!(pending_mint_attempt(&attempts, &outpoint(1), 5_000), None);
+    }
+
+    #[test]
+    fn mint_retry_after_dedup_window_should_be_a_new_attempt() {
+        let attempts = BTreeMap::from([(outpoint(0), MintAttempt { created_at_time: 1_000, susd: 0, convert_memo: true })]);
+        let now = 1_000 + MINT_DEDUP_WINDOW_NANOS;
+        assert_eq!(pending_mint_attempt(&attempts, &outpoint(0), now), None);
+    }
+
+    #[test]
+    fn ledger_duplicate_should_collapse_into_the_original_transfer() {
+        let original = dedup_transfer_result(Ok(Nat::from(42u64)));
+        let retry = dedup_transfer_result(Err(TransferError::Duplicate {
+            duplicate_of: Nat::from(42u64),
+        }));
+        assert_eq!(original, Ok((Nat::from(42u64), true)));
+        assert_eq!(retry, Ok((Nat::from(42u64), false)));
+        assert_eq!(
+            dedup_transfer_result(Err(TransferError::TemporarilyUnavailable)),
+            Err(TransferError::TemporarilyUnavailable)
+        );
+    }
+
+    fn collateralized_account(btc_1: u64, susd_1: u64, exchange_rate: u64) -> CollateralizedAccount {
+        CollateralizedAccount {
+            exchange_rate,
+            collateral_ratio: collateral_ratio(btc_1, susd_1, exchange_rate),
+            btc_1,
+            susd_1,
+            susd_2: 0,
+            susd_3: 0,
+            stability_fee: 0,
+        }
+    }
+
+    fn runes_utxo(vout: u32, runes: u64) -> Utxo {
+        Utxo {
+            outpoint: outpoint(vout),
+            value: runes,
+            height: 0,
+        }
+    }
+
+    #[test]
+    fn runes_deposit_should_be_backed_by_the_indexer_balance() {
+        let runes_utxos = vec![runes_utxo(0, 1_000), runes_utxo(1, 500)];
+        let deposit_outpoints = vec![outpoint(0), outpoint(1)];
+        assert!(is_runes_deposit_backed(1_500, &deposit_outpoints, &runes_utxos));
+        assert!(is_runes_deposit_backed(200, &deposit_outpoints, &runes_utxos));
+        assert!(!is_runes_deposit_backed(1_501, &deposit_outpoints, &runes_utxos));
+        assert!(!is_runes_deposit_backed(1, &deposit_outpoints, &[]));
+    }
+
+    #[test]
+    fn credited_runes_deposit_should_stay_backed() {
+        let mut state = state::MinterState::from(crate::lifecycle::init::test_init_args());
+        let runes_utxos = vec![runes_utxo(0, 1_000), runes_utxo(1, 500)];
+
+        state::audit::add_pending_runes_deposit(&mut state, "ssi".to_string(), 1_000, 0, vec![outpoint(0)]);
+
+        assert!(state.is_backing_runes_deposit(&outpoint(0)));
+        assert!(!state.is_backing_runes_deposit(&outpoint(1)));
+        assert!(is_pending_runes_deposit_backed(&state, "ssi", 1_000, &runes_utxos));
+        assert!(!is_pending_runes_deposit_backed(&state, "ssi", 1_001, &runes_utxos));
+        assert!(!is_pending_runes_deposit_backed(&state, "other", 1, &runes_utxos));
+    }
+
+    #[test]
+    fn runes_deposit_should_not_be_backed_by_other_utxos() {
+        let runes_utxos = vec![runes_utxo(0, 1_000), runes_utxo(1, 500)];
+        assert!(is_runes_deposit_backed(500, &[outpoint(1)], &runes_utxos));
+        assert!(!is_runes_deposit_backed(501, &[outpoint(1)], &runes_utxos));
+        assert!(!is_runes_deposit_backed(1, &[], &runes_utxos));
+    }
+
+    #[test]
+    fn runes_deposit_amount_should_default_to_the_pending_deposit() {
+        assert_eq!(runes_deposit_amount(None, 1_000), Ok(1_000));
+        assert_eq!(runes_deposit_amount(Some(400), 1_000), Ok(400));
+        assert_eq!(runes_deposit_amount(Some(1_000), 1_000), Ok(1_000));
+        assert!(runes_deposit_amount(Some(0), 1_000).is_err());
+        assert!(runes_deposit_amount(Some(1_001), 1_000).is_err());
+    }
+
+    #[test]
+    fn missing_outpoints_should_list_recorded_utxos_that_were_not_found() {
+        let recorded = vec![runes_utxo(0, 1_000), runes_utxo(1, 2_000)];
+        assert_eq!(missing_outpoints(&recorded, &[runes_utxo(1, 2_000)]), vec![outpoint(0)]);
+        assert_eq!(missing_outpoints(&recorded, &recorded), vec![]);
+    }
+
+    #[test]
+    fn repayment_amount_should_be_capped_at_the_outstanding_loan() {
+        assert_eq!(repayment_amount(500, 1_000), Ok(500));
+        assert_eq!(repayment_amount(5_000, 1_000), Ok(1_000));
+        assert!(repayment_amount(0, 1_000).is_err());
+        assert!(repayment_amount(500, 0).is_err());
+    }
+
+    #[test]
+    fn mintable_susd_should_keep_the_over_collateralization_ratio() {
+        let account = collateralized_account(0, 0, 60_000);
+        assert_eq!(compute_mintable_susd(150, &account, DEFAULT_MIN_COLLATERAL_RATIO_BPS), 6_000_000);
+        assert_eq!(borrowable_susd(&account, DEFAULT_MIN_COLLATERAL_RATIO_BPS), 0);
+    }
+
+    #[test]
+    fn mintable_susd_should_be_zero_if_the_deposit_does_not_restore_the_ratio() {
+        // 100% collateral ratio
+        let account = collateralized_account(100_000, 6_000_000_000, 60_000);
+        assert_eq!(compute_mintable_susd(10_000, &account), 0);
+        // the deposit restores the ratio, and only the headroom above it is mintable
+        assert_eq!(compute_mintable_susd(60_000, &account), 400_000_000);
+    }
+
+    #[test]
+    fn borrowable_susd_should_be_the_headroom_above_the_minimum_ratio() {
+        // 300% collateral ratio, half of the collateral backs the current loan
+        let account = collateralized_account(100_000, 2_000_000_000, 60_000);
+        assert_eq!(borrowable_susd(&account, DEFAULT_MIN_COLLATERAL_RATIO_BPS), 2_000_000_000);
+        assert_eq!(borrowable_susd(&collateralized_account(150, 0, 60_000), DEFAULT_MIN_COLLATERAL_RATIO_BPS), compute_mintable_susd(150, &collateralized_account(0, 0, 60_000), DEFAULT_MIN_COLLATERAL_RATIO_BPS));
+        // 100% collateral ratio
+        assert_eq!(borrowable_susd(&collateralized_account(100_000, 6_000_000_000, 60_000), DEFAULT_MIN_COLLATERAL_RATIO_BPS), 0);
+        assert_eq!(borrowable_susd(&collateralized_account(0, 0, 60_000), DEFAULT_MIN_COLLATERAL_RATIO_BPS), 0);
+    }
+
+    #[test]
+    fn added_collateral_should_improve_the_collateral_ratio() {
+        // 100% collateral ratio
+        let account = with_added_collateral(collateralized_account(100_000, 6_000_000_000, 60_000), 100_000);
+        assert_eq!(account.btc_1, 200_000);
+        assert_eq!(account.susd_1, 6_000_000_000);
+        assert_eq!(account.collateral_ratio, 20_000);
+
+        let account = with_added_collateral(collateralized_account(0, 0, 60_000), 100_000);
+        assert_eq!(account.btc_1, 100_000);
+        assert_eq!(account.collateral_ratio, collateralized_account(0, 0, 60_000).collateral_ratio);
+    }
+
+    #[test]
+    fn mint_should_reject_the_default_account() {
+        let owner = Principal::anonymous();
+        let account = |subaccount| Account { owner, subaccount };
+        assert!(check_not_default_account(&account(None), "mint").is_err());
+        assert!(check_not_default_account(&account(Some(*DEFAULT_SUBACCOUNT)), "mint").is_err());
+        assert_eq!(check_not_default_account(&account(Some([1; 32])), "mint"), Ok(()));
+    }
+
+    #[test]
+    fn transfer_amount_should_exceed_the_ledger_fee() {
+        assert_eq!(check_amount_covers_fee(11, 10, "mint"), Ok(()));
+        assert_eq!(check_amount_covers_fee(1, 0, "mint"), Ok(()));
+        assert!(check_amount_covers_fee(10, 10, "mint").is_err());
+        assert!(check_amount_covers_fee(0, 0, "mint").is_err());
+    }
+
+    #[test]
+    fn only_transfers_between_accounts_should_pin_the_fee() {
+        let minter = Principal::from_slice(&[1]);
+        let account = |subaccount| Account { owner: minter, subaccount };
+        let box_account = account(Some([1; 32]));
+        let balance_account = account(Some([2; 32]));
+
+        // @dev mints and burns
+        assert_eq!(transfer_fee(Some(10), &account(None), &box_account, minter), None);
+        assert_eq!(transfer_fee(Some(10), &account(Some(*DEFAULT_SUBACCOUNT)), &box_account, minter), None);
+        assert_eq!(transfer_fee(Some(10), &box_account, &account(None), minter), None);
+        // @dev transfers between accounts
+        assert_eq!(transfer_fee(Some(10), &box_account, &balance_account, minter), Some(10));
+        assert_eq!(transfer_fee(None, &box_account, &balance_account, minter), None);
+    }
+
+    #[test]
+    fn debt_ceiling_should_cap_the_total_and_reserved_debt() {
+        state::replace_state(state::MinterState::from(crate::lifecycle::init::test_init_args()));
+        mutate_state(|s| {
+            s.debt_ceiling_susd = 1_000;
+            s.total_debt = 400;
+        });
+
+        let first = reserve_debt(500).unwrap();
+        // @dev a concurrent mint sees the reservation of the first one
+        assert!(reserve_debt(101).is_err());
+        let second = reserve_debt(100).unwrap();
+        assert_eq!(read_state(|s| s.reserved_debt), 600);
+
+        drop(first);
+        assert_eq!(read_state(|s| s.reserved_debt), 100);
+        assert!(reserve_debt(500).is_ok());
+        drop(second);
+        assert_eq!(read_state(|s| s.reserved_debt), 0);
+
+        mutate_state(|s| s.total_debt = u64::MAX);
+        assert!(reserve_debt(1).is_err());
+    }
+
+    #[test]
+    fn is_confirmed_should_not_overflow() {
+        let utxo = |height| Utxo {
+            outpoint: outpoint(0),
+            value: 10_000,
+            height,
+        };
+        assert!(is_confirmed(&utxo(100), 100, 1));
+        assert!(!is_confirmed(&utxo(100), 100, 2));
+        assert!(is_confirmed(&utxo(100), 105, 6));
+        assert!(is_confirmed(&utxo(0), 0, 0));
+        assert!(!is_confirmed(&utxo(100), u32::MAX, u32::MAX));
+        assert!(is_confirmed(&utxo(1), u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn known_utxos_should_exclude_the_ignored_and_quarantined_ones() {
+        let mut state = state::MinterState::from(crate::lifecycle::init::test_init_args());
+        let account = Account { owner: Principal::from_slice(&[1]), subaccount: Some([1; 32]) };
+        let utxo = |vout| Utxo { outpoint: outpoint(vout), value: 10_000, height: 1 };
+
+        state.utxos_state_addresses.entry(account).or_default().insert(utxo(0));
+        state.finalized_utxos.entry(account).or_default().insert(utxo(1));
+        state.ignored_utxos.insert(utxo(2));
+        state.quarantined_utxos.insert(utxo(3));
+        let other = Account { owner: Principal::from_slice(&[1]), subaccount: Some([2; 32]) };
+        state.utxos_state_addresses.entry(other).or_default().insert(utxo(4));
+
+        assert_eq!(state.known_utxos_for_account(&account), vec![utxo(0), utxo(1)]);
+    }
+
+    #[test]
+    fn kyt_withdrawal_check_should_refuse_addresses_with_alerts() {
+        use ic_ckbtc_kyt::{Alert, AlertLevel, ExposureType};
+
+        let response = |alerts| FetchAlertsResponse {
+            external_id: "id".to_string(),
+            alerts,
+            provider: Principal::anonymous(),
+        };
+        assert_eq!(kyt_withdrawal_outcome("bc1q", Ok(response(vec![]))), Ok(()));
+        assert_eq!(
+            kyt_withdrawal_outcome(
+                "bc1q",
+                Ok(response(vec![Alert {
+                    level: AlertLevel::Severe,
+                    category: None,
+                    service: None,
+                    exposure_type: ExposureType::Direct,
+                }]))
+            ),
+            Err(UpdateBalanceError::GenericError {
+                error_code: ErrorCode::TaintedAddress as u64,
+                error_message: "@update_ssi_balance: The redemption to bc1q was refused by the KYT check".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn kyt_withdrawal_check_should_retry_when_the_provider_is_unavailable() {
+        assert_eq!(
+            kyt_withdrawal_outcome("bc1q", Err(KytError::TemporarilyUnavailable("busy".to_string()))),
+            Err(UpdateBalanceError::TemporarilyUnavailable(
+                "The KYT provider is temporarily unavailable: busy".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn mint_retry_should_repeat_the_memos_of_the_first_attempt() {
+        let memo: Memo = crate::memo::encode(&MintMemo::convert(&[1; 32], 0)).into();
+        let attempt = |convert_memo| MintAttempt { created_at_time: 1_000, susd: 0, convert_memo };
+
+        assert_eq!(mint_memos(None, memo.clone(), &outpoint(0)), (memo.clone(), Some(memo.clone())));
+        assert_eq!(mint_memos(Some(&attempt(true)), memo.clone(), &outpoint(0)), (memo.clone(), Some(memo.clone())));
+        let compact: Memo = crate::memo::encode(&MintMemo::compact(&[1; 32], 0)).into();
+        assert_eq!(mint_memos(Some(&attempt(false)), memo, &outpoint(0)), (compact, None));
+    }
+
+    #[test]
+    fn failed_payment_should_revert_the_swap_credit() {
+        use futures::executor::block_on;
+        use std::cell::Cell;
+
+        let reverted = Cell::new(None);
+        let revert = |amount: u64| {
+            reverted.set(Some(amount));
+            async { Ok(vec![1]) }
+        };
+        let failure = || UpdateBalanceError::TemporarilyUnavailable("ledger".to_string());
+
+        assert_eq!(block_on(revert_swap_credit_on_failure(Ok(7), Some(1_000), "ssi", revert)), Ok(7));
+        assert_eq!(reverted.get(), None);
+
+        assert_eq!(block_on(revert_swap_credit_on_failure::<u64, _, _>(Err(failure()), None, "ssi", revert)), Err(failure()));
+        assert_eq!(reverted.get(), None);
+
+        assert_eq!(block_on(revert_swap_credit_on_failure::<u64, _, _>(Err(failure()), Some(1_000), "ssi", revert)), Err(failure()));
+        assert_eq!(reverted.get(), Some(1_000));
+
+        // @dev a failed revert still returns the error of the payment
+        let failing_revert = |_: u64| async { Err(UpdateBalanceError::TemporarilyUnavailable("revert".to_string())) };
+        assert_eq!(block_on(revert_swap_credit_on_failure::<u64, _, _>(Err(failure()), Some(1_000), "ssi", failing_revert)), Err(failure()));
+    }
+
+    #[test]
+    fn redeem_only_mode_should_only_allow_redemptions() {
+        let account = Account { owner: Principal::from_slice(&[1]), subaccount: Some([1; 32]) };
+        let mode = state::Mode::RedeemOnly;
+
+        assert_eq!(check_operation_mode(&mode, SyronOperation::RedeemBitcoin, &account), Ok(()));
+        for op in [SyronOperation::GetSyron, SyronOperation::Payment, SyronOperation::DepositSyron] {
+            assert!(check_operation_mode(&mode, op, &account).is_err(), "op: {:?}", op);
+        }
+        // @dev loans can still be repaid
+        assert!(mode.is_withdrawal_available_for(&account).is_ok());
+        assert!(check_operation_mode(&state::Mode::ReadOnly, SyronOperation::RedeemBitcoin, &account).is_err());
+        assert!(check_operation_mode(&state::Mode::GeneralAvailability, SyronOperation::GetSyron, &account).is_ok());
+    }
+
+    #[test]
+    fn seeded_totals_should_survive_a_replay() {
+        use crate::state::eventlog::{replay, Event};
+
+        let events = vec![
+            Event::Init(crate::lifecycle::init::test_init_args()),
+            Event::Borrowed { ssi: "ssi".to_string(), collateral: 10, debt: 100 },
+            Event::SeededTotals { total_collateral: 1_000, total_debt: 50_000 },
+            Event::Borrowed { ssi: "ssi".to_string(), collateral: 10, debt: 100 },
+            Event::Repaid { ssi: "ssi".to_string(), collateral: 5, debt: 50 },
+        ];
+        let state = replay(events.into_iter()).unwrap();
+        assert_eq!(state.total_collateral, 1_005);
+        assert_eq!(state.total_debt, 50_050);
+    }
+
+    #[test]
+    fn partial_redemption_should_repay_a_proportional_loan() {
+        // 200% collateral ratio
+        let account = collateralized_account(100_000, 100_000 * 60_000 / 2, 60_000);
+        assert_eq!(partial_redemption(&account, 25_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS), Ok((25_000, 750_000_000)));
+        assert_eq!(partial_redemption(&account, 100_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS), Ok((100_000, 3_000_000_000)));
+    }
+
+    #[test]
+    fn partial_redemption_should_reject_invalid_amounts() {
+        let account = collateralized_account(100_000, 1_000_000, 60_000);
+        assert!(partial_redemption(&account, 0, DEFAULT_MIN_COLLATERAL_RATIO_BPS).is_err());
+        assert!(partial_redemption(&account, 100_001, DEFAULT_MIN_COLLATERAL_RATIO_BPS).is_err());
+    }
+
+    #[test]
+    fn partial_redemption_should_reject_undercollateralized_loans() {
+        // 120% collateral ratio
+        let account = collateralized_account(100_000, 5_000_000_000, 60_000);
+        assert!(partial_redemption(&account, 10_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS).is_err());
+    }
+
+    #[test]
+    fn deposit_amount_should_deduct_the_kyt_fee() {
+        assert_eq!(deposit_amount(10_000, 1_000, 500), Ok(9_500));
+        assert_eq!(deposit_amount(1_000, 1_000, 0), Ok(1_000));
+    }
+
+    #[test]
+    fn deposit_amount_should_reject_values_below_the_minimum_deposit() {
+        assert_eq!(deposit_amount(999, 1_000, 0), Err(IgnoredReason::BelowMinDeposit));
+    }
+
+    #[test]
+    fn deposit_amount_should_not_underflow_when_kyt_fee_exceeds_the_value() {
+        // kyt_fee > utxo.value >= min_deposit
+        assert_eq!(deposit_amount(1_500, 1_000, 2_000), Err(IgnoredReason::KytFeeNotCovered));
+        assert_eq!(deposit_amount(1_000, 1_000, 1_000), Ok(0));
+    }
+
+    #[test]
+    fn pending_utxos_above_the_tip_should_not_underflow() {
+        let utxo = Utxo {
+            outpoint: outpoint(0),
+            value: 10_000,
+            height: 101,
+        };
+        let pending = to_pending_utxos(vec![utxo], 100, 6);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].confirmations, 1);
+    }
+
+    #[test]
+    fn no_new_utxos_should_report_the_most_confirmed_pending_utxos() {
+        let pending = |vout: u32, confirmations: u32| PendingUtxo {
+            outpoint: outpoint(vout),
+            value: 10_000,
+            confirmations,
+        };
+        match no_new_utxos_error(vec![pending(0, 1), pending(1, 5), pending(2, 3)], 6, 2) {
+            UpdateBalanceError::NoNewUtxos {
+                current_confirmations,
+                pending_utxos,
+                ..
+            } => {
+                assert_eq!(current_confirmations, Some(5));
+                assert_eq!(pending_utxos, Some(vec![pending(1, 5), pending(2, 3)]));
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn requested_confirmations_should_only_be_stricter() {
+        assert_eq!(effective_min_confirmations(None, 6).unwrap(), 6);
+        assert_eq!(effective_min_confirmations(Some(6), 6).unwrap(), 6);
+        assert_eq!(effective_min_confirmations(Some(12), 6).unwrap(), 12);
+        assert!(effective_min_confirmations(Some(3), 6).is_err());
+    }
+
+    #[test]
+    fn utxo_in_both_runes_minter_lists_should_be_credited_once() {
+        let shared = runes_utxo(0, 100);
+        let sats = vec![shared.clone(), runes_utxo(1, 0), runes_utxo(1, 0)];
+        let runes = vec![shared.clone(), runes_utxo(2, 200)];
+
+        let (sats, runes) = dedup_runes_minter_utxos(sats, runes);
+        assert_eq!(sats, vec![runes_utxo(1, 0)]);
+        assert_eq!(runes, vec![shared.clone(), runes_utxo(2, 200)]);
+
+        let credited = sats.iter().chain(runes.iter()).filter(|u| u.outpoint == shared.outpoint).count();
+        assert_eq!(credited, 1);
+    }
+
+    #[test]
+    fn collateral_cap_should_report_the_headroom() {
+        assert_eq!(check_collateral_cap(0, 100_000, u64::MAX), Ok(()));
+        assert_eq!(check_collateral_cap(60_000, 40_000, 100_000), Ok(()));
+        assert_eq!(check_collateral_cap(60_000, 40_001, 100_000), Err(40_000));
+        assert_eq!(check_collateral_cap(120_000, 1, 100_000), Err(0));
+    }
+
+    #[test]
+    fn too_many_concurrent_requests_should_hint_a_retry() {
+        assert_eq!(
+            UpdateBalanceError::from(GuardError::TooManyConcurrentRequests),
+            UpdateBalanceError::RetryLater {
+                reason: "too many concurrent requests".to_string(),
+                retry_after_nanos: Some(CONCURRENCY_RETRY_AFTER_NANOS),
+            }
+        );
+        assert_eq!(
+            UpdateBalanceError::from(GuardError::AlreadyProcessing),
+            UpdateBalanceError::AlreadyProcessing
+        );
+    }
+
+    #[test]
+    fn recipient_account_should_default_to_the_ssi_balance() {
+        let minter = Principal::from_slice(&[1]);
+        let caller = Principal::from_slice(&[2]);
+        let owner = Principal::from_slice(&[3]);
+        let ssi_balance = Account {
+            owner: minter,
+            subaccount: Some([2; 32]),
+        };
+
+        assert_eq!(recipient_account(minter, caller, None, None, ssi_balance), Ok(ssi_balance));
+        assert_eq!(
+            recipient_account(minter, caller, None, Some([7; 32]), ssi_balance),
+            Ok(Account {
+                owner: caller,
+                subaccount: Some([7; 32]),
+            })
+        );
+        assert_eq!(
+            recipient_account(minter, caller, Some(owner), None, ssi_balance),
+            Ok(Account {
+                owner,
+                subaccount: None,
+            })
+        );
+        assert!(matches!(
+            recipient_account(minter, caller, Some(minter), Some([2; 32]), ssi_balance),
+            Err(UpdateBalanceError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn liquidation_preview_should_seize_the_repaid_value() {
+        // 1 BTC at 1 SUSD per satoshi backing 80M SUSD: 125% collateral ratio
+        let account = collateralized_account(100_000_000, 80_000_000, 1);
+        let preview = liquidation_preview(&account, 40_000_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS, 0);
+        assert_eq!(
+            preview,
+            LiquidationPreview {
+                liquidatable: true,
+                underwater_since: None,
+                collateral_ratio: 12_500,
+                repaid_susd: 40_000_000,
+                seized_collateral: 40_000_000,
+                bonus: 0,
+            }
+        );
+
+        // the repayment is capped at the loan
+        assert_eq!(liquidation_preview(&account, u64::MAX, DEFAULT_MIN_COLLATERAL_RATIO_BPS, 0).repaid_susd, 80_000_000);
+
+        let healthy = collateralized_account(200_000_000, 80_000_000, 1);
+        assert!(!liquidation_preview(&healthy, 40_000_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS, 0).liquidatable);
+    }
+
+    #[test]
+    fn liquidation_bonus_should_be_capped_at_the_collateral() {
+        assert_eq!(seized_collateral(40_000_000, 1, 100_000_000, 500), (42_000_000, 2_000_000));
+        assert_eq!(seized_collateral(100_000_000, 1, 102_000_000, 500), (102_000_000, 2_000_000));
+        assert_eq!(seized_collateral(100_000_000, 1, 90_000_000, 500), (90_000_000, 0));
+        assert_eq!(seized_collateral(100, 0, 90, 500), (0, 0));
+    }
+
+    #[test]
+    fn liquidation_should_wait_for_the_grace_period() {
+        let mut state = state::MinterState::from(crate::lifecycle::init::test_init_args());
+        assert_eq!(track_underwater(&mut state, "ssi", true, 1_000), Some(1_000));
+        assert_eq!(track_underwater(&mut state, "ssi", true, 1_500), Some(1_000));
+
+        let account = collateralized_account(100_000_000, 80_000_000, 1);
+        let preview = liquidation_preview(&account, 40_000_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS, 0);
+        assert!(!with_grace_period(preview.clone(), Some(1_000), 1_099, 100).liquidatable);
+        assert!(with_grace_period(preview.clone(), Some(1_000), 1_100, 100).liquidatable);
+        assert!(!with_grace_period(preview, None, 1_100, 100).liquidatable);
+
+        // the grace period restarts once the loan recovers
+        assert_eq!(track_underwater(&mut state, "ssi", false, 2_000), None);
+        assert_eq!(track_underwater(&mut state, "ssi", true, 3_000), Some(3_000));
+        assert_eq!(state.underwater_since.get("ssi"), Some(&3_000));
+    }
+
+    #[test]
+    fn loans_between_the_threshold_and_the_minimum_ratio_should_not_be_liquidatable() {
+        // 140%: below the minimum collateral ratio but above a 120% liquidation threshold
+        let account = collateralized_account(140_000_000, 100_000_000, 1);
+        assert_eq!(borrowable_susd(&account, DEFAULT_MIN_COLLATERAL_RATIO_BPS), 0);
+        assert!(!liquidation_preview(&account, 50_000_000, 12_000, 0).liquidatable);
+        assert!(liquidation_preview(&account, 50_000_000, DEFAULT_MIN_COLLATERAL_RATIO_BPS, 0).liquidatable);
+    }
+
+    #[test]
+    fn stability_fee_should_accrue_with_time() {
+        let year = NANOS_PER_YEAR;
+        assert_eq!(stability_fee(1_000_000, 200, year), 20_000);
+        assert_eq!(stability_fee(1_000_000, 200, year / 2), 10_000);
+        assert_eq!(stability_fee(1_000_000, 0, year), 0);
+        assert_eq!(stability_fee(0, 200, year), 0);
+        // a short interval rounds down, so the accrual time must not move forward
+        assert_eq!(stability_fee(1_000_000, 200, 1_000), 0);
+    }
+
+    #[test]
+    fn stability_fee_should_count_towards_the_debt() {
+        let account = CollateralizedAccount {
+            stability_fee: 1_000_000_000,
+            ..collateralized_account(100_000, 3_000_000_000, 60_000)
+        };
+        assert_eq!(account.debt(), 4_000_000_000);
+        assert_eq!(borrowable_susd(&account, DEFAULT_MIN_COLLATERAL_RATIO_BPS), 0);
+        assert_eq!(repayment_amount(5_000_000_000, account.debt()), Ok(4_000_000_000));
+    }
+
+    #[test]
+    fn repayment_should_settle_the_stability_fee_first() {
+        assert_eq!(split_repayment(500, 200), (200, 300));
+        assert_eq!(split_repayment(100, 200), (100, 0));
+        assert_eq!(split_repayment(500, 0), (0, 500));
+    }
+
+    #[test]
+    fn redemption_fee_should_be_deducted_from_the_redeemed_amount() {
+        assert_eq!(redemption_fee(100_000, 0), (100_000, 0));
+        assert_eq!(redemption_fee(100_000, 50), (99_500, 500));
+        assert_eq!(redemption_fee(199, 50), (199, 0));
+        assert_eq!(redemption_fee(100_000, 10_000), (0, 100_000));
+    }
+
+    #[test]
+    fn redemption_should_report_insufficient_funds() {
+        let err = redemption_transfer_error(
+            TransferError::InsufficientFunds { balance: Nat::from(42_u64) },
+            "@test",
+        );
+        assert_eq!(
+            err,
+            UpdateBalanceError::GenericError {
+                error_code: ErrorCode::InsufficientAmount as u64,
+                error_message: "@test: The balance (42) is insufficient".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn redemption_should_report_a_bad_fee() {
+        let err = redemption_transfer_error(
+            TransferError::BadFee { expected_fee: Nat::from(10_u64) },
+            "@test",
+        );
+        assert_eq!(
+            err,
+            UpdateBalanceError::GenericError {
+                error_code: ErrorCode::BadFee as u64,
+                error_message: "@test: The ledger expects a fee of 10".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn redemption_should_keep_the_generic_error_for_other_rejections() {
+        let err = redemption_transfer_error(TransferError::TooOld, "@test");
+        assert!(matches!(
+            err,
+            UpdateBalanceError::GenericError { error_code, .. } if error_code == ErrorCode::ConfigurationError as u64
+        ));
+    }
+
+    #[test]
+    fn redemption_should_be_within_the_bounds() {
+        assert_eq!(redemption_bounds(1_000, 1_000, 5_000), Ok(()));
+        assert_eq!(redemption_bounds(5_000, 1_000, 5_000), Ok(()));
+        assert!(matches!(
+            redemption_bounds(999, 1_000, 5_000),
+            Err(UpdateBalanceError::GenericError { error_code, .. }) if error_code == ErrorCode::InsufficientAmount as u64
+        ));
+        assert!(matches!(
+            redemption_bounds(5_001, 1_000, 5_000),
+            Err(UpdateBalanceError::GenericError { error_code, .. }) if error_code == ErrorCode::RedemptionLimitExceeded as u64
+        ));
+    }
+
+    #[test]
+    fn block_indices_should_keep_the_order_of_the_transactions() {
+        let collateral_only = BlockIndices {
+            collateral: 7,
+            loan: None,
+            balance: None,
+        };
+        assert_eq!(collateral_only.to_vec(), vec![7]);
+
+        let with_loan = BlockIndices {
+            collateral: 7,
+            loan: Some(3),
+            balance: Some(4),
+        };
+        assert_eq!(with_loan.to_vec(), vec![7, 3, 4]);
+    }
+
+    #[test]
+    fn deposit_amount_should_ignore_dust() {
+        assert_eq!(deposit_amount(546, 0, 0), Err(IgnoredReason::Dust));
+        assert_eq!(deposit_amount(547, 0, 0), Ok(547));
+    }
+}